@@ -1,60 +1,346 @@
-use color_eyre::eyre::Result;
-use ratatui::layout::Rect;
+use std::{io::Write, time::Duration};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use ratatui::{backend::CrosstermBackend, layout::Rect};
+
+use regex::Regex;
+use tokio::time::timeout;
 
 use crate::{
-    cli::Args,
+    buffer::BufferId,
+    cli::{Args, StartPosition},
+    commands,
     components::{Components, Context},
-    cursor::Cursor,
-    editor::{Editor, EditorView},
-    terminal::{Event, Terminal},
+    config::Config,
+    diff_mode,
+    editor::{Editor, EditorView, OpenAction, Severity},
+    git,
+    jobs::JobCallback,
+    lsp,
+    mode::Mode,
+    movements,
+    script::{self, ScriptLine, ScriptStep},
+    terminal::{EditorBackend, Event, Terminal, IO},
+    window::WindowId,
+};
+
+/// How often waiting for jobs checks whether they all finished
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Area used to lay out windows when running headless
+const HEADLESS_AREA: Rect = Rect {
+    x: 0,
+    y: 0,
+    width: 80,
+    height: 24,
 };
 
-pub struct App {
+pub struct App<B: EditorBackend = CrosstermBackend<IO>> {
     components: Components,
-    terminal: Terminal,
+    /// Missing when running headless
+    terminal: Option<Terminal<B>>,
     pub editor: Editor,
+    /// Buffer written to stdout on exit
+    stdout: Option<BufferId>,
+    script: Vec<ScriptLine>,
 }
 
 impl App {
     pub fn new(args: Args) -> Result<Self> {
-        let terminal = Terminal::new()?;
+        let terminal = match args.headless {
+            true => None,
+            false => Some(Terminal::new()?),
+        };
+
+        Self::with_terminal(args, terminal)
+    }
+}
+
+impl<B: EditorBackend> App<B> {
+    /// Create the app on any backend, `None` runs headless
+    pub fn with_terminal(args: Args, terminal: Option<Terminal<B>>) -> Result<Self> {
+        let script = match &args.script {
+            Some(path) => script::parse(&std::fs::read_to_string(path)?)?,
+            None => vec![],
+        };
 
-        let area = terminal.size()?;
+        let area = match &terminal {
+            Some(terminal) => terminal.size()?,
+            None => HEADLESS_AREA,
+        };
 
         let mut components = Components::new(area);
 
-        let mut editor = Editor::new(area);
+        let config = Config::load(args.clean)?;
+        let terminal = terminal.map(|terminal| terminal.mouse(config.mouse).paste(true));
+        let mut editor = Editor::new(area, config);
 
         let editor_view = Box::new(EditorView::new());
         components.push(editor_view);
 
-        for path in args.files {
-            let id = editor.open(&path).unwrap();
+        let split = if args.horizontal {
+            Some(OpenAction::HorizontalSplit)
+        } else if args.vertical || args.diff {
+            Some(OpenAction::VerticalSplit)
+        } else {
+            None
+        };
+
+        let mut stdin_buffer = None;
+        if args.read_stdin() {
+            let buffer_id = editor.open_stdin(OpenAction::Replace);
+            editor.buffers.get_mut(buffer_id).unwrap().readonly = args.readonly;
+            stdin_buffer = Some(buffer_id);
+        }
+
+        // every file is shown once so its start position lands in a window,
+        // without splits they end up as hidden buffers behind the first one
+        let mut first_file = None;
+        for (index, file) in args.file_args().into_iter().enumerate() {
+            let action = match split {
+                Some(split) if index > 0 || stdin_buffer.is_some() => split,
+                _ => OpenAction::Replace,
+            };
+            let buffer_id = editor.open(&file.path, action)?;
+            editor.buffers.get_mut(buffer_id).unwrap().readonly = args.readonly;
+
+            if let Some(position) = file.position {
+                Self::apply_start_position(&mut editor, &position)?;
+            }
 
-            let window_id = editor.windows.get_focused().unwrap().id;
-            let buf = editor.buffers.get_mut(id).unwrap();
-            buf.set_cursor(window_id, Cursor::default());
+            first_file.get_or_insert(buffer_id);
         }
 
+        match (first_file, split) {
+            (Some(buffer_id), None) if stdin_buffer.is_none() => {
+                editor.show_buffer(buffer_id, OpenAction::Replace);
+            }
+            (Some(buffer_id), Some(_)) => {
+                if let Some(window_id) = editor
+                    .windows
+                    .get_by_buffer_id(stdin_buffer.unwrap_or(buffer_id))
+                    .map(|window| window.id)
+                {
+                    editor.windows.focus(window_id);
+                }
+            }
+            _ => {
+                if let Some(buffer_id) = stdin_buffer {
+                    editor.show_buffer(buffer_id, OpenAction::Replace);
+                }
+            }
+        }
+
+        if args.diff {
+            let windows: Vec<WindowId> = editor.windows.iter().map(|window| window.id).collect();
+            match windows[..] {
+                [left, right] => diff_mode::start(&mut editor, [left, right])?,
+                _ => bail!("-d compares two files"),
+            }
+        }
+
+        for command in &args.commands {
+            let mut context = Context {
+                editor: &mut editor,
+            };
+            if let Err(err) = commands::execute(&mut components, &mut context, command) {
+                editor.set_error(err.to_string());
+            }
+        }
+
+        let stdout = if args.stdout {
+            stdin_buffer.or_else(|| editor.windows.get_focused().map(|w| w.buffer_id))
+        } else {
+            None
+        };
+
         Ok(Self {
             components,
             terminal,
             editor,
+            stdout,
+            script,
         })
     }
 
+    /// Move the cursor in the focused window to a start position
+    fn apply_start_position(editor: &mut Editor, position: &StartPosition) -> Result<()> {
+        let mut context = Context { editor };
+
+        match position {
+            StartPosition::Line(line, column) => movements::goto_line(&mut context, *line, *column),
+            StartPosition::LastLine => movements::goto_last_line(&mut context),
+            StartPosition::Search(pattern) => {
+                let pattern = Regex::new(pattern)?;
+                if !movements::search_forward(&mut context, &pattern) {
+                    context
+                        .editor
+                        .set_error(format!("Pattern not found: {pattern}"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn run(&mut self) -> Result<()> {
-        self.terminal.enter()?;
+        if self.terminal.is_none() {
+            return self.run_headless().await;
+        }
+
+        self.terminal().enter()?;
+        lsp::enable(&mut self.editor);
+
+        if !self.script.is_empty() {
+            self.wait_for_loads().await;
+            if let Err(err) = self.run_script().await {
+                self.editor.set_error(err.to_string());
+            }
+        }
 
         self.event_loop().await;
 
-        self.terminal.exit()?;
+        self.terminal().exit()?;
+
+        self.write_stdout()
+    }
+
+    /// Apply the script without a terminal and write the results.
+    ///
+    /// Fails on the first error reported by a key or command.
+    async fn run_headless(&mut self) -> Result<()> {
+        self.wait_for_loads().await;
+        // `-c` commands may have started jobs too
+        self.wait_for_jobs().await;
+        if let Some(status) = self.editor.status.take() {
+            if status.severity == Severity::Error {
+                bail!(status.text);
+            }
+        }
+
+        self.run_script().await?;
+
+        if !self.editor.should_quit() {
+            self.editor.write_all(false)?;
+        }
+
+        self.write_stdout()
+    }
+
+    /// Feed every script line through the components, like typed input.
+    ///
+    /// Jobs started by a key or command, like filters, are applied before the next one, and
+    /// fail the script when they report an error.
+    async fn run_script(&mut self) -> Result<()> {
+        let script = std::mem::take(&mut self.script);
+
+        for ScriptLine { line, step } in script {
+            if self.editor.should_quit() {
+                break;
+            }
+
+            let mut context = Context {
+                editor: &mut self.editor,
+            };
+
+            match step {
+                ScriptStep::Command(command) => {
+                    context.editor.status = None;
+                    commands::execute(&mut self.components, &mut context, &command)
+                        .map_err(|err| eyre!("line {line}: {err}"))?;
+                    self.wait_for_jobs().await;
+                    self.end_undo_step();
+                    self.check_status(line)?;
+                }
+                ScriptStep::Keys(keys) => {
+                    for key in keys {
+                        let mut context = Context {
+                            editor: &mut self.editor,
+                        };
+                        self.components
+                            .handle_events(&Event::Key(key), &mut context);
+                        self.wait_for_jobs().await;
+                        if self.editor.mode != Mode::Insert {
+                            self.editor.commit_history();
+                        }
+                        self.check_status(line)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fail the script line when its last key, command or job reported an error
+    fn check_status(&self, line: usize) -> Result<()> {
+        match self.editor.status.as_ref() {
+            Some(status) if status.severity == Severity::Error => {
+                bail!("line {line}: {}", status.text)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run job callbacks until the jobs counted as running sent all of theirs
+    pub(crate) async fn wait_for_jobs(&mut self) {
+        loop {
+            let callback = match self.editor.jobs.try_next() {
+                Some(callback) => callback,
+                // a job sends its last callback before it stops counting as running
+                None if self.editor.jobs.is_idle() => match self.editor.jobs.try_next() {
+                    Some(callback) => callback,
+                    None => break,
+                },
+                None => match timeout(JOB_POLL_INTERVAL, self.editor.jobs.next()).await {
+                    Ok(Some(callback)) => callback,
+                    Ok(None) => break,
+                    // the job finished without a callback, or is still running
+                    Err(_) => continue,
+                },
+            };
+
+            let mut context = Context {
+                editor: &mut self.editor,
+            };
+            callback(&mut self.components, &mut context);
+        }
+    }
+
+    /// Run job callbacks until every buffer finished loading
+    pub(crate) async fn wait_for_loads(&mut self) {
+        while self.editor.buffers.iter().any(|buf| buf.is_loading()) {
+            let Some(callback) = self.editor.jobs.next().await else {
+                break;
+            };
+
+            let mut context = Context {
+                editor: &mut self.editor,
+            };
+            callback(&mut self.components, &mut context);
+        }
+    }
+
+    fn write_stdout(&self) -> Result<()> {
+        if let Some(buffer_id) = self.stdout {
+            if let Some(buf) = self.editor.buffers.get(buffer_id) {
+                let mut stdout = std::io::stdout().lock();
+                buf.content().write_to(&mut stdout)?;
+                stdout.flush()?;
+            }
+        }
 
         Ok(())
     }
 
+    pub(crate) fn terminal(&mut self) -> &mut Terminal<B> {
+        self.terminal
+            .as_mut()
+            .expect("terminal is only missing when headless")
+    }
+
     async fn event_loop(&mut self) {
-        self.terminal.start();
+        self.terminal().start();
 
         self.render_app().await;
 
@@ -63,14 +349,74 @@ impl App {
                 break;
             }
 
+            let terminal = self
+                .terminal
+                .as_mut()
+                .expect("terminal is only missing when headless");
+
             tokio::select! {
-                Some(term_event) = self.terminal.next() => {
+                Some(term_event) = terminal.next() => {
                     self.handle_terminal_events(term_event).await;
                 }
+                Some(callback) = self.editor.jobs.next() => {
+                    self.handle_job_callback(callback).await;
+                }
             }
         }
     }
 
+    /// Handle queued terminal events and finished jobs without waiting for new ones,
+    /// like one pass of the event loop
+    #[cfg(test)]
+    pub(crate) async fn handle_pending_events(&mut self) {
+        loop {
+            if self.editor.should_quit() {
+                break;
+            }
+
+            if let Some(event) = self.terminal().try_next() {
+                self.handle_terminal_events(event).await;
+            } else if let Some(callback) = self.editor.jobs.try_next() {
+                self.handle_job_callback(callback).await;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Wait for the next background job to finish and apply it
+    #[cfg(test)]
+    pub(crate) async fn handle_next_job(&mut self) {
+        if let Some(callback) = self.editor.jobs.next().await {
+            self.handle_job_callback(callback).await;
+        }
+    }
+
+    /// Apply the result of a background job and redraw
+    async fn handle_job_callback(&mut self, callback: JobCallback) {
+        let mut context = Context {
+            editor: &mut self.editor,
+        };
+
+        callback(&mut self.components, &mut context);
+        self.end_undo_step();
+        lsp::sync(&mut self.editor);
+        git::update(&mut self.editor);
+        diff_mode::update(&mut self.editor);
+
+        if !self.editor.should_quit() {
+            self.render_app().await;
+        }
+    }
+
+    /// Every change outside Insert mode is its own undo step,
+    /// an Insert mode session is one step once it ends
+    fn end_undo_step(&mut self) {
+        if self.editor.mode != Mode::Insert {
+            self.editor.commit_history();
+        }
+    }
+
     /// Handle possible events from the terminal
     async fn handle_terminal_events(&mut self, event: Event) {
         let mut context = Context {
@@ -79,13 +425,18 @@ impl App {
 
         let redraw = match event {
             Event::Resize(width, height) => {
-                self.terminal
+                let terminal = self
+                    .terminal
+                    .as_mut()
+                    .expect("terminal is only missing when headless");
+                terminal
                     .resize(Rect::new(0, 0, width, height))
                     .expect("Not able to resize terminal");
 
-                let new_area = self.terminal.size().expect("Not able to get terminal size");
+                let new_area = terminal.size().expect("Not able to get terminal size");
 
                 self.components.resize(new_area);
+                context.editor.windows.resize(new_area);
                 self.components.handle_events(
                     &Event::Resize(new_area.width, new_area.height),
                     &mut context,
@@ -93,8 +444,26 @@ impl App {
 
                 true
             }
+            Event::Resume => {
+                if let Some(terminal) = self.terminal.as_mut() {
+                    let _ = terminal.clear();
+                }
+                true
+            }
             event => self.components.handle_events(&event, &mut context),
         };
+        self.end_undo_step();
+        lsp::sync(&mut self.editor);
+        git::update(&mut self.editor);
+        diff_mode::update(&mut self.editor);
+
+        if self.editor.take_suspend() {
+            if let Some(terminal) = self.terminal.as_mut() {
+                if let Err(err) = terminal.suspend() {
+                    self.editor.set_error(format!("Failed to suspend: {err}"));
+                }
+            }
+        }
 
         // handle redraw event only if editor will not quit soon
         if redraw && !self.editor.should_quit() {
@@ -102,12 +471,16 @@ impl App {
         }
     }
 
-    async fn render_app(&mut self) {
+    pub(crate) async fn render_app(&mut self) {
+        let Some(terminal) = self.terminal.as_mut() else {
+            return;
+        };
+
         let mut frame_context = Context {
             editor: &mut self.editor,
         };
 
-        let _ = self.terminal.draw(|frame| {
+        let _ = terminal.draw(|frame| {
             self.components.render(frame, &mut frame_context);
             if let Some(position) = self.components.cursor(frame, &mut frame_context) {
                 frame.set_cursor(position.x as u16, position.y as u16);
@@ -272,3 +645,118 @@ impl App {
 //         Ok(())
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+    use color_eyre::eyre::Result;
+    use pretty_assertions::assert_eq;
+    use ratatui::backend::TestBackend;
+
+    use super::App;
+    use crate::{
+        cli::Args,
+        mode::Mode,
+        test_harness::{file, TestApp},
+    };
+
+    /// Run `script` without a terminal on `path`
+    async fn run_headless(script: &str, path: &str) -> Result<()> {
+        let script = file(script);
+        let args = Args::parse_from([
+            "editor",
+            "--clean",
+            "--headless",
+            "-s",
+            script.path().to_str().unwrap(),
+            path,
+        ]);
+        App::<TestBackend>::with_terminal(args, None)?.run().await
+    }
+
+    #[tokio::test]
+    async fn inserts_typed_text() {
+        let file = file("one\ntwo\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("jixy<Esc>").await;
+
+        assert_eq!(app.text(), "one\nxytwo\n");
+        assert_eq!(app.cursor(), (1, 2));
+        assert_eq!(app.app.editor.mode, Mode::Normal);
+    }
+
+    #[tokio::test]
+    async fn renders_buffer_and_cursor() {
+        let file = file("fn main() {}\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("lll").await;
+
+        assert!(app.screen_line(0).contains("fn main() {}"));
+        let (x, y) = app.screen_cursor();
+        assert_eq!(y, 0);
+        assert_eq!(app.screen_line(0).find("main").unwrap() as u16, x);
+    }
+
+    #[tokio::test]
+    async fn writes_and_quits_from_the_prompt() {
+        let file = file("one\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("i1<Esc>:wq<CR>").await;
+
+        assert!(app.app.editor.should_quit());
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "1one\n");
+    }
+
+    #[tokio::test]
+    async fn q_keeps_unsaved_changes() {
+        let file = file("one\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("i1<Esc>q").await;
+        assert!(!app.app.editor.should_quit());
+        assert!(app
+            .app
+            .editor
+            .status
+            .as_ref()
+            .unwrap()
+            .text
+            .starts_with("No write"));
+
+        app.keys(":w<CR>q").await;
+        assert!(app.app.editor.should_quit());
+    }
+
+    #[tokio::test]
+    async fn start_column_stays_on_the_line() {
+        let file = file("ab世\nxyz\n");
+        let path = format!("{}:1:99", file.path().display());
+        let app = TestApp::new(&["--clean", &path]).await;
+
+        assert_eq!(app.cursor(), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn headless_fails_on_failed_jobs() {
+        let file = file("c\nb\na\n");
+        let path = file.path().to_str().unwrap();
+
+        let err = run_headless(":1,2!false\n", path).await.unwrap_err();
+        assert!(err.to_string().starts_with("line 1: "), "{err}");
+        assert_eq!(fs::read_to_string(path).unwrap(), "c\nb\na\n");
+    }
+
+    #[tokio::test]
+    async fn headless_writes_filtered_lines() {
+        let file = file("c\nb\na\n");
+        let path = file.path().to_str().unwrap();
+
+        run_headless(":%!sort\nix<Esc>\n", path).await.unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "xa\nb\nc\n");
+    }
+}
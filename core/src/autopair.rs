@@ -0,0 +1,184 @@
+//! Brackets and quotes typed in Insert mode, closed, stepped over and deleted in pairs
+
+use crate::{components::Context, cursor::Cursor, editor::Editor, indent, movements};
+
+/// Configured pairs, without the ones left alone in the language of the focused buffer
+fn pairs(editor: &Editor) -> Vec<(char, char)> {
+    let unpaired = editor
+        .windows
+        .get_focused()
+        .and_then(|window| editor.buffers.get(window.buffer_id))
+        .and_then(|buf| buf.language())
+        .map_or(&[][..], |language| language.unpaired);
+
+    editor
+        .config
+        .auto_pairs
+        .iter()
+        .filter_map(|pair| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next()) {
+                (Some(open), Some(close), None) => Some((open, close)),
+                _ => None,
+            }
+        })
+        .filter(|(open, _)| !unpaired.contains(open))
+        .collect()
+}
+
+/// Chars before the cursor and at it in the focused window
+fn around_cursor(editor: &Editor) -> (Option<char>, Option<char>) {
+    let Some(window) = editor.windows.get_focused() else {
+        return (None, None);
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let content = buf.content();
+    let pos = buf.get_cursor(window.id).range.start;
+
+    let before = pos.checked_sub(1).map(|before| content.char(before));
+    let after = (pos < content.len_chars()).then(|| content.char(pos));
+    (before, after)
+}
+
+/// Whether the syntax tree puts the cursor in a string or a comment
+fn in_string_or_comment(editor: &mut Editor) -> bool {
+    let Some(window) = editor.windows.get_focused() else {
+        return false;
+    };
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    let pos = buf.get_cursor(window.id).range.start;
+    let content = buf.content().clone();
+    let Some(syntax) = buf.syntax() else {
+        return false;
+    };
+
+    syntax
+        .scope_at(content.slice(..), content.char_to_byte(pos))
+        .is_some_and(|scope| scope.starts_with("string") || scope.starts_with("comment"))
+}
+
+/// Type a char, closing an opener and stepping over a closer at the cursor
+pub fn insert_char(context: &mut Context, char: char) {
+    let pairs = pairs(context.editor);
+    let (before, after) = around_cursor(context.editor);
+    let is_closer = |char: char| pairs.iter().any(|&(_, close)| close == char);
+
+    if after == Some(char) && is_closer(char) {
+        movements::move_right(context);
+        return;
+    }
+
+    let Some(&(_, close)) = pairs.iter().find(|(open, _)| *open == char) else {
+        movements::insert_char(context, char);
+        return;
+    };
+    // not before a word, and quotes not right after one, e.g. "don't"
+    let paired = after.is_none_or(|after| after.is_whitespace() || is_closer(after))
+        && !(char == close && before.is_some_and(char::is_alphanumeric))
+        && !in_string_or_comment(context.editor);
+
+    match paired {
+        true => {
+            movements::insert_text(context, &format!("{char}{close}"));
+            movements::move_left(context);
+        }
+        false => movements::insert_char(context, char),
+    }
+}
+
+/// Delete the char before the cursor, and the closer at the cursor when they are a pair
+pub fn delete_char(context: &mut Context) {
+    let (before, after) = around_cursor(context.editor);
+    let empty_pair = match (before, after) {
+        (Some(before), Some(after)) => pairs(context.editor).contains(&(before, after)),
+        _ => false,
+    };
+
+    if empty_pair {
+        let window = context.editor.windows.get_focused().unwrap();
+        let buf = context.editor.buffers.get_mut(window.buffer_id).unwrap();
+        let pos = buf.get_cursor(window.id).range.start;
+        if let Err(err) = buf.remove(pos..pos + 1) {
+            context.editor.set_error(err.to_string());
+            return;
+        }
+    }
+
+    movements::delete_char(context);
+}
+
+/// Break the line, between brackets the closer goes down a line further and the cursor to an
+/// indented line between them
+pub fn insert_new_line(context: &mut Context) {
+    let (before, after) = around_cursor(context.editor);
+    let between = match (before, after) {
+        (Some(before), Some(after)) if before != after => {
+            pairs(context.editor).contains(&(before, after))
+        }
+        _ => false,
+    };
+    let window = context.editor.windows.get_focused().unwrap();
+    let readonly = context
+        .editor
+        .buffers
+        .get(window.buffer_id)
+        .unwrap()
+        .readonly;
+
+    movements::insert_new_line(context);
+    if !between || readonly {
+        return;
+    }
+
+    // back to the end of the opener's line, to open the line between
+    let window = context.editor.windows.get_focused().unwrap();
+    let (window_id, buffer_id) = (window.id, window.buffer_id);
+    let buf = context.editor.buffers.get_mut(buffer_id).unwrap();
+    let content = buf.content();
+    let opener_line = content.char_to_line(buf.get_cursor(window_id).range.start) - 1;
+    let end = content.line_to_char(opener_line + 1) - 1;
+    buf.set_cursor(
+        window_id,
+        Cursor {
+            range: end..end + 1,
+        },
+    );
+    movements::insert_new_line(context);
+
+    // without a syntax tree the line between is only as indented as the opener's
+    let width = context.editor.config.indent_width;
+    let buf = context.editor.buffers.get(buffer_id).unwrap();
+    let opener_indent = indent::leading(buf.content(), opener_line);
+    if indent::leading(buf.content(), opener_line + 1).len() <= opener_indent.len() {
+        movements::insert_text(context, &indent::unit(&opener_indent, width));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::test_harness::{file, TestApp};
+
+    #[tokio::test]
+    async fn pairs_brackets_and_quotes() {
+        let file = file("\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("i(<BS>x [a] \"b\" it's<CR>{<CR>y<Esc>").await;
+        assert_eq!(app.text(), "x [a] \"b\" it's\n{\n    y\n}\n");
+    }
+
+    #[tokio::test]
+    async fn pairs_following_the_language() {
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", "\n")]).await;
+
+        // no pair for the quote of lifetimes and chars, nor in comments
+        app.keys("ifn main() {<CR>let a = ('x', \"y\");<CR>// \"q<Esc>")
+            .await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    let a = ('x', \"y\");\n    // \"q\n}\n"
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Mock language server on stdio, see `lsp/mock.rs`
+
+#[path = "../lsp/mock.rs"]
+mod mock;
+#[path = "../lsp/transport.rs"]
+mod transport;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    mock::serve(tokio::io::stdin(), tokio::io::stdout()).await
+}
@@ -0,0 +1,122 @@
+//! Brackets matching the one at the cursor, from the syntax tree or a bounded scan of the text
+
+use color_eyre::eyre::{bail, Result};
+use ropey::RopeSlice;
+use text::next_grapheme_boundary;
+
+use crate::{buffer::Buffer, cursor::Cursor, editor::Editor};
+
+/// Chars scanned at most for a match without a syntax tree, huge files stay responsive
+const SCAN_LIMIT: usize = 20_000;
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Char of the bracket matching the one at `pos`.
+///
+/// With a syntax tree, brackets in strings and comments are left out.
+pub fn matching(buf: &mut Buffer, pos: usize) -> Option<usize> {
+    let content = buf.content().clone();
+    if pos >= content.len_chars() {
+        return None;
+    }
+    let char = content.char(pos);
+    let &pair = PAIRS
+        .iter()
+        .find(|(open, close)| char == *open || char == *close)?;
+
+    match buf.syntax() {
+        Some(syntax) => syntax
+            .matching_bracket(content.char_to_byte(pos))
+            .map(|byte| content.byte_to_char(byte)),
+        None => scan(content.slice(..), pos, pair),
+    }
+}
+
+/// Match of the bracket at `pos` in the plain text, at most `SCAN_LIMIT` chars away
+fn scan(content: RopeSlice, pos: usize, (open, close): (char, char)) -> Option<usize> {
+    let forward = content.char(pos) == open;
+    let mut chars = content.chars_at(if forward { pos + 1 } else { pos });
+    let mut depth = 0;
+
+    for distance in 1..=SCAN_LIMIT {
+        let char = match forward {
+            true => chars.next()?,
+            false => chars.prev()?,
+        };
+        let (nested, matched) = match forward {
+            true => (open, close),
+            false => (close, open),
+        };
+        if char == nested {
+            depth += 1;
+        } else if char == matched {
+            if depth == 0 {
+                return Some(match forward {
+                    true => pos + distance,
+                    false => pos - distance,
+                });
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Move the cursor to the bracket matching the one under it, `%`
+pub fn goto_matching(editor: &mut Editor) -> Result<()> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    let pos = buf.get_cursor(window.id).range.start;
+    let Some(target) = matching(buf, pos) else {
+        bail!("No matching bracket");
+    };
+
+    let end = next_grapheme_boundary(&buf.content().slice(..), target);
+    buf.set_cursor(window.id, Cursor { range: target..end });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use ratatui::style::Modifier;
+
+    use crate::test_harness::{file, TestApp};
+
+    #[tokio::test]
+    async fn jumps_between_pairs() {
+        let (_dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn main() {\n    let a = \"(\";\n    f(a);\n}\n")])
+                .await;
+
+        app.keys("$h%").await;
+        assert_eq!(app.cursor(), (3, 0));
+        app.keys("%").await;
+        assert_eq!(app.cursor(), (0, 10));
+
+        // the match of the bracket at the cursor stands out
+        let cell = app.app.terminal().backend().buffer().get(0, 3).clone();
+        assert!(cell.modifier.contains(Modifier::BOLD));
+
+        // brackets in strings have no match
+        app.keys("j0lllllllllllll%").await;
+        assert_eq!(app.cursor(), (1, 13));
+        app.keys("j0lllll%").await;
+        assert_eq!(app.cursor(), (2, 7));
+    }
+
+    #[tokio::test]
+    async fn scans_text_without_syntax_tree() {
+        let file = file("a (b [c] (d)) e\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("ll%").await;
+        assert_eq!(app.cursor(), (0, 12));
+        app.keys("h%").await;
+        assert_eq!(app.cursor(), (0, 9));
+        app.keys("0%").await;
+        assert_eq!(app.cursor(), (0, 0));
+    }
+}
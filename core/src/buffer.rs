@@ -1,13 +1,23 @@
+use color_eyre::eyre::{bail, Result};
+use lsp_types::{Diagnostic, TextDocumentContentChangeEvent};
 use ropey::{Rope, RopeSlice};
 use std::{
     collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
     path::{Path, PathBuf},
 };
 use syntax::Highlight;
 
-use crate::{cursor::Cursor, window::WindowId};
+use crate::{
+    cursor::Cursor, diff::Edit, fold::Folds, git, language::Language, lsp, window::WindowId,
+};
+
+/// Files larger than this are streamed in the background and are not highlighted
+pub const LARGE_FILE_THRESHOLD: usize = 1024 * 1024;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct BufferId(usize);
 
 impl BufferId {
@@ -31,14 +41,28 @@ impl Buffers {
     }
 
     pub fn add(&mut self, content: Rope, file_path: Option<&Path>) -> BufferId {
-        let buffer_id = BufferId(self.next_buffer_id);
-        self.next_buffer_id += 1;
+        let buffer_id = self.next_id();
         self.buffers
             .push(Buffer::new(buffer_id, content, file_path));
 
         buffer_id
     }
 
+    /// Add an empty buffer that is filled by a background load
+    pub fn add_loading(&mut self, file_path: Option<&Path>, total: Option<usize>) -> BufferId {
+        let buffer_id = self.next_id();
+        self.buffers
+            .push(Buffer::new_loading(buffer_id, file_path, total));
+
+        buffer_id
+    }
+
+    fn next_id(&mut self) -> BufferId {
+        let buffer_id = BufferId(self.next_buffer_id);
+        self.next_buffer_id += 1;
+        buffer_id
+    }
+
     pub fn find_by_file_path(&self, file_path: &Path) -> Option<BufferId> {
         self.iter()
             .find(|b| {
@@ -50,6 +74,12 @@ impl Buffers {
             .map(|b| b.id)
     }
 
+    /// Drop a buffer, no window may show it anymore
+    pub fn remove(&mut self, buffer_id: BufferId) -> Option<Buffer> {
+        let index = self.buffers.iter().position(|buf| buf.id == buffer_id)?;
+        Some(self.buffers.remove(index))
+    }
+
     pub fn get(&self, buffer_id: BufferId) -> Option<&Buffer> {
         self.iter().find(|buf| buf.id == buffer_id)
     }
@@ -67,17 +97,74 @@ impl Buffers {
     }
 }
 
+/// Progress of a file that is still being read in the background
+#[derive(Debug, Clone, Copy)]
+pub struct Loading {
+    pub loaded: usize,
+    /// Unknown when reading from a stream such as stdin
+    pub total: Option<usize>,
+}
+
+impl Loading {
+    pub fn percent(&self) -> Option<usize> {
+        match self.total {
+            Some(0) => Some(100),
+            Some(total) => Some(self.loaded.min(total) * 100 / total),
+            None => None,
+        }
+    }
+}
+
+/// Content and cursors from before an undoable change
+struct Revision {
+    content: Rope,
+    cursors: HashMap<WindowId, Cursor>,
+}
+
+#[derive(Default)]
+struct History {
+    undo: Vec<Revision>,
+    redo: Vec<Revision>,
+    /// Set while edits are grouped into the latest undo step
+    open: bool,
+}
+
 pub struct Buffer {
     pub id: BufferId,
     content: Rope,
     cursors: HashMap<WindowId, Cursor>,
     file_path: Option<PathBuf>,
-    pub highlight: Highlight,
+    /// Syntax highlighting, skipped for large files
+    pub highlight: Option<Highlight>,
+    pub loading: Option<Loading>,
+    /// Loading stopped on an error, so the content is only the start of the file
+    pub incomplete: bool,
+    /// Edits are rejected while set
+    pub readonly: bool,
+    modified: bool,
+    history: History,
+    /// Edits not yet sent to the language server, recorded once it knows the buffer
+    changes: Option<Vec<TextDocumentContentChangeEvent>>,
+    /// Last diagnostics published by the language server
+    pub diagnostics: Vec<Diagnostic>,
+    /// Changes compared to the version in git
+    pub git: Option<git::Diff>,
+    /// Commits of the lines, shown by `:blame`
+    pub blame: Option<git::Blame>,
+    pub folds: Folds,
+    /// Incremented on every change of the content
+    version: usize,
+    /// Version of the content the syntax tree was last parsed from
+    parsed_version: Option<usize>,
 }
 
 impl Buffer {
     pub fn new(id: BufferId, content: Rope, file_path: Option<&Path>) -> Self {
-        let highlight = Highlight::new(content.slice(..));
+        let highlight = if content.len_bytes() <= LARGE_FILE_THRESHOLD {
+            Some(Highlight::new(content.slice(..)))
+        } else {
+            None
+        };
 
         match file_path {
             Some(file_path) => Self {
@@ -86,6 +173,18 @@ impl Buffer {
                 cursors: HashMap::default(),
                 file_path: Some(file_path.to_path_buf()),
                 highlight,
+                loading: None,
+                incomplete: false,
+                readonly: false,
+                modified: false,
+                history: History::default(),
+                changes: None,
+                diagnostics: vec![],
+                git: None,
+                blame: None,
+                folds: Folds::default(),
+                version: 0,
+                parsed_version: None,
             },
             None => Self {
                 id,
@@ -93,27 +192,347 @@ impl Buffer {
                 cursors: HashMap::default(),
                 file_path: None,
                 highlight,
+                loading: None,
+                incomplete: false,
+                readonly: false,
+                modified: false,
+                history: History::default(),
+                changes: None,
+                diagnostics: vec![],
+                git: None,
+                blame: None,
+                folds: Folds::default(),
+                version: 0,
+                parsed_version: None,
             },
         }
     }
 
+    /// Create a buffer whose content will be streamed in with [`Buffer::append_loaded`]
+    pub fn new_loading(id: BufferId, file_path: Option<&Path>, total: Option<usize>) -> Self {
+        Self {
+            id,
+            content: Rope::new(),
+            cursors: HashMap::default(),
+            file_path: file_path.map(Path::to_path_buf),
+            highlight: None,
+            loading: Some(Loading { loaded: 0, total }),
+            incomplete: false,
+            readonly: false,
+            modified: false,
+            history: History::default(),
+            changes: None,
+            diagnostics: vec![],
+            git: None,
+            blame: None,
+            folds: Folds::default(),
+            version: 0,
+            parsed_version: None,
+        }
+    }
+
+    /// Append a chunk read by a background load and update its progress
+    pub fn append_loaded(&mut self, text: &str, loaded: usize) {
+        let end = self.content.len_chars();
+        self.content.insert(end, text);
+        self.version += 1;
+
+        if let Some(loading) = self.loading.as_mut() {
+            loading.loaded = loaded;
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
     pub fn content(&self) -> &Rope {
         &self.content
     }
 
-    pub fn content_mut(&mut self) -> &mut Rope {
-        &mut self.content
+    pub fn file_path(&self) -> Option<&Path> {
+        self.file_path.as_deref()
+    }
+
+    /// Language detected from the file name
+    pub fn language(&self) -> Option<&'static Language> {
+        self.file_path.as_deref().and_then(Language::from_path)
+    }
+
+    /// Syntax tree of the current content, parsed again if it changed; only Rust files have one
+    pub fn syntax(&mut self) -> Option<&Highlight> {
+        if self.language()?.name != "rust" {
+            return None;
+        }
+        let highlight = self.highlight.as_mut()?;
+        if self.parsed_version != Some(self.version) {
+            highlight.reparse(self.content.slice(..));
+            self.parsed_version = Some(self.version);
+        }
+        Some(highlight)
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Changes whenever the content does
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Insert text at a char index.
+    ///
+    /// Cursors after the index move along with the text.
+    pub fn insert(&mut self, char_idx: usize, text: &str) -> Result<()> {
+        self.ensure_writable()?;
+        self.record_undo();
+
+        self.record_change(char_idx..char_idx, text);
+
+        let line = self.content.char_to_line(char_idx);
+        let lines = text.matches('\n').count();
+        if lines > 0 {
+            // text inserted at the start of a line pushes it down
+            let at_line_start = self.content.line_to_char(line) == char_idx;
+            self.folds
+                .insert_lines(line + usize::from(!at_line_start), lines);
+        }
+
+        let len = text.chars().count();
+        self.content.insert(char_idx, text);
+        self.modified = true;
+        self.version += 1;
+
+        self.map_cursors(|pos| if pos > char_idx { pos + len } else { pos });
+
+        Ok(())
+    }
+
+    /// Remove a char range
+    pub fn remove(&mut self, range: Range<usize>) -> Result<()> {
+        self.ensure_writable()?;
+        self.record_undo();
+
+        self.record_change(range.clone(), "");
+
+        let start_line = self.content.char_to_line(range.start);
+        let end_line = self.content.char_to_line(range.end);
+        if end_line > start_line {
+            // whole lines go away, otherwise the end line is joined into the start line
+            let whole_lines = self.content.line_to_char(start_line) == range.start
+                && self.content.line_to_char(end_line) == range.end;
+            match whole_lines {
+                true => self.folds.remove_lines(start_line..end_line),
+                false => self.folds.remove_lines(start_line + 1..end_line + 1),
+            }
+        }
+
+        let len = range.len();
+        self.content.remove(range.clone());
+        self.modified = true;
+        self.version += 1;
+
+        self.map_cursors(|pos| {
+            if pos >= range.end {
+                pos - len
+            } else {
+                pos.min(range.start)
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Apply edits sorted by position, all ranges refer to the content before the first one
+    pub fn apply_edits(&mut self, edits: &[Edit]) -> Result<()> {
+        self.ensure_writable()?;
+
+        for edit in edits.iter().rev() {
+            if !edit.range.is_empty() {
+                self.remove(edit.range.clone())?;
+            }
+            if !edit.text.is_empty() {
+                self.insert(edit.range.start, &edit.text)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start recording edits for the language server
+    pub fn track_changes(&mut self) {
+        self.changes = Some(vec![]);
+    }
+
+    /// Edits recorded since the last call
+    pub fn take_changes(&mut self) -> Vec<TextDocumentContentChangeEvent> {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Record replacing `range` with `text`, positions refer to the content before the edit
+    fn record_change(&mut self, range: Range<usize>, text: &str) {
+        let Some(changes) = self.changes.as_mut() else {
+            return;
+        };
+
+        changes.push(TextDocumentContentChangeEvent {
+            range: Some(lsp_types::Range::new(
+                lsp::position(&self.content, range.start),
+                lsp::position(&self.content, range.end),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        });
+    }
+
+    /// Move the start of every cursor, keeping its length within the content
+    fn map_cursors(&mut self, map: impl Fn(usize) -> usize) {
+        let len_chars = self.content.len_chars();
+        for cursor in self.cursors.values_mut() {
+            let start = map(cursor.range.start).min(len_chars);
+            let end = (start + cursor.range.len()).min(len_chars);
+            cursor.range = start..end;
+        }
+    }
+
+    /// Save the state before the first edit of an undo step
+    fn record_undo(&mut self) {
+        if self.history.open {
+            return;
+        }
+
+        self.history.undo.push(Revision {
+            content: self.content.clone(),
+            cursors: self.cursors.clone(),
+        });
+        self.history.redo.clear();
+        self.history.open = true;
+    }
+
+    /// End the current undo step, the next edit starts a new one
+    pub fn commit(&mut self) {
+        self.history.open = false;
+    }
+
+    /// Revert the last undo step, returns false when there is nothing to undo
+    pub fn undo(&mut self) -> Result<bool> {
+        self.ensure_writable()?;
+        self.commit();
+
+        let Some(revision) = self.history.undo.pop() else {
+            return Ok(false);
+        };
+        let current = self.restore(revision);
+        self.history.redo.push(current);
+
+        Ok(true)
+    }
+
+    /// Apply the last undone step again, returns false when there is nothing to redo
+    pub fn redo(&mut self) -> Result<bool> {
+        self.ensure_writable()?;
+        self.commit();
+
+        let Some(revision) = self.history.redo.pop() else {
+            return Ok(false);
+        };
+        let current = self.restore(revision);
+        self.history.undo.push(current);
+
+        Ok(true)
+    }
+
+    /// Swap in a revision, returning the replaced state
+    fn restore(&mut self, revision: Revision) -> Revision {
+        let content = std::mem::replace(&mut self.content, revision.content);
+        let cursors = self.cursors.clone();
+
+        // the whole text changed as far as the language server is concerned
+        if let Some(changes) = self.changes.as_mut() {
+            *changes = vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: self.content.to_string(),
+            }];
+        }
+
+        // windows opened since the revision keep their cursor, clamped to the content
+        let len = self.content.len_chars();
+        for (window_id, cursor) in self.cursors.iter_mut() {
+            *cursor = match revision.cursors.get(window_id) {
+                Some(cursor) => cursor.clone(),
+                None => Cursor::with_range(cursor.range.start.min(len), cursor.range.end.min(len)),
+            };
+        }
+        self.modified = true;
+        self.version += 1;
+        self.folds.open_all();
+
+        Revision { content, cursors }
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.readonly {
+            bail!("Cannot modify, buffer is read-only");
+        }
+        // an undo step would hold only the chunks loaded so far
+        if self.is_loading() {
+            bail!("Buffer is still loading");
+        }
+        Ok(())
+    }
+
+    /// Write the content to `file_path`, or to the buffer's own path.
+    ///
+    /// An unnamed buffer takes `file_path` as its name.
+    pub fn save(&mut self, file_path: Option<&Path>, force: bool) -> Result<()> {
+        if self.readonly && !force {
+            bail!("Buffer is read-only (add ! to override)");
+        }
+        if self.is_loading() {
+            bail!("Buffer is still loading");
+        }
+        if self.incomplete && !force {
+            bail!("Buffer was only partly loaded, writing it would truncate the file (add ! to override)");
+        }
+
+        let path = match (file_path, self.file_path.as_ref()) {
+            (Some(path), _) => path.to_path_buf(),
+            (None, Some(path)) => path.clone(),
+            (None, None) => bail!("No file name"),
+        };
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.content.write_to(&mut writer)?;
+        writer.flush()?;
+
+        if self.file_path.is_none() {
+            self.file_path = Some(path.clone());
+        }
+        if self.file_path.as_ref() == Some(&path) {
+            self.modified = false;
+        }
+
+        Ok(())
     }
 
     pub fn get_cursor(&self, window_id: WindowId) -> &Cursor {
         &self.cursors[&window_id]
     }
 
+    pub fn has_cursor(&self, window_id: WindowId) -> bool {
+        self.cursors.contains_key(&window_id)
+    }
+
     pub fn set_cursor(&mut self, window_id: WindowId, cursor: Cursor) {
         self.cursors.insert(window_id, cursor);
     }
 
-    pub fn get_line(&self, index: usize) -> Option<RopeSlice> {
+    pub fn get_line(&self, index: usize) -> Option<RopeSlice<'_>> {
         self.content.get_line(index)
     }
 
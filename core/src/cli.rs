@@ -1,10 +1,211 @@
-use std::path::PathBuf;
+use std::{
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+};
 
 use clap::Parser;
 
 #[derive(Parser, Debug)]
 #[command(author, version = "", about)]
 pub struct Args {
+    /// Files to open, `-` reads from stdin.
+    /// `file:line:col` opens at a position and `+N`, `+/pattern` or `+`
+    /// position the cursor in the first file
     #[clap(name = "file")]
     pub files: Vec<PathBuf>,
+
+    /// Write the buffer to stdout on exit
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// Open files read-only
+    #[arg(short = 'R')]
+    pub readonly: bool,
+
+    /// Run a command after opening the files, can be repeated
+    #[arg(short = 'c', value_name = "command")]
+    pub commands: Vec<String>,
+
+    /// Open one window per file, stacked
+    #[arg(short = 'o', conflicts_with = "vertical")]
+    pub horizontal: bool,
+
+    /// Open one window per file, side by side
+    #[arg(short = 'O')]
+    pub vertical: bool,
+
+    /// Compare two files side by side
+    #[arg(short = 'd', conflicts_with_all = ["horizontal", "vertical"])]
+    pub diff: bool,
+
+    /// Skip loading the user config
+    #[arg(long)]
+    pub clean: bool,
+
+    /// Run keys and commands from a script after opening the files
+    #[arg(short = 's', value_name = "scriptin")]
+    pub script: Option<PathBuf>,
+
+    /// Run without a terminal, modified buffers are written once the script ends
+    #[arg(long, requires = "script")]
+    pub headless: bool,
+}
+
+/// Where to place the cursor after opening a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartPosition {
+    /// 1-based line and optional column
+    Line(usize, Option<usize>),
+    LastLine,
+    Search(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileArg {
+    pub path: PathBuf,
+    pub position: Option<StartPosition>,
+}
+
+impl Args {
+    /// Whether stdin should be loaded into a buffer.
+    ///
+    /// Either `-` was passed or stdin is piped and no files were given.
+    pub fn read_stdin(&self) -> bool {
+        self.files.iter().any(|path| is_stdin(path))
+            || (self.file_args().is_empty() && !io::stdin().is_terminal())
+    }
+
+    /// Files to open with their start position.
+    ///
+    /// A `+` argument applies to the first file, taking precedence over `file:line:col`.
+    pub fn file_args(&self) -> Vec<FileArg> {
+        let mut start = None;
+        let mut files = vec![];
+
+        for arg in &self.files {
+            match arg.to_str().and_then(parse_start_position) {
+                Some(position) => start = Some(position),
+                None if is_stdin(arg) => {}
+                None => files.push(parse_file_position(arg)),
+            }
+        }
+
+        if let (Some(start), Some(first)) = (start, files.first_mut()) {
+            first.position = Some(start);
+        }
+
+        files
+    }
+}
+
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Parse `+`, `+N` and `+/pattern`
+fn parse_start_position(arg: &str) -> Option<StartPosition> {
+    let rest = arg.strip_prefix('+')?;
+
+    if rest.is_empty() {
+        return Some(StartPosition::LastLine);
+    }
+    if let Some(pattern) = rest.strip_prefix('/') {
+        return Some(StartPosition::Search(pattern.to_string()));
+    }
+
+    rest.parse()
+        .ok()
+        .map(|line| StartPosition::Line(line, None))
+}
+
+/// Split a trailing `:line` or `:line:col` from a path that does not exist as given
+fn parse_file_position(arg: &Path) -> FileArg {
+    let unchanged = FileArg {
+        path: arg.to_path_buf(),
+        position: None,
+    };
+
+    if arg.exists() {
+        return unchanged;
+    }
+    let Some(arg_str) = arg.to_str() else {
+        return unchanged;
+    };
+
+    // compiler output often ends the location with a colon
+    let trimmed = arg_str.trim_end_matches(':');
+
+    let mut parts = trimmed.rsplitn(3, ':');
+    let last = parts.next().and_then(|part| part.parse::<usize>().ok());
+    let middle = parts.next();
+    let rest = parts.next();
+
+    match (last, middle, rest) {
+        (Some(col), Some(line), Some(path)) if line.parse::<usize>().is_ok() => FileArg {
+            path: PathBuf::from(path),
+            position: Some(StartPosition::Line(line.parse().unwrap(), Some(col))),
+        },
+        (Some(line), Some(_), _) => {
+            let (path, _) = trimmed.rsplit_once(':').unwrap();
+            FileArg {
+                path: PathBuf::from(path),
+                position: Some(StartPosition::Line(line, None)),
+            }
+        }
+        _ => unchanged,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn args(files: &[&str]) -> Args {
+        Args::parse_from(std::iter::once("editor").chain(files.iter().copied()))
+    }
+
+    #[test]
+    fn parses_file_line_col() {
+        assert_eq!(
+            parse_file_position(Path::new("src/missing.rs:12:5:")),
+            FileArg {
+                path: PathBuf::from("src/missing.rs"),
+                position: Some(StartPosition::Line(12, Some(5))),
+            }
+        );
+        assert_eq!(
+            parse_file_position(Path::new("src/missing.rs:12")),
+            FileArg {
+                path: PathBuf::from("src/missing.rs"),
+                position: Some(StartPosition::Line(12, None)),
+            }
+        );
+        assert_eq!(parse_file_position(Path::new("missing.rs")).position, None);
+    }
+
+    #[test]
+    fn plus_argument_applies_to_first_file() {
+        let files = args(&["+/fn main", "a.rs", "b.rs"]).file_args();
+
+        assert_eq!(
+            files[0].position,
+            Some(StartPosition::Search("fn main".to_string()))
+        );
+        assert_eq!(files[1].position, None);
+
+        let files = args(&["a.rs", "+"]).file_args();
+        assert_eq!(files[0].position, Some(StartPosition::LastLine));
+    }
+
+    #[test]
+    fn parses_flags() {
+        let args = args(&["-R", "-c", "w", "-c", "q", "-O", "--clean", "a.rs"]);
+
+        assert!(args.readonly && args.vertical && args.clean);
+        assert_eq!(args.commands, vec!["w", "q"]);
+
+        assert!(Args::try_parse_from(["editor", "--headless", "a.rs"]).is_err());
+    }
 }
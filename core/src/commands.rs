@@ -0,0 +1,844 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, eyre, Report, Result};
+
+use crate::{
+    buffer::BufferId,
+    components::{Components, Context},
+    diff_mode,
+    editor::{Editor, OpenAction},
+    format, git,
+    grep::GrepPicker,
+    lsp, movements,
+    picker::FilePicker,
+    popup::Popup,
+    quickfix::{self, QuickfixPanel},
+};
+
+/// Arguments passed to a typed command
+#[derive(Clone, Copy)]
+pub struct CommandArgs<'a> {
+    pub args: &'a [&'a str],
+    /// Everything after the command name as typed, e.g. a pattern with spaces
+    pub text: &'a str,
+    /// Set when the command was suffixed with `!`
+    pub force: bool,
+}
+
+type CommandFn = fn(&mut Components, &mut Context, CommandArgs) -> Result<()>;
+
+/// Command that can be typed in the command prompt, e.g. `:write`
+pub struct TypableCommand {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub doc: &'static str,
+    pub fun: CommandFn,
+}
+
+pub const TYPABLE_COMMANDS: &[TypableCommand] = &[
+    TypableCommand {
+        name: "quit",
+        aliases: &["q"],
+        doc: "Close the current window, quitting after the last one",
+        fun: quit,
+    },
+    TypableCommand {
+        name: "qall",
+        aliases: &["qa"],
+        doc: "Quit, closing every window",
+        fun: quit_all,
+    },
+    TypableCommand {
+        name: "write",
+        aliases: &["w"],
+        doc: "Write the current buffer, optionally to a new path",
+        fun: write,
+    },
+    TypableCommand {
+        name: "wq",
+        aliases: &["x"],
+        doc: "Write the current buffer and close the window",
+        fun: write_quit,
+    },
+    TypableCommand {
+        name: "wall",
+        aliases: &["wa"],
+        doc: "Write every modified buffer",
+        fun: write_all,
+    },
+    TypableCommand {
+        name: "format",
+        aliases: &["fmt"],
+        doc: "Format the current buffer with the formatter of its language",
+        fun: format,
+    },
+    TypableCommand {
+        name: "rename",
+        aliases: &[],
+        doc: "Rename the symbol under the cursor with the language server",
+        fun: rename,
+    },
+    TypableCommand {
+        name: "edit",
+        aliases: &["e"],
+        doc: "Open a file in the current window",
+        fun: edit,
+    },
+    TypableCommand {
+        name: "split",
+        aliases: &["sp"],
+        doc: "Open a file, or the current buffer, in a stacked window",
+        fun: split,
+    },
+    TypableCommand {
+        name: "vsplit",
+        aliases: &["vs"],
+        doc: "Open a file, or the current buffer, in a side by side window",
+        fun: vsplit,
+    },
+    TypableCommand {
+        name: "bnext",
+        aliases: &["bn"],
+        doc: "Show the next buffer",
+        fun: buffer_next,
+    },
+    TypableCommand {
+        name: "bprevious",
+        aliases: &["bp"],
+        doc: "Show the previous buffer",
+        fun: buffer_previous,
+    },
+    TypableCommand {
+        name: "bdelete",
+        aliases: &["bd"],
+        doc: "Close the current buffer, showing the next one in its windows",
+        fun: buffer_delete,
+    },
+    TypableCommand {
+        name: "files",
+        aliases: &[],
+        doc: "Pick a file below the working directory or the given one",
+        fun: files,
+    },
+    TypableCommand {
+        name: "grep",
+        aliases: &[],
+        doc: "Search a pattern, spaces included, in the files below the working directory",
+        fun: grep,
+    },
+    TypableCommand {
+        name: "grepin",
+        aliases: &[],
+        doc: "Search a pattern in the files below the given directory",
+        fun: grep_in,
+    },
+    TypableCommand {
+        name: "cnext",
+        aliases: &["cn"],
+        doc: "Jump to the next quickfix entry",
+        fun: quickfix_next,
+    },
+    TypableCommand {
+        name: "cprevious",
+        aliases: &["cp", "cprev"],
+        doc: "Jump to the previous quickfix entry",
+        fun: quickfix_previous,
+    },
+    TypableCommand {
+        name: "make",
+        aliases: &[],
+        doc: "Run the make command and list the errors it printed",
+        fun: make,
+    },
+    TypableCommand {
+        name: "cfile",
+        aliases: &["cf"],
+        doc: "List the errors of an error file, errors.err by default",
+        fun: quickfix_file,
+    },
+    TypableCommand {
+        name: "copen",
+        aliases: &["cope"],
+        doc: "Show the quickfix list",
+        fun: quickfix_open,
+    },
+    TypableCommand {
+        name: "previewhunk",
+        aliases: &[],
+        doc: "Show the git changes at the cursor",
+        fun: preview_hunk,
+    },
+    TypableCommand {
+        name: "stagehunk",
+        aliases: &[],
+        doc: "Add the git changes at the cursor to the index",
+        fun: stage_hunk,
+    },
+    TypableCommand {
+        name: "reverthunk",
+        aliases: &[],
+        doc: "Replace the git changes at the cursor with the indexed lines",
+        fun: revert_hunk,
+    },
+    TypableCommand {
+        name: "blame",
+        aliases: &[],
+        doc: "Show or hide the commit of each line next to the text",
+        fun: blame,
+    },
+    TypableCommand {
+        name: "blamecommit",
+        aliases: &[],
+        doc: "Open the changes of the commit of the cursor line",
+        fun: blame_commit,
+    },
+    TypableCommand {
+        name: "diffsplit",
+        aliases: &[],
+        doc: "Open a file side by side and compare it with the current buffer",
+        fun: diff_split,
+    },
+    TypableCommand {
+        name: "diffoff",
+        aliases: &[],
+        doc: "Stop comparing windows",
+        fun: diff_off,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static TypableCommand> {
+    TYPABLE_COMMANDS
+        .iter()
+        .find(|command| command.name == name || command.aliases.contains(&name))
+}
+
+/// Inclusive 0-based line range given before a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Lines addresses are resolved against
+pub struct RangeContext {
+    /// Line of the cursor, `.`
+    pub current: usize,
+    /// Last line of the buffer, `$`
+    pub last: usize,
+    /// Lines of the last Visual mode selection, `'<` and `'>`
+    pub selection: Option<LineRange>,
+}
+
+/// Split a leading range such as `%`, `3,7`, `.,$`, `'<,'>` or `.,+2` from a command
+pub fn parse_range<'a>(
+    input: &'a str,
+    context: &RangeContext,
+) -> Result<(Option<LineRange>, &'a str)> {
+    if let Some(rest) = input.strip_prefix('%') {
+        let range = LineRange {
+            start: 0,
+            end: context.last,
+        };
+        return Ok((Some(range), rest));
+    }
+
+    let (start, rest) = parse_address(input, context)?;
+    let Some(start) = start else {
+        return Ok((None, input));
+    };
+
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(after) => match parse_address(after, context)? {
+            (Some(end), rest) => (end, rest),
+            (None, _) => bail!("Missing address after ,"),
+        },
+        None => (start, rest),
+    };
+
+    let range = LineRange {
+        start: start.min(end),
+        end: start.max(end),
+    };
+    Ok((Some(range), rest))
+}
+
+/// Parse one address with optional `+N`/`-N` offsets, returns `None` when there is none
+fn parse_address<'a>(input: &'a str, context: &RangeContext) -> Result<(Option<usize>, &'a str)> {
+    let digits = input.len() - input.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+
+    let (mut line, mut rest) = if digits > 0 {
+        let line: usize = input[..digits].parse()?;
+        (Some(line.saturating_sub(1)), &input[digits..])
+    } else if let Some(rest) = input.strip_prefix('.') {
+        (Some(context.current), rest)
+    } else if let Some(rest) = input.strip_prefix('$') {
+        (Some(context.last), rest)
+    } else if let Some(rest) = input.strip_prefix("'<") {
+        let Some(selection) = context.selection else {
+            bail!("No previous selection");
+        };
+        (Some(selection.start), rest)
+    } else if let Some(rest) = input.strip_prefix("'>") {
+        let Some(selection) = context.selection else {
+            bail!("No previous selection");
+        };
+        (Some(selection.end), rest)
+    } else {
+        (None, input)
+    };
+
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let after = &rest[1..];
+        let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let count: usize = match digits {
+            0 => 1,
+            _ => after[..digits].parse()?,
+        };
+
+        let base = line.unwrap_or(context.current);
+        line = Some(match sign {
+            '+' => base + count,
+            _ => base
+                .checked_sub(count)
+                .ok_or_else(|| eyre!("Invalid range"))?,
+        });
+        rest = &after[digits..];
+    }
+
+    Ok((line, rest))
+}
+
+/// Resolve range addresses against the focused window
+fn range_context(context: &Context) -> Option<RangeContext> {
+    let window = context.editor.windows.get_focused()?;
+    let content = context.editor.buffers.get(window.buffer_id)?.content();
+
+    let cursor = context
+        .editor
+        .buffers
+        .get(window.buffer_id)?
+        .get_cursor(window.id);
+
+    // a trailing newline does not start another addressable line
+    let last = match content.len_chars() {
+        0 => 0,
+        len if content.char(len - 1) == '\n' => content.len_lines().saturating_sub(2),
+        _ => content.len_lines() - 1,
+    };
+
+    let selection = window.last_selection.as_ref().map(|chars| LineRange {
+        start: content.char_to_line(chars.start),
+        end: content.char_to_line(chars.end.saturating_sub(1).max(chars.start)),
+    });
+
+    Some(RangeContext {
+        current: content.char_to_line(cursor.range.start),
+        last,
+        selection,
+    })
+}
+
+/// Parse and run a command line such as `w foo.txt`, `q!`, `42`, `!ls` or `%!sort`
+pub fn execute(components: &mut Components, context: &mut Context, input: &str) -> Result<()> {
+    let input = input.trim().trim_start_matches(':');
+
+    if input.is_empty() {
+        return Ok(());
+    }
+
+    let (range, input) = match range_context(context) {
+        Some(range_context) => parse_range(input, &range_context)?,
+        None => (None, input),
+    };
+    let input = input.trim_start();
+
+    if let Some(cmd) = input.strip_prefix('!') {
+        if cmd.trim().is_empty() {
+            bail!("No shell command");
+        }
+        match range {
+            Some(range) => context.editor.filter_lines(range, cmd)?,
+            None => context.editor.run_shell(cmd),
+        }
+        return Ok(());
+    }
+
+    if let Some(range) = range {
+        if input.is_empty() {
+            movements::goto_line(context, range.end + 1, None);
+            return Ok(());
+        }
+        bail!("No range allowed");
+    }
+
+    let (name, text) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let text = text.trim_start();
+    let args: Vec<&str> = text.split_whitespace().collect();
+
+    let (name, force) = match name.strip_suffix('!') {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+
+    let command = find(name).ok_or_else(|| eyre!("Not an editor command: {name}"))?;
+
+    (command.fun)(
+        components,
+        context,
+        CommandArgs {
+            args: &args,
+            text,
+            force,
+        },
+    )
+}
+
+fn quit(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    context.editor.close_window(args.force)
+}
+
+fn quit_all(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    if !args.force {
+        context.editor.ensure_saved()?;
+    }
+    context.editor.quit();
+    Ok(())
+}
+
+fn write(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    write_buffer(context.editor, args, false)
+}
+
+/// Write the focused buffer, closing its window afterwards when `quit` is set.
+///
+/// With `format_on_save` the buffer is formatted in the background first and
+/// written once that finished. A failing formatter still writes the text as it
+/// is and reports the error, so it can't keep changes from being saved.
+fn write_buffer(editor: &mut Editor, args: CommandArgs, quit: bool) -> Result<()> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No window to write");
+    };
+    let buffer_id = window.buffer_id;
+    let path = args.args.first().map(PathBuf::from);
+    let force = args.force;
+
+    if editor.config.format_on_save && !editor.buffers.get(buffer_id).unwrap().readonly {
+        let target = path.clone();
+        let formatting = format::format_buffer(editor, buffer_id, move |result, _, context| {
+            let editor = &mut context.editor;
+            if let Err(err) = save(
+                editor,
+                buffer_id,
+                target.as_deref(),
+                force,
+                result.err(),
+                quit,
+            ) {
+                editor.set_error(err.to_string());
+            }
+        });
+        if formatting {
+            return Ok(());
+        }
+    }
+
+    save(editor, buffer_id, path.as_deref(), force, None, quit)
+}
+
+/// Write a buffer and report it, `format_error` being why it was written unformatted
+fn save(
+    editor: &mut Editor,
+    buffer_id: BufferId,
+    path: Option<&Path>,
+    force: bool,
+    format_error: Option<Report>,
+    quit: bool,
+) -> Result<()> {
+    let Some(buf) = editor.buffers.get_mut(buffer_id) else {
+        bail!("Buffer was closed before it was written");
+    };
+    buf.save(path, force)?;
+
+    let name = buf
+        .file_path()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    lsp::did_save(editor, buffer_id);
+    match format_error {
+        Some(err) => editor.set_error(format!("\"{name}\" written, not formatted: {err}")),
+        None => editor.set_status(format!("\"{name}\" written")),
+    }
+
+    if quit {
+        // the focus may have moved while formatting
+        if let Some(window_id) = editor.windows.get_by_buffer_id(buffer_id).map(|w| w.id) {
+            editor.windows.focus(window_id);
+        }
+        editor.close_window(force)?;
+    }
+
+    Ok(())
+}
+
+fn format(_components: &mut Components, context: &mut Context, _args: CommandArgs) -> Result<()> {
+    let editor = &mut context.editor;
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer to format");
+    };
+    let buffer_id = window.buffer_id;
+
+    if editor.buffers.get(buffer_id).unwrap().readonly {
+        bail!("Cannot modify, buffer is read-only");
+    }
+    let formatting = format::format_buffer(editor, buffer_id, |result, _, context| {
+        if let Err(err) = result {
+            context.editor.set_error(err.to_string());
+        }
+    });
+    if !formatting {
+        bail!("No formatter configured for this file");
+    }
+
+    Ok(())
+}
+
+fn rename(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    let [new_name] = args.args else {
+        bail!("Usage: rename <new name>");
+    };
+    lsp::rename(context.editor, new_name)
+}
+
+fn write_quit(
+    _components: &mut Components,
+    context: &mut Context,
+    args: CommandArgs,
+) -> Result<()> {
+    write_buffer(context.editor, args, true)
+}
+
+fn write_all(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    context.editor.write_all(args.force)
+}
+
+fn edit(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    let Some(path) = args.args.first() else {
+        bail!("No file name");
+    };
+    context.editor.open(Path::new(path), OpenAction::Replace)?;
+    Ok(())
+}
+
+fn open_split(context: &mut Context, args: CommandArgs, action: OpenAction) -> Result<()> {
+    match args.args.first() {
+        Some(path) => {
+            context.editor.open(Path::new(path), action)?;
+        }
+        None => {
+            let Some(window) = context.editor.windows.get_focused() else {
+                bail!("No buffer to split");
+            };
+            let buffer_id = window.buffer_id;
+            context.editor.show_buffer(buffer_id, action);
+        }
+    }
+    Ok(())
+}
+
+fn split(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    open_split(context, args, OpenAction::HorizontalSplit)
+}
+
+fn vsplit(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    open_split(context, args, OpenAction::VerticalSplit)
+}
+
+fn buffer_next(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    context.editor.cycle_buffer(true);
+    Ok(())
+}
+
+fn buffer_previous(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    context.editor.cycle_buffer(false);
+    Ok(())
+}
+
+fn buffer_delete(
+    _components: &mut Components,
+    context: &mut Context,
+    args: CommandArgs,
+) -> Result<()> {
+    let Some(window) = context.editor.windows.get_focused() else {
+        bail!("No buffer to close");
+    };
+    context.editor.close_buffer(window.buffer_id, args.force)
+}
+
+fn files(components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    let root = args.args.first().map(PathBuf::from).unwrap_or_default();
+    components.push(Box::new(FilePicker::new(&root, context.editor)));
+    Ok(())
+}
+
+fn grep(components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    let root = PathBuf::new();
+    components.push(Box::new(GrepPicker::new(&root, args.text, context.editor)));
+    Ok(())
+}
+
+fn grep_in(components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    let (root, pattern) = args
+        .text
+        .split_once(char::is_whitespace)
+        .unwrap_or((args.text, ""));
+    if root.is_empty() {
+        bail!("No directory to search in");
+    }
+    let picker = GrepPicker::new(Path::new(root), pattern.trim_start(), context.editor);
+    components.push(Box::new(picker));
+    Ok(())
+}
+
+fn quickfix_next(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    quickfix::step(context.editor, true)
+}
+
+fn quickfix_previous(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    quickfix::step(context.editor, false)
+}
+
+fn make(_components: &mut Components, context: &mut Context, args: CommandArgs) -> Result<()> {
+    quickfix::make(context.editor, args.args)
+}
+
+fn quickfix_file(
+    _components: &mut Components,
+    context: &mut Context,
+    args: CommandArgs,
+) -> Result<()> {
+    let path = args
+        .args
+        .first()
+        .copied()
+        .unwrap_or(quickfix::DEFAULT_ERROR_FILE);
+    quickfix::read_error_file(context.editor, Path::new(path))
+}
+
+fn quickfix_open(
+    components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    if context.editor.quickfix.entries.is_empty() {
+        bail!("No quickfix entries");
+    }
+    components.push(Box::new(QuickfixPanel::new(context.editor)));
+    Ok(())
+}
+
+fn preview_hunk(
+    components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    let text = git::preview_hunk(context.editor)?;
+    components.push(Box::new(Popup::new(text)));
+    Ok(())
+}
+
+fn stage_hunk(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    git::stage_hunk(context.editor)
+}
+
+fn revert_hunk(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    git::revert_hunk(context.editor)
+}
+
+fn blame(_components: &mut Components, context: &mut Context, _args: CommandArgs) -> Result<()> {
+    git::toggle_blame(context.editor)
+}
+
+fn blame_commit(
+    _components: &mut Components,
+    context: &mut Context,
+    _args: CommandArgs,
+) -> Result<()> {
+    git::show_blamed_commit(context.editor)
+}
+
+fn diff_split(
+    _components: &mut Components,
+    context: &mut Context,
+    args: CommandArgs,
+) -> Result<()> {
+    let Some(path) = args.args.first() else {
+        bail!("No file name");
+    };
+    let Some(window) = context.editor.windows.get_focused() else {
+        bail!("No buffer to compare");
+    };
+    let current = window.id;
+
+    context
+        .editor
+        .open(Path::new(path), OpenAction::VerticalSplit)?;
+    let opened = context.editor.windows.get_focused().unwrap().id;
+    diff_mode::start(context.editor, [current, opened])
+}
+
+fn diff_off(_components: &mut Components, context: &mut Context, _args: CommandArgs) -> Result<()> {
+    context.editor.diff_mode = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::test_harness::{file, TestApp};
+
+    fn lines(start: usize, end: usize) -> Option<LineRange> {
+        Some(LineRange { start, end })
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let context = RangeContext {
+            current: 4,
+            last: 9,
+            selection: lines(1, 2),
+        };
+        let parse = |input| parse_range(input, &context).unwrap();
+
+        assert_eq!(parse("%!sort"), (lines(0, 9), "!sort"));
+        assert_eq!(parse("3,7!jq ."), (lines(2, 6), "!jq ."));
+        assert_eq!(parse(".,$"), (lines(4, 9), ""));
+        assert_eq!(parse(".,+2!fmt"), (lines(4, 6), "!fmt"));
+        assert_eq!(parse("'<,'>!rev"), (lines(1, 2), "!rev"));
+        assert_eq!(parse("7,3"), (lines(2, 6), ""));
+        assert_eq!(parse("12"), (lines(11, 11), ""));
+        assert_eq!(parse("w"), (None, "w"));
+        assert!(parse_range("1,", &context).is_err());
+        assert!(parse_range(
+            "'<",
+            &RangeContext {
+                selection: None,
+                ..context
+            }
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn shell_output_opens_in_scratch_buffer() {
+        let file = file("text\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys(":!echo out; echo err >&2<CR>").await;
+        app.wait_for_job().await;
+
+        assert_eq!(app.text(), "out\nerr\n");
+        assert!(app.buffer().readonly);
+        assert_eq!(app.app.editor.windows.count(), 2);
+
+        // the next run reuses the window
+        app.keys(":!exit 3<CR>").await;
+        app.wait_for_job().await;
+
+        assert_eq!(app.app.editor.windows.count(), 2);
+        let status = app.app.editor.status.as_ref().unwrap();
+        assert_eq!(status.text, "shell returned 3");
+
+        // and replaces the previous output buffer
+        assert_eq!(app.app.editor.buffers.iter().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn filters_lines_through_command() {
+        let file = file("c\nb\na\nlast");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys(":1,3!sort<CR>").await;
+        app.wait_for_job().await;
+        assert_eq!(app.text(), "a\nb\nc\nlast");
+
+        // operator on the cursor line and the one below, the last without newline
+        app.keys("jjj").await;
+        app.keys("!k").await;
+        app.keys("tr a-z A-Z<CR>").await;
+        app.wait_for_job().await;
+        assert_eq!(app.text(), "a\nb\nC\nLAST");
+        assert_eq!(app.cursor(), (2, 0));
+
+        app.keys("u").await;
+        assert_eq!(app.text(), "a\nb\nc\nlast");
+    }
+
+    #[tokio::test]
+    async fn failed_filter_keeps_text() {
+        let file = file("one\ntwo\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("vj!").await;
+        app.keys("echo oops >&2; exit 2<CR>").await;
+        app.wait_for_job().await;
+
+        assert_eq!(app.text(), "one\ntwo\n");
+        let status = app.app.editor.status.as_ref().unwrap();
+        assert_eq!(status.text, "shell returned 2: oops");
+    }
+
+    #[tokio::test]
+    async fn closes_buffers_in_every_window() {
+        let (dir, mut app) = TestApp::with_files(&[("a.txt", "a\n"), ("b.txt", "b\n")]).await;
+        let status = |app: &TestApp| app.app.editor.status.as_ref().unwrap().text.clone();
+
+        app.keys(":bd<CR>").await;
+        assert_eq!(status(&app), "Can't close the last buffer");
+
+        let b = dir.path().join("b.txt");
+        app.keys(&format!(":e {}<CR>:vs<CR>ix<Esc>:bd<CR>", b.display()))
+            .await;
+        assert_eq!(
+            status(&app),
+            "No write since last change (add ! to override)"
+        );
+
+        app.keys(":bd!<CR>").await;
+        assert_eq!(app.app.editor.buffers.iter().count(), 1);
+        assert_eq!(app.app.editor.windows.count(), 2);
+        let a = app.buffer().id;
+        assert!(app
+            .app
+            .editor
+            .windows
+            .iter()
+            .all(|window| window.buffer_id == a));
+        assert_eq!(app.text(), "a\n");
+    }
+}
@@ -0,0 +1,236 @@
+//! Commenting lines out and back in with the comment tokens of the buffer's language
+
+use std::ops::Range;
+
+use color_eyre::eyre::{bail, Result};
+use ropey::Rope;
+use text::next_grapheme_boundary;
+
+use crate::{cursor::Cursor, editor::Editor, indent};
+
+/// Replace `len` chars from `start` with `text`
+struct Edit {
+    start: usize,
+    len: usize,
+    text: String,
+}
+
+/// Chars of a line without its line break
+fn line_end(content: &Rope, line: usize) -> usize {
+    let start = content.line_to_char(line);
+    let text = content.line(line);
+    let breaks = text
+        .chars_at(text.len_chars())
+        .reversed()
+        .take_while(|char| *char == '\n' || *char == '\r')
+        .count();
+    start + text.len_chars() - breaks
+}
+
+/// Text of a line after its indentation, without the line break
+fn trimmed(content: &Rope, line: usize) -> String {
+    let start = content.line_to_char(line) + indent::leading(content, line).chars().count();
+    content.slice(start..line_end(content, line)).to_string()
+}
+
+/// Edit taking the line comment `token` off a line, and a space after it
+fn uncomment_line(content: &Rope, line: usize, token: &str) -> Edit {
+    let start = content.line_to_char(line) + indent::leading(content, line).chars().count();
+    let rest = &trimmed(content, line)[token.len()..];
+    let len = token.chars().count() + usize::from(rest.starts_with(' '));
+    Edit {
+        start,
+        len,
+        text: String::new(),
+    }
+}
+
+/// Edits taking the block comment tokens off a line, and the spaces inside them.
+///
+/// Whitespace after the closing token is left alone.
+fn uncomment_block(content: &Rope, line: usize, (open, close): (&str, &str)) -> [Edit; 2] {
+    let mut edits = uncomment_line(content, line, open);
+    let text = trimmed(content, line);
+    let text = text.trim_end();
+    let inner = &text[open.len()..text.len() - close.len()];
+    if inner.trim().is_empty() {
+        // nothing between the tokens, e.g. `<!-- -->`
+        edits.len = open.chars().count();
+    }
+
+    let end = edits.start + text.chars().count();
+    let mut len = close.chars().count();
+    if inner.ends_with(' ') && edits.len + len < text.chars().count() {
+        len += 1;
+    }
+    let close = Edit {
+        start: end - len,
+        len,
+        text: String::new(),
+    };
+    [edits, close]
+}
+
+/// Comment out the `lines` with text, or comment them back in when all of them are.
+///
+/// Tokens go after the indentation the lines share, so a commented block keeps its shape.
+/// Languages without a line comment wrap each line in a block comment.
+pub fn toggle(editor: &mut Editor, lines: Range<usize>) -> Result<()> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    if buf.readonly {
+        bail!("Cannot modify, buffer is read-only");
+    }
+    let Some(language) = buf.language() else {
+        bail!("No comment tokens for this file");
+    };
+    let (line_token, block) = (language.line_comment, language.block_comment);
+    let content = buf.content().clone();
+
+    let lines: Vec<_> = lines
+        .filter(|&line| !trimmed(&content, line).trim().is_empty())
+        .collect();
+    let column = lines
+        .iter()
+        .map(|&line| indent::leading(&content, line).chars().count())
+        .min()
+        .unwrap_or(0);
+
+    let mut edits = vec![];
+    match (line_token, block) {
+        (Some(token), _) => {
+            let commented = lines
+                .iter()
+                .all(|&line| trimmed(&content, line).starts_with(token));
+            for &line in &lines {
+                edits.push(match commented {
+                    true => uncomment_line(&content, line, token),
+                    false => Edit {
+                        start: content.line_to_char(line) + column,
+                        len: 0,
+                        text: format!("{token} "),
+                    },
+                });
+            }
+        }
+        (None, Some((open, close))) => {
+            let commented = lines.iter().all(|&line| {
+                let text = trimmed(&content, line);
+                let text = text.trim_end();
+                text.len() >= open.len() + close.len()
+                    && text.starts_with(open)
+                    && text.ends_with(close)
+            });
+            for &line in &lines {
+                match commented {
+                    true => edits.extend(uncomment_block(&content, line, (open, close))),
+                    false => {
+                        edits.push(Edit {
+                            start: content.line_to_char(line) + column,
+                            len: 0,
+                            text: format!("{open} "),
+                        });
+                        edits.push(Edit {
+                            start: line_end(&content, line),
+                            len: 0,
+                            text: format!(" {close}"),
+                        });
+                    }
+                }
+            }
+        }
+        (None, None) => bail!("No comment tokens for {}", language.name),
+    }
+
+    // the cursor stays on its char, or goes to the start of a token taken off over it
+    let cursor = buf.get_cursor(window.id).range.start;
+    let mut pos = cursor;
+    let mut shift = 0;
+    for edit in &edits {
+        if edit.start + edit.len <= cursor {
+            shift += edit.text.chars().count() as isize - edit.len as isize;
+        } else if edit.start <= cursor {
+            pos = edit.start;
+        }
+    }
+
+    // from the end, so the edits before keep their chars
+    for edit in edits.iter().rev() {
+        if edit.len > 0 {
+            buf.remove(edit.start..edit.start + edit.len)?;
+        }
+        if !edit.text.is_empty() {
+            buf.insert(edit.start, &edit.text)?;
+        }
+    }
+
+    let content = buf.content().slice(..);
+    let pos = (pos as isize + shift).max(0) as usize;
+    buf.set_cursor(
+        window.id,
+        Cursor {
+            range: pos..next_grapheme_boundary(&content, pos),
+        },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::test_harness::TestApp;
+
+    #[tokio::test]
+    async fn toggles_line_comments() {
+        let text = "fn main() {\n    if a {\n        b();\n\n    }\n}\n";
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", text)]).await;
+
+        app.keys("jgcj").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    // if a {\n    //     b();\n\n    }\n}\n"
+        );
+
+        app.keys("gcc").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    if a {\n    //     b();\n\n    }\n}\n"
+        );
+        app.keys("jgcc").await;
+        assert_eq!(app.text(), text);
+
+        // a partly commented range is commented as a whole, blank lines are left alone
+        app.keys("kgccvjjjgc").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    // // if a {\n    //     b();\n\n    // }\n}\n"
+        );
+        app.keys("u").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    // if a {\n        b();\n\n    }\n}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn wraps_lines_in_block_comments() {
+        let (_dir, mut app) = TestApp::with_files(&[("notes.md", "# Title\n  text\n")]).await;
+
+        app.keys("gcj").await;
+        assert_eq!(app.text(), "<!-- # Title -->\n<!--   text -->\n");
+        app.keys("gcj").await;
+        assert_eq!(app.text(), "# Title\n  text\n");
+    }
+
+    #[tokio::test]
+    async fn uncomments_blocks_with_trailing_whitespace() {
+        let (_dir, mut app) =
+            TestApp::with_files(&[("notes.md", "<!-- a -->  \n<!-- b -->\u{a0}\n")]).await;
+
+        app.keys("gcj").await;
+        assert_eq!(app.text(), "a  \nb\u{a0}\n");
+    }
+}
@@ -0,0 +1,534 @@
+//! Insert mode completion of the text before the cursor, with candidates from
+//! pluggable sources ranked by fuzzy matching
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+use ropey::Rope;
+use text::next_grapheme_boundary;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    buffer::{BufferId, LARGE_FILE_THRESHOLD},
+    components::{Component, Context, EventPropagation},
+    cursor::Cursor,
+    editor::Editor,
+    fuzzy::{self, fuzzy_match},
+    lsp,
+    mode::Mode,
+    popup::{self, BACKGROUND, MENU_ITEMS, SELECTED},
+    terminal::{Event, Frame},
+    window::WindowId,
+};
+
+const MATCH_COLOR: Color = Color::Rgb(235, 188, 186);
+const KIND_COLOR: Color = Color::Rgb(110, 106, 134);
+
+/// Widest label before it is cut off
+const MAX_LABEL_WIDTH: usize = 50;
+/// Entries read from one directory by path completion
+const MAX_PATHS: usize = 1000;
+
+pub struct CompletionItem {
+    /// Shown in the popup and matched against the typed text
+    pub label: String,
+    /// Replaces the typed text when accepted
+    pub text: String,
+    /// Char index where the replaced text starts, it ends at the cursor
+    pub start: usize,
+    /// Name of the source, shown next to the label
+    pub kind: &'static str,
+}
+
+/// Candidates found so far, slow sources add theirs once they arrive
+pub type Items = Arc<Mutex<Vec<CompletionItem>>>;
+
+/// Position completion was asked for
+pub struct Request {
+    pub buffer_id: BufferId,
+    pub window_id: WindowId,
+    pub cursor: usize,
+    /// Start of the word before the cursor
+    pub word_start: usize,
+}
+
+/// Provides completion candidates, see [`Editor::completion_sources`]
+pub trait CompletionSource: Send + Sync {
+    /// Add candidates for the text before the cursor.
+    ///
+    /// Sources that have to wait add them from a background task and send a job
+    /// once done, so the popup is redrawn.
+    fn complete(&self, editor: &mut Editor, request: &Request, items: &Items);
+}
+
+/// Words of the open buffers
+pub struct BufferWords;
+
+/// Files and directories, once the text before the cursor contains a `/`
+pub struct Paths;
+
+/// Completion of the buffer's language server
+pub struct LanguageServer;
+
+pub fn default_sources() -> Vec<Arc<dyn CompletionSource>> {
+    vec![
+        Arc::new(BufferWords),
+        Arc::new(Paths),
+        Arc::new(LanguageServer),
+    ]
+}
+
+fn is_word_char(char: char) -> bool {
+    char.is_alphanumeric() || char == '_'
+}
+
+/// Start of the run of chars matching `belongs` that ends at `cursor`
+fn run_start(content: &Rope, cursor: usize, belongs: impl Fn(char) -> bool) -> usize {
+    let mut start = cursor;
+    while start > 0 && belongs(content.char(start - 1)) {
+        start -= 1;
+    }
+    start
+}
+
+/// Whether there is text before the cursor to complete
+pub fn has_prefix(editor: &Editor) -> bool {
+    let Some(window) = editor.windows.get_focused() else {
+        return false;
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let cursor = buf.get_cursor(window.id).range.start;
+
+    cursor > 0 && !buf.content().char(cursor - 1).is_whitespace()
+}
+
+impl CompletionSource for BufferWords {
+    fn complete(&self, editor: &mut Editor, request: &Request, items: &Items) {
+        let mut seen = HashSet::new();
+        let mut words = vec![];
+
+        let buffers = editor
+            .buffers
+            .iter()
+            .filter(|buf| buf.content().len_bytes() <= LARGE_FILE_THRESHOLD);
+        for buf in buffers {
+            let mut word = String::new();
+            let mut start = 0;
+
+            // a sentinel space ends the last word
+            for (index, char) in buf.content().chars().chain([' ']).enumerate() {
+                if is_word_char(char) {
+                    if word.is_empty() {
+                        start = index;
+                    }
+                    word.push(char);
+                    continue;
+                }
+                if word.is_empty() {
+                    continue;
+                }
+
+                // skip the word being typed
+                let typed =
+                    buf.id == request.buffer_id && (start..=index).contains(&request.cursor);
+                let word = std::mem::take(&mut word);
+                if !typed && word.chars().count() > 1 && seen.insert(word.clone()) {
+                    words.push(CompletionItem {
+                        label: word.clone(),
+                        text: word,
+                        start: request.word_start,
+                        kind: "word",
+                    });
+                }
+            }
+        }
+
+        items.lock().unwrap().extend(words);
+    }
+}
+
+impl CompletionSource for Paths {
+    fn complete(&self, editor: &mut Editor, request: &Request, items: &Items) {
+        let buf = editor.buffers.get(request.buffer_id).unwrap();
+        let content = buf.content();
+
+        let start = run_start(content, request.cursor, |char| {
+            !char.is_whitespace() && !"\"'`()[]{}<>=,;".contains(char)
+        });
+        let typed = content.slice(start..request.cursor).to_string();
+        let Some((dir_text, _)) = typed.rsplit_once('/') else {
+            return;
+        };
+        let dir_text = format!("{dir_text}/");
+        let hidden = typed
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .starts_with('.');
+
+        // relative paths start at the buffer's directory
+        let dir = match dir_text.strip_prefix("~/") {
+            Some(rest) => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(rest)),
+            None => {
+                let base = buf
+                    .file_path()
+                    .and_then(|path| path.parent())
+                    .map(PathBuf::from)
+                    .or_else(|| std::env::current_dir().ok())
+                    .unwrap_or_default();
+                Some(base.join(&dir_text))
+            }
+        };
+        let Some(Ok(entries)) = dir.map(std::fs::read_dir) else {
+            return;
+        };
+
+        let paths = entries.flatten().take(MAX_PATHS).filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !hidden {
+                return None;
+            }
+
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            let text = match is_dir {
+                true => format!("{dir_text}{name}/"),
+                false => format!("{dir_text}{name}"),
+            };
+            Some(CompletionItem {
+                label: text.clone(),
+                text,
+                start,
+                kind: if is_dir { "dir" } else { "file" },
+            })
+        });
+
+        items.lock().unwrap().extend(paths);
+    }
+}
+
+impl CompletionSource for LanguageServer {
+    fn complete(&self, editor: &mut Editor, request: &Request, items: &Items) {
+        lsp::complete(editor, request, items.clone());
+    }
+}
+
+/// Popup listing the candidates matching the text before the cursor.
+///
+/// Typing goes on as usual and narrows the list. Tab, Ctrl-n and Ctrl-p select,
+/// Enter or Ctrl-y accept, Ctrl-e cancels.
+pub struct Completion {
+    window_id: WindowId,
+    buffer_id: BufferId,
+    items: Items,
+    selected: usize,
+}
+
+struct Candidate {
+    item: usize,
+    matched: fuzzy::Match,
+}
+
+impl Completion {
+    /// Ask every source for candidates at the cursor of the focused window
+    pub fn new(editor: &mut Editor) -> Option<Self> {
+        let window = editor.windows.get_focused()?;
+        let buf = editor.buffers.get(window.buffer_id)?;
+        let cursor = buf.get_cursor(window.id).range.start;
+
+        let request = Request {
+            buffer_id: buf.id,
+            window_id: window.id,
+            cursor,
+            word_start: run_start(buf.content(), cursor, is_word_char),
+        };
+
+        let items = Items::default();
+        for source in editor.completion_sources.clone() {
+            source.complete(editor, &request, &items);
+        }
+
+        Some(Self {
+            window_id: request.window_id,
+            buffer_id: request.buffer_id,
+            items,
+            selected: 0,
+        })
+    }
+
+    /// Candidates matching the typed text, best first
+    fn candidates(&self, editor: &Editor) -> Vec<Candidate> {
+        if editor.windows.focused_node != Some(self.window_id) {
+            return vec![];
+        }
+        let Some(buf) = editor.buffers.get(self.buffer_id) else {
+            return vec![];
+        };
+        let content = buf.content();
+        let cursor = buf.get_cursor(self.window_id).range.start;
+
+        let items = self.items.lock().unwrap();
+        let mut typed: HashMap<usize, Option<String>> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut candidates = vec![];
+
+        for (index, item) in items.iter().enumerate() {
+            let query = typed.entry(item.start).or_insert_with(|| {
+                let query = match item.start <= cursor && cursor <= content.len_chars() {
+                    true => content.slice(item.start..cursor).to_string(),
+                    false => return None,
+                };
+                (!query.contains(char::is_whitespace)).then_some(query)
+            });
+            let Some(query) = query.as_deref() else {
+                continue;
+            };
+            if item.text == query || !seen.insert(item.text.as_str()) {
+                continue;
+            }
+
+            if let Some(matched) = fuzzy_match(query, &item.label) {
+                candidates.push(Candidate {
+                    item: index,
+                    matched,
+                });
+            }
+        }
+
+        candidates.sort_by(|a, b| {
+            let (a_label, b_label) = (&items[a.item].label, &items[b.item].label);
+            b.matched
+                .score
+                .cmp(&a.matched.score)
+                .then(a_label.len().cmp(&b_label.len()))
+                .then(a_label.cmp(b_label))
+        });
+        candidates
+    }
+
+    /// Replace the typed text with the item's text, leaving the cursor after it
+    fn accept(&self, editor: &mut Editor, item: usize) {
+        let items = self.items.lock().unwrap();
+        let item = &items[item];
+        let buf = editor.buffers.get_mut(self.buffer_id).unwrap();
+        let cursor = buf.get_cursor(self.window_id).range.start;
+
+        let result = buf
+            .remove(item.start..cursor)
+            .and_then(|_| buf.insert(item.start, &item.text));
+        if let Err(err) = result {
+            editor.set_error(err.to_string());
+            return;
+        }
+
+        let end = item.start + item.text.chars().count();
+        let next = next_grapheme_boundary(&buf.content().slice(..), end);
+        buf.set_cursor(self.window_id, Cursor::with_range(end, next));
+    }
+}
+
+/// Open the completion popup, selecting the last candidate when `last` is set
+pub fn open(editor: &mut Editor, last: bool) -> EventPropagation {
+    let Some(mut completion) = Completion::new(editor) else {
+        return EventPropagation::Consume(None);
+    };
+    if last {
+        completion.selected = completion.candidates(editor).len().saturating_sub(1);
+    }
+
+    EventPropagation::Consume(Some(Box::new(move |components, _| {
+        components.push(Box::new(completion));
+    })))
+}
+
+impl Component for Completion {
+    fn handle_events(&mut self, event: &Event, context: &mut Context) -> EventPropagation {
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Mouse(_) | Event::Paste(_) => {
+                return EventPropagation::Ignore(Some(popup::close()))
+            }
+            _ => return EventPropagation::Ignore(None),
+        };
+        if context.editor.mode != Mode::Insert {
+            return EventPropagation::Ignore(Some(popup::close()));
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let candidates = self.candidates(context.editor);
+
+        let selecting = matches!(
+            (key.code, ctrl),
+            (KeyCode::Char('n' | 'p' | 'y' | 'e'), true)
+                | (
+                    KeyCode::Tab | KeyCode::BackTab | KeyCode::Up | KeyCode::Down,
+                    _
+                )
+                | (KeyCode::Enter | KeyCode::Esc, _)
+        );
+        if candidates.is_empty() {
+            // results of slow sources may still arrive while typing
+            return match selecting {
+                true => EventPropagation::Ignore(Some(popup::close())),
+                false => EventPropagation::Ignore(None),
+            };
+        }
+
+        let len = candidates.len();
+        self.selected = self.selected.min(len - 1);
+
+        match (key.code, ctrl) {
+            (KeyCode::Char('n'), true) | (KeyCode::Tab | KeyCode::Down, _) => {
+                self.selected = (self.selected + 1) % len;
+            }
+            (KeyCode::Char('p'), true) | (KeyCode::BackTab | KeyCode::Up, _) => {
+                self.selected = (self.selected + len - 1) % len;
+            }
+            (KeyCode::Char('y'), true) | (KeyCode::Enter, _) => {
+                self.accept(context.editor, candidates[self.selected].item);
+                return EventPropagation::Consume(Some(popup::close()));
+            }
+            (KeyCode::Char('e'), true) => return EventPropagation::Consume(Some(popup::close())),
+            (KeyCode::Esc, _) => return EventPropagation::Ignore(Some(popup::close())),
+            // typing narrows the list
+            _ => {
+                self.selected = 0;
+                return EventPropagation::Ignore(None);
+            }
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    fn render(&self, f: &mut Frame<'_>, area: Rect, context: &mut Context) {
+        let candidates = self.candidates(context.editor);
+        if candidates.is_empty() {
+            return;
+        }
+
+        let items = self.items.lock().unwrap();
+        let label_width = candidates
+            .iter()
+            .map(|candidate| items[candidate.item].label.width())
+            .max()
+            .unwrap_or(0)
+            .min(MAX_LABEL_WIDTH);
+        let kind_width = candidates
+            .iter()
+            .map(|candidate| items[candidate.item].kind.width())
+            .max()
+            .unwrap_or(0);
+
+        let width = (label_width + kind_width + 3) as u16 + 2;
+        let height = candidates.len().min(MENU_ITEMS) as u16 + 2;
+        let menu_area = popup::area_near(&popup::anchor(context), width, height, area);
+
+        let visible = menu_area.height.saturating_sub(2).max(1) as usize;
+        let selected = self.selected.min(candidates.len() - 1);
+        let first = (selected + 1).saturating_sub(visible);
+
+        let lines: Vec<Line> = candidates
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .map(|(index, candidate)| {
+                let item = &items[candidate.item];
+                let mut spans: Vec<Span> = item
+                    .label
+                    .chars()
+                    .enumerate()
+                    .map(|(char_index, char)| {
+                        let style = match candidate.matched.indices.contains(&char_index) {
+                            true => Style::default()
+                                .fg(MATCH_COLOR)
+                                .add_modifier(Modifier::BOLD),
+                            false => Style::default(),
+                        };
+                        Span::styled(char.to_string(), style)
+                    })
+                    .collect();
+
+                let padding = label_width.saturating_sub(item.label.width()) + 2;
+                spans.push(Span::raw(" ".repeat(padding)));
+                spans.push(Span::styled(item.kind, Style::default().fg(KIND_COLOR)));
+
+                let line = Line::from(spans);
+                match index == selected {
+                    true => line.style(Style::default().bg(SELECTED)),
+                    false => line,
+                }
+            })
+            .collect();
+
+        let paragraph = Paragraph::new(lines)
+            .style(Style::default().bg(BACKGROUND).fg(Color::White))
+            .block(Block::default().borders(Borders::ALL));
+
+        f.render_widget(Clear, menu_area);
+        f.render_widget(paragraph, menu_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        mode::Mode,
+        test_harness::{file, TestApp},
+    };
+
+    #[tokio::test]
+    async fn completes_words_from_open_buffers() {
+        let other = file("alphabet\n");
+        let file = file("\nalpha beta\n");
+        let mut app = TestApp::new(&[
+            "--clean",
+            file.path().to_str().unwrap(),
+            other.path().to_str().unwrap(),
+        ])
+        .await;
+
+        // shortest first, Ctrl-n moves on to the word of the other buffer
+        app.keys("ial<C-n><C-n><CR>").await;
+        assert_eq!(app.text(), "alphabet\nalpha beta\n");
+        assert_eq!(app.cursor(), (0, 8));
+        assert_eq!(app.app.editor.mode, Mode::Insert);
+
+        // fuzzy matches narrow down while typing
+        app.keys(" ap<Tab>t<CR>").await;
+        assert_eq!(app.text(), "alphabet alphabet\nalpha beta\n");
+
+        // Ctrl-e keeps what was typed
+        app.keys(" be<Tab><C-e><Esc>").await;
+        assert_eq!(app.text(), "alphabet alphabet be\nalpha beta\n");
+        assert_eq!(app.app.editor.mode, Mode::Normal);
+    }
+
+    #[tokio::test]
+    async fn completes_paths_next_to_the_buffer() {
+        let (_dir, mut app) = TestApp::with_files(&[
+            ("notes.md", "\n"),
+            ("first.txt", ""),
+            ("folder/inside.txt", ""),
+        ])
+        .await;
+
+        app.keys("isee ./fo<Tab><CR>").await;
+        assert_eq!(app.text(), "see ./folder/\n");
+
+        // Tab without text before the cursor inserts itself
+        app.keys("<CR><Tab>./fi<Tab><CR>").await;
+        assert_eq!(app.text(), "see ./folder/\n\t./first.txt\n");
+    }
+}
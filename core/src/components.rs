@@ -90,7 +90,7 @@ pub struct Context<'a> {
 
 /// Event callback to be called when event is either done propagating
 /// or has passed through all components
-type EventCallback = Box<dyn FnOnce(&mut Components, &mut Context)>;
+pub type EventCallback = Box<dyn FnOnce(&mut Components, &mut Context)>;
 
 pub enum EventPropagation {
     Ignore(Option<EventCallback>),
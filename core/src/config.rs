@@ -1,5 +1,132 @@
 #![allow(unused_imports)]
 
+use std::{collections::HashMap, path::PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "config.json5";
+
+/// User configuration read from `config.json5` in the config directory
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Minimum lines and columns kept between the cursor and the window edge
+    pub scrolloff: usize,
+    /// Capture the mouse to place the cursor, select and scroll
+    pub mouse: bool,
+    /// Run the language's formatter before writing
+    pub format_on_save: bool,
+    /// Formatter per language name, e.g. `rust`
+    pub formatters: HashMap<String, Formatter>,
+    /// Language server per language name, e.g. `rust`
+    pub language_servers: HashMap<String, LanguageServer>,
+    /// Brackets and quotes closed as they are typed in Insert mode, each as the opener and the
+    /// closer, e.g. `()`
+    pub auto_pairs: Vec<String>,
+    /// Spaces added per indentation level, a tab when the line above is indented with tabs
+    pub indent_width: usize,
+    /// Shell command run by `:make`
+    pub make_command: String,
+    /// Regexes reading quickfix entries from `:make` output and error files, with the
+    /// named groups `file`, `line` and optionally `col` and `message`
+    pub error_patterns: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scrolloff: 12,
+            mouse: false,
+            format_on_save: false,
+            formatters: HashMap::from([(
+                "rust".to_string(),
+                Formatter {
+                    command: "rustfmt".to_string(),
+                    args: vec!["--edition".to_string(), "2021".to_string()],
+                    timeout: default_formatter_timeout(),
+                },
+            )]),
+            language_servers: HashMap::from([(
+                "rust".to_string(),
+                LanguageServer {
+                    command: "rust-analyzer".to_string(),
+                    args: vec![],
+                    roots: vec!["Cargo.toml".to_string()],
+                },
+            )]),
+            auto_pairs: ["()", "[]", "{}", "\"\"", "''", "``"]
+                .map(String::from)
+                .to_vec(),
+            indent_width: 4,
+            make_command: "cargo build --message-format=short".to_string(),
+            error_patterns: vec![
+                r"^(?P<file>[^:\s][^:]*):(?P<line>\d+):(?P<col>\d+): (?P<message>.*)$".to_string(),
+                r"^(?P<file>[^:\s][^:]*):(?P<line>\d+): (?P<message>.*)$".to_string(),
+            ],
+        }
+    }
+}
+
+/// Command reading the text on stdin and printing it formatted on stdout
+#[derive(Clone, Debug, Deserialize)]
+pub struct Formatter {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Milliseconds to wait before giving up
+    #[serde(default = "default_formatter_timeout")]
+    pub timeout: u64,
+}
+
+fn default_formatter_timeout() -> u64 {
+    5000
+}
+
+/// Command speaking the language server protocol on stdin and stdout
+#[derive(Clone, Debug, Deserialize)]
+pub struct LanguageServer {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Files marking the workspace root, searched upwards from the opened file
+    #[serde(default)]
+    pub roots: Vec<String>,
+}
+
+impl Config {
+    /// Load the user config, or the defaults when `clean` is set or no file exists
+    pub fn load(clean: bool) -> Result<Self> {
+        if clean {
+            return Ok(Self::default());
+        }
+
+        let Some(path) = config_dir().map(|dir| dir.join(CONFIG_FILE)) else {
+            return Ok(Self::default());
+        };
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        json5::from_str(&content).wrap_err_with(|| format!("Invalid config {}", path.display()))
+    }
+}
+
+/// `$RUST_TEXT_EDITOR_CONFIG`, falling back to the platform config directory
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("RUST_TEXT_EDITOR_CONFIG") {
+        return Some(PathBuf::from(dir));
+    }
+
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(base.join(env!("CARGO_PKG_NAME")))
+}
+
 // use std::{collections::HashMap, fmt, path::PathBuf};
 //
 // use color_eyre::eyre::Result;
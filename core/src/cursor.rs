@@ -1,6 +1,6 @@
 use std::ops::Range;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Cursor {
     pub range: Range<usize>,
 }
@@ -0,0 +1,294 @@
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use ropey::Rope;
+use similar::{capture_diff_slices_deadline, Algorithm, DiffTag};
+
+/// Time after which diffing settles for a less minimal result
+const DIFF_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Replace a char range of the old text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// Edits turning `old` into `new`, sorted by position.
+///
+/// Lines are compared first and changed lines refined per character,
+/// so text between the edits keeps its place.
+pub fn edits(old: &str, new: &str) -> Vec<Edit> {
+    let deadline = Some(Instant::now() + DIFF_DEADLINE);
+
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    // char index where each old line starts
+    let mut line_starts = Vec::with_capacity(old_lines.len() + 1);
+    let mut start = 0;
+    for line in &old_lines {
+        line_starts.push(start);
+        start += line.chars().count();
+    }
+    line_starts.push(start);
+
+    let mut edits = vec![];
+
+    for op in capture_diff_slices_deadline(Algorithm::Myers, &old_lines, &new_lines, deadline) {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+
+        let offset = line_starts[old_range.start];
+        let old_chars: Vec<char> = old_lines[old_range].concat().chars().collect();
+        let new_chars: Vec<char> = new_lines[new_range].concat().chars().collect();
+
+        for op in capture_diff_slices_deadline(Algorithm::Myers, &old_chars, &new_chars, deadline) {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                continue;
+            }
+
+            edits.push(Edit {
+                range: offset + old_range.start..offset + old_range.end,
+                text: new_chars[new_range].iter().collect(),
+            });
+        }
+    }
+
+    edits
+}
+
+/// Lines of the content with their line breaks, without the empty line after the last one
+pub fn lines(content: &Rope) -> Vec<String> {
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    // a rope always has a last, possibly empty, line
+    if lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Hunk replacing lines of one text, side 0, with lines of another, side 1
+pub trait LineHunk {
+    fn from_lines(lines: [Range<usize>; 2]) -> Self;
+    fn lines(&self) -> [Range<usize>; 2];
+    fn lines_mut(&mut self) -> [&mut Range<usize>; 2];
+}
+
+/// Line ranges of `old` replaced by lines of `new`, a deletion next to an insertion is one hunk
+pub fn line_hunks<H: LineHunk>(old: &[String], new: &[String]) -> Vec<H> {
+    let deadline = Some(Instant::now() + DIFF_DEADLINE);
+    let mut hunks: Vec<H> = vec![];
+
+    for op in capture_diff_slices_deadline(Algorithm::Myers, old, new, deadline) {
+        let (tag, old, new) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some(last) if last.lines()[0].end == old.start && last.lines()[1].end == new.start => {
+                let [last_old, last_new] = last.lines_mut();
+                last_old.end = old.end;
+                last_new.end = new.end;
+            }
+            _ => hunks.push(H::from_lines([old, new])),
+        }
+    }
+
+    hunks
+}
+
+/// Lines that changed between two versions of a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineChange {
+    pub start: usize,
+    /// End of the changed lines in the old version
+    pub old_end: usize,
+    /// End of the changed lines in the new version
+    pub new_end: usize,
+}
+
+/// Lines of `content` differing from `lines`, as returned by [`lines`] for an older
+/// version, found by comparing from both ends. `None` when nothing changed.
+pub fn changed_lines(lines: &[String], content: &Rope) -> Option<LineChange> {
+    let last = content.len_lines() - 1;
+    let len = last + usize::from(content.line(last).len_chars() > 0);
+
+    let common = lines.len().min(len);
+    let start = (0..common)
+        .find(|&line| content.line(line) != lines[line].as_str())
+        .unwrap_or(common);
+    let suffix = (0..common - start)
+        .find(|&back| content.line(len - 1 - back) != lines[lines.len() - 1 - back].as_str())
+        .unwrap_or(common - start);
+
+    let change = LineChange {
+        start,
+        old_end: lines.len() - suffix,
+        new_end: len - suffix,
+    };
+    (change.start < change.old_end || change.start < change.new_end).then_some(change)
+}
+
+/// Follow a change of the text on `side` of the hunks, diffing `base`, the text on the other
+/// side, only against the changed lines and the hunks touching them.
+///
+/// `lines` are updated to the `content`. Returns the range of `hunks` that was diffed again,
+/// `None` when nothing changed.
+pub fn rediff<H: LineHunk>(
+    base: &[String],
+    lines: &mut Vec<String>,
+    content: &Rope,
+    hunks: &mut Vec<H>,
+    side: usize,
+) -> Option<Range<usize>> {
+    let change = changed_lines(lines, content)?;
+    lines.splice(
+        change.start..change.old_end,
+        (change.start..change.new_end).map(|line| content.line(line).to_string()),
+    );
+    let delta = change.new_end as isize - change.old_end as isize;
+
+    // hunks next to the change are diffed again too, so neighbouring changes merge
+    let first = hunks
+        .iter()
+        .position(|hunk| hunk.lines()[side].end >= change.start)
+        .unwrap_or(hunks.len());
+    let last = first
+        + hunks[first..]
+            .iter()
+            .take_while(|hunk| hunk.lines()[side].start <= change.old_end)
+            .count();
+
+    // the span is between equal lines, where the sides are a fixed offset apart
+    let offset = |hunks: &[H]| {
+        hunks.last().map_or(0, |hunk| {
+            let lines = hunk.lines();
+            lines[1 - side].end as isize - lines[side].end as isize
+        })
+    };
+    let start = hunks[first..last].first().map_or(change.start, |hunk| {
+        hunk.lines()[side].start.min(change.start)
+    });
+    let end = hunks[first..last].last().map_or(change.old_end, |hunk| {
+        hunk.lines()[side].end.max(change.old_end)
+    });
+    let base_start = (start as isize + offset(&hunks[..first])) as usize;
+    let base_end = (end as isize + offset(&hunks[..last])) as usize;
+    let new_end = (end as isize + delta) as usize;
+
+    let mut span = [&base[base_start..base_end], &lines[start..new_end]];
+    let mut offsets = [base_start, start];
+    if side == 0 {
+        span.reverse();
+        offsets.reverse();
+    }
+    let mut replaced: Vec<H> = line_hunks(span[0], span[1]);
+    for hunk in &mut replaced {
+        for (range, offset) in hunk.lines_mut().into_iter().zip(offsets) {
+            *range = range.start + offset..range.end + offset;
+        }
+    }
+
+    for hunk in &mut hunks[last..] {
+        let range = &mut hunk.lines_mut()[side];
+        **range = (range.start as isize + delta) as usize..(range.end as isize + delta) as usize;
+    }
+    let count = replaced.len();
+    hunks.splice(first..last, replaced);
+
+    Some(first..first + count)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn apply(old: &str, edits: &[Edit]) -> String {
+        let mut chars: Vec<char> = old.chars().collect();
+        for edit in edits.iter().rev() {
+            chars.splice(edit.range.clone(), edit.text.chars());
+        }
+        chars.into_iter().collect()
+    }
+
+    impl LineHunk for [Range<usize>; 2] {
+        fn from_lines(lines: [Range<usize>; 2]) -> Self {
+            lines
+        }
+
+        fn lines(&self) -> [Range<usize>; 2] {
+            self.clone()
+        }
+
+        fn lines_mut(&mut self) -> [&mut Range<usize>; 2] {
+            let [old, new] = self;
+            [old, new]
+        }
+    }
+
+    #[test]
+    fn rediffs_only_the_changed_lines() {
+        let base = lines(&Rope::from("a\nb\nc\nd\ne\nf\n"));
+        let mut current = lines(&Rope::from("a\nx\nc\nd\ne\nf\n"));
+        let mut hunks: Vec<[Range<usize>; 2]> = line_hunks(&base, &current);
+        assert_eq!(hunks, vec![[1..2, 1..2]]);
+
+        let changes = [
+            // a line added far from the hunk shifts nothing before it
+            "a\nx\nc\nd\ne\nnew\nf\n",
+            // lines added above move the hunks below
+            "top\n\na\nx\nc\nd\ne\nnew\nf\n",
+            // a change next to a hunk merges with it
+            "top\n\na\nx\ny\nd\ne\nnew\nf\n",
+            // and undoing everything leaves no hunks
+            "a\nb\nc\nd\ne\nf\n",
+        ];
+        for text in changes {
+            let content = Rope::from(text);
+            rediff(&base, &mut current, &content, &mut hunks, 1);
+            assert_eq!(current, lines(&content));
+            assert_eq!(
+                hunks,
+                line_hunks::<[Range<usize>; 2]>(&base, &current),
+                "{text}"
+            );
+        }
+
+        assert_eq!(
+            rediff(
+                &base,
+                &mut current,
+                &Rope::from("a\nb\nc\nd\ne\nf\n"),
+                &mut hunks,
+                1
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn edits_only_changed_characters() {
+        let old = "fn  main(){\nlet x=1;\n}\n";
+        let new = "fn main() {\n    let x = 1;\n}\n";
+
+        let edits = edits(old, new);
+
+        assert_eq!(apply(old, &edits), new);
+        assert_eq!(
+            edits[0],
+            Edit {
+                range: 3..4,
+                text: String::new()
+            }
+        );
+        assert!(edits.iter().all(|edit| edit.range.end <= 20));
+    }
+}
@@ -0,0 +1,464 @@
+//! Two windows compared line by line, opened by `editor -d` and `:diffsplit`
+
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, Result};
+use similar::{capture_diff_slices_deadline, Algorithm, DiffTag};
+
+use crate::{
+    buffer::{BufferId, Buffers},
+    diff::{line_hunks, lines, rediff, LineHunk},
+    editor::Editor,
+    window::{WindowId, Windows},
+};
+
+/// Time after which diffing settles for a less minimal result
+const DIFF_DEADLINE: Duration = Duration::from_millis(200);
+
+/// Line of each side shown on one screen row, `None` for the filler of a missing line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Row {
+    pub lines: [Option<usize>; 2],
+    /// Part of a hunk
+    pub changed: bool,
+    /// Chars differing from the line on the other side, when both sides have one
+    pub inline: [Vec<Range<usize>>; 2],
+}
+
+/// Lines of each side replaced by the lines of the other side
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    lines: [Range<usize>; 2],
+    /// Inline changes of the lines both sides have, in pairs
+    inline: Vec<[Vec<Range<usize>>; 2]>,
+}
+
+impl LineHunk for Hunk {
+    fn from_lines(lines: [Range<usize>; 2]) -> Self {
+        Self {
+            lines,
+            inline: vec![],
+        }
+    }
+
+    fn lines(&self) -> [Range<usize>; 2] {
+        self.lines.clone()
+    }
+
+    fn lines_mut(&mut self) -> [&mut Range<usize>; 2] {
+        let [left, right] = &mut self.lines;
+        [left, right]
+    }
+}
+
+impl Hunk {
+    /// Whether the hunk is at `line` of `side`, missing lines belong to the line above
+    fn contains(&self, side: usize, line: usize) -> bool {
+        let range = &self.lines[side];
+        range.contains(&line) || (range.is_empty() && range.start.saturating_sub(1) == line)
+    }
+}
+
+pub struct DiffMode {
+    pub windows: [WindowId; 2],
+    buffers: [BufferId; 2],
+    /// Lines of each buffer as of `versions`
+    lines: [Vec<String>; 2],
+    versions: [Option<usize>; 2],
+    hunks: Vec<Hunk>,
+    pub rows: Vec<Row>,
+    /// Row of each line of each side
+    line_rows: [Vec<usize>; 2],
+    /// First row shown in both windows
+    pub top_row: usize,
+}
+
+impl DiffMode {
+    fn new(windows: [WindowId; 2], buffers: [BufferId; 2]) -> Self {
+        Self {
+            windows,
+            buffers,
+            lines: [vec![], vec![]],
+            versions: [None, None],
+            hunks: vec![],
+            rows: vec![],
+            line_rows: [vec![], vec![]],
+            top_row: 0,
+        }
+    }
+
+    /// Side shown by a window, 0 on the left
+    pub fn side(&self, window_id: WindowId) -> Option<usize> {
+        self.windows.iter().position(|&id| id == window_id)
+    }
+
+    /// Reread the buffers changed since the last call and diff the changed lines again
+    fn refresh(&mut self, buffers: &Buffers) {
+        if self.versions.contains(&None) {
+            // the first time the sides are diffed as a whole
+            for side in 0..2 {
+                if let Some(buf) = buffers.get(self.buffers[side]) {
+                    self.versions[side] = Some(buf.version());
+                    self.lines[side] = lines(buf.content());
+                }
+            }
+            self.hunks = hunks(&self.lines);
+        } else {
+            let mut changed = false;
+            for side in 0..2 {
+                let Some(buf) = buffers.get(self.buffers[side]) else {
+                    continue;
+                };
+                if self.versions[side].replace(buf.version()) == Some(buf.version()) {
+                    continue;
+                }
+
+                let [left, right] = &mut self.lines;
+                let (base, lines) = match side {
+                    0 => (&*right, left),
+                    _ => (&*left, right),
+                };
+                if let Some(range) = rediff(base, lines, buf.content(), &mut self.hunks, side) {
+                    for hunk in &mut self.hunks[range] {
+                        hunk.inline = inline(&self.lines, &hunk.lines);
+                    }
+                    changed = true;
+                }
+            }
+            if !changed {
+                return;
+            }
+        }
+
+        self.rows = rows(&self.lines, &self.hunks);
+        for side in 0..2 {
+            self.line_rows[side] = vec![0; self.lines[side].len()];
+            for (index, row) in self.rows.iter().enumerate() {
+                if let Some(line) = row.lines[side] {
+                    self.line_rows[side][line] = index;
+                }
+            }
+        }
+    }
+
+    /// Row showing a line, lines after the last one continue below the rows
+    fn row_of(&self, side: usize, line: usize) -> usize {
+        match self.line_rows[side].get(line) {
+            Some(&row) => row,
+            None => self.rows.len() + line - self.lines[side].len(),
+        }
+    }
+
+    /// Line of a side shown on a row, `None` for fillers
+    pub fn line_at_row(&self, side: usize, row: usize) -> Option<usize> {
+        match self.rows.get(row) {
+            Some(row) => row.lines[side],
+            None => Some(self.lines[side].len() + row - self.rows.len()),
+        }
+    }
+
+    /// First line of a side shown from a row on
+    fn first_line_from(&self, side: usize, row: usize) -> usize {
+        (row..)
+            .find_map(|row| self.line_at_row(side, row))
+            .unwrap_or_default()
+    }
+
+    /// Screen row of a line in a window, counted from the top of the window
+    pub fn screen_row(&self, window_id: WindowId, line: usize) -> Option<usize> {
+        let side = self.side(window_id)?;
+        Some(self.row_of(side, line).saturating_sub(self.top_row))
+    }
+
+    /// Follow the scrolling of the focused window in the other one
+    fn sync(&mut self, windows: &mut Windows, buffers: &Buffers) {
+        if let Some(side) = windows.focused_node.and_then(|id| self.side(id)) {
+            let window = windows.get(self.windows[side]).unwrap();
+            let buf = buffers.get(self.buffers[side]).unwrap();
+
+            // the window scrolled by itself, e.g. to follow the cursor
+            if window.offset.vertical != self.first_line_from(side, self.top_row) {
+                self.top_row = self.row_of(side, window.offset.vertical);
+            }
+
+            // fillers above the cursor can push it below the window
+            let height = (window.text_area().height as usize).max(1);
+            let line = buf
+                .content()
+                .char_to_line(buf.get_cursor(window.id).range.start);
+            let cursor_row = self.row_of(side, line);
+            if cursor_row < self.top_row {
+                self.top_row = cursor_row;
+            } else if cursor_row >= self.top_row + height {
+                self.top_row = cursor_row + 1 - height;
+            }
+
+            let horizontal = window.offset.horizontal;
+            let other = windows.get_mut(self.windows[1 - side]).unwrap();
+            other.offset.horizontal = horizontal;
+        }
+
+        for side in 0..2 {
+            let last_line = buffers
+                .get(self.buffers[side])
+                .map_or(0, |buf| buf.content().len_lines().saturating_sub(1));
+            let line = self.first_line_from(side, self.top_row).min(last_line);
+            if let Some(window) = windows.get_mut(self.windows[side]) {
+                window.offset.vertical = line;
+            }
+        }
+    }
+}
+
+/// Line ranges differing between the sides, neighbouring changes merged
+fn hunks(lines: &[Vec<String>; 2]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = line_hunks(&lines[0], &lines[1]);
+    for hunk in &mut hunks {
+        hunk.inline = inline(lines, &hunk.lines);
+    }
+    hunks
+}
+
+/// Inline changes of the lines a hunk pairs up
+fn inline(
+    lines: &[Vec<String>; 2],
+    [left, right]: &[Range<usize>; 2],
+) -> Vec<[Vec<Range<usize>>; 2]> {
+    left.clone()
+        .zip(right.clone())
+        .map(|(left, right)| inline_changes(&lines[0][left], &lines[1][right]))
+        .collect()
+}
+
+/// Rows showing both sides aligned, the shorter side of a hunk is padded with fillers
+fn rows(lines: &[Vec<String>; 2], hunks: &[Hunk]) -> Vec<Row> {
+    let mut rows = vec![];
+    let mut next = [0, 0];
+
+    let equal = |rows: &mut Vec<Row>, next: [usize; 2], until: usize| {
+        for left in next[0]..until {
+            rows.push(Row {
+                lines: [Some(left), Some(next[1] + left - next[0])],
+                ..Default::default()
+            });
+        }
+    };
+
+    for hunk in hunks {
+        equal(&mut rows, next, hunk.lines[0].start);
+
+        let [left, right] = &hunk.lines;
+        for index in 0..left.len().max(right.len()) {
+            let line =
+                |range: &Range<usize>| Some(range.start + index).filter(|_| index < range.len());
+            rows.push(Row {
+                lines: [line(left), line(right)],
+                changed: true,
+                inline: hunk.inline.get(index).cloned().unwrap_or_default(),
+            });
+        }
+        next = [left.end, right.end];
+    }
+    equal(&mut rows, next, lines[0].len());
+
+    rows
+}
+
+/// Char ranges of two lines differing from each other, line breaks left out
+fn inline_changes(left: &str, right: &str) -> [Vec<Range<usize>>; 2] {
+    let left: Vec<char> = left.trim_end_matches(['\n', '\r']).chars().collect();
+    let right: Vec<char> = right.trim_end_matches(['\n', '\r']).chars().collect();
+
+    let deadline = Some(Instant::now() + DIFF_DEADLINE);
+    let mut changes: [Vec<Range<usize>>; 2] = Default::default();
+    for op in capture_diff_slices_deadline(Algorithm::Myers, &left, &right, deadline) {
+        let (tag, left, right) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        for (side, range) in [left, right].into_iter().enumerate() {
+            if !range.is_empty() {
+                changes[side].push(range);
+            }
+        }
+    }
+
+    changes
+}
+
+/// Compare the buffers of two windows until one of them closes
+pub fn start(editor: &mut Editor, windows: [WindowId; 2]) -> Result<()> {
+    let mut buffers = vec![];
+    for window_id in windows {
+        match editor.windows.get(window_id) {
+            Some(window) => buffers.push(window.buffer_id),
+            None => bail!("No window to compare"),
+        }
+    }
+    if windows[0] == windows[1] {
+        bail!("Diff needs two windows");
+    }
+
+    editor.diff_mode = Some(DiffMode::new(windows, [buffers[0], buffers[1]]));
+    update(editor);
+    Ok(())
+}
+
+/// Diff the buffers again once they changed and align the scrolling of the windows
+pub fn update(editor: &mut Editor) {
+    let Some(mut mode) = editor.diff_mode.take() else {
+        return;
+    };
+
+    // ends once a window closes or shows another buffer
+    for side in 0..2 {
+        match editor.windows.get(mode.windows[side]) {
+            Some(window) if window.buffer_id == mode.buffers[side] => {}
+            _ => return,
+        }
+    }
+
+    mode.refresh(&editor.buffers);
+    mode.sync(&mut editor.windows, &editor.buffers);
+    editor.diff_mode = Some(mode);
+}
+
+/// Replace the hunk at the cursor in one side with the lines of the other, `do` and `dp`
+pub fn transfer(editor: &mut Editor, put: bool) -> Result<()> {
+    let Some(mode) = editor.diff_mode.as_ref() else {
+        bail!("Not in diff mode");
+    };
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let Some(side) = mode.side(window.id) else {
+        bail!("Window is not part of the diff");
+    };
+
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let line = buf
+        .content()
+        .char_to_line(buf.get_cursor(window.id).range.start);
+    let Some(hunk) = mode.hunks.iter().find(|hunk| hunk.contains(side, line)) else {
+        bail!("No difference at the cursor");
+    };
+
+    let (from, to) = match put {
+        true => (side, 1 - side),
+        false => (1 - side, side),
+    };
+    let text = mode.lines[from][hunk.lines[from].clone()].concat();
+    let target = hunk.lines[to].clone();
+
+    let buf = editor.buffers.get_mut(mode.buffers[to]).unwrap();
+    if buf.readonly {
+        bail!("Cannot modify, buffer is read-only");
+    }
+    let content = buf.content();
+    let start = content.line_to_char(target.start.min(content.len_lines()));
+    let end = content.line_to_char(target.end.min(content.len_lines()));
+
+    buf.commit();
+    let result = buf
+        .remove(start..end)
+        .and_then(|_| buf.insert(start, &text));
+    buf.commit();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    fn split(text: &str) -> Vec<String> {
+        text.split_inclusive('\n').map(String::from).collect()
+    }
+
+    #[test]
+    fn aligns_lines_with_fillers() {
+        let lines = [split("a\nbead\nc\n"), split("a\nboat\nc\nd\n")];
+        let rows = rows(&lines, &hunks(&lines));
+
+        assert_eq!(
+            rows.iter().map(|row| row.lines).collect::<Vec<_>>(),
+            vec![
+                [Some(0), Some(0)],
+                [Some(1), Some(1)],
+                [Some(2), Some(2)],
+                [None, Some(3)],
+            ]
+        );
+        assert_eq!(rows[1].inline, [vec![1..2, 3..4], vec![1..2, 3..4]]);
+        assert!(rows[3].changed && !rows[2].changed);
+    }
+
+    #[tokio::test]
+    async fn scrolls_together_and_moves_hunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let left = dir.path().join("left.txt");
+        let right = dir.path().join("right.txt");
+        let mut lines: Vec<String> = (0..100).map(|n| format!("line {n}\n")).collect();
+        fs::write(&left, lines.concat()).unwrap();
+        lines[1] = "changed\n".to_string();
+        lines.insert(40, "added\n".to_string());
+        fs::write(&right, lines.concat()).unwrap();
+
+        let mut app = TestApp::new(&[
+            "--clean",
+            "-d",
+            left.to_str().unwrap(),
+            right.to_str().unwrap(),
+        ])
+        .await;
+        assert!(app.screen_line(1).starts_with("line 1"));
+        assert!(app.screen_line(1)[40..].starts_with("changed"));
+
+        // the filler keeps the following lines side by side
+        app.keys(&"j".repeat(45)).await;
+        let y = (0..24)
+            .find(|&y| app.screen_line(y)[40..].starts_with("added"))
+            .unwrap();
+        assert!(app.screen_line(y).starts_with("---"));
+        assert!(app.screen_line(y + 1).starts_with("line 40"));
+        assert!(app.screen_line(y + 1)[40..].starts_with("line 40"));
+
+        app.keys(&"j".repeat(35)).await;
+        let offsets: Vec<usize> = app
+            .app
+            .editor
+            .windows
+            .iter()
+            .map(|window| window.offset.vertical)
+            .collect();
+        assert_eq!(offsets[0] + 1, offsets[1]);
+
+        // obtain the added line, put the changed one
+        app.keys(&"k".repeat(41)).await;
+        app.keys("do").await;
+        assert_eq!(app.text().lines().nth(40), Some("added"));
+        // only the changed lines were diffed again
+        let mode = app.app.editor.diff_mode.as_ref().unwrap();
+        assert_eq!(mode.hunks, hunks(&mode.lines));
+        app.keys(&"k".repeat(38)).await;
+        app.keys("dp").await;
+        let right_text = app
+            .app
+            .editor
+            .buffers
+            .iter()
+            .nth(1)
+            .unwrap()
+            .content()
+            .to_string();
+        assert_eq!(right_text, app.text());
+        assert!(!(0..24).any(|y| app.screen_line(y).starts_with("---")));
+    }
+}
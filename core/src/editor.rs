@@ -1,7 +1,15 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufReader, Read},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
 
-use color_eyre::eyre::Result;
-use crossterm::event::{KeyCode, KeyEvent};
+use color_eyre::eyre::{bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use lsp_types::{Diagnostic, DiagnosticSeverity};
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style, Stylize},
@@ -10,33 +18,116 @@ use ratatui::{
 };
 use ropey::{Rope, RopeSlice};
 use syntax::HighlightInfo;
-use text::width;
+use text::{next_grapheme_boundary, width};
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
-    buffer::{BufferId, Buffers},
+    autopair, brackets,
+    buffer::{BufferId, Buffers, Loading, LARGE_FILE_THRESHOLD},
+    commands::LineRange,
+    comment,
+    completion::{self, CompletionSource},
     components::{self, Component, Context, EventPropagation, Position},
+    config::Config,
     cursor::Cursor,
+    diff_mode::{self, DiffMode},
+    fold::{self, Folds},
+    git::{self, Blame, Hunk, HunkKind},
+    indent,
+    jobs::{JobCallback, Jobs},
+    lsp,
     mode::Mode,
     movements,
+    picker::FilePicker,
     prompt::CommandPrompt,
+    quickfix::Quickfix,
+    shell,
     terminal::Event,
+    textobject,
     window::{Offset, Windows},
 };
 
+/// Size of each chunk read while loading a large file
+const LOAD_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Lines scrolled by one turn of the mouse wheel
+const MOUSE_SCROLL_LINES: isize = 3;
+
+const SELECTION_COLOR: Color = Color::Rgb(64, 61, 82);
+
+/// Background of the bracket matching the one at the cursor
+const MATCH_COLOR: Color = Color::Rgb(86, 82, 120);
+
+/// Background of the summary line of a closed fold
+const FOLD_COLOR: Color = Color::Rgb(42, 40, 58);
+
+/// Backgrounds of diff mode: lines only on one side, fillers on the other, changed lines
+/// and the changed part of them
+const DIFF_ADD_COLOR: Color = Color::Rgb(30, 60, 40);
+const DIFF_DELETE_COLOR: Color = Color::Rgb(60, 30, 36);
+const DIFF_CHANGE_COLOR: Color = Color::Rgb(40, 42, 72);
+const DIFF_TEXT_COLOR: Color = Color::Rgb(82, 72, 136);
+
+/// Where [`Editor::open`] shows the opened buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAction {
+    /// Show it in the focused window
+    Replace,
+    /// Show it in a new window stacked with the others
+    HorizontalSplit,
+    /// Show it in a new window side by side with the others
+    VerticalSplit,
+    /// Only load the buffer
+    Load,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Error,
+}
+
+/// Message shown on the last line until the next key press
+pub struct StatusMessage {
+    pub text: String,
+    pub severity: Severity,
+}
+
 pub struct Editor {
     pub mode: Mode,
     pub buffers: Buffers,
     pub windows: Windows,
+    pub jobs: Jobs,
+    pub config: Config,
+    pub status: Option<StatusMessage>,
+    /// Scratch buffer showing the output of the last `:!cmd`
+    pub shell_output: Option<BufferId>,
+    pub lsp: lsp::Registry,
+    /// Asked for candidates when Insert mode completion opens
+    pub completion_sources: Vec<Arc<dyn CompletionSource>>,
+    pub quickfix: Quickfix,
+    /// Windows compared by `editor -d` or `:diffsplit`
+    pub diff_mode: Option<DiffMode>,
     should_quit: bool,
+    should_suspend: bool,
 }
 
 impl Editor {
-    pub fn new(area: Rect) -> Self {
+    pub fn new(area: Rect, config: Config) -> Self {
         Self {
             mode: Mode::Normal,
             windows: Windows::new(area),
             buffers: Buffers::new(),
+            jobs: Jobs::new(),
+            config,
+            status: None,
+            shell_output: None,
+            lsp: lsp::Registry::new(),
+            completion_sources: completion::default_sources(),
+            quickfix: Quickfix::default(),
+            diff_mode: None,
             should_quit: false,
+            should_suspend: false,
         }
     }
 
@@ -44,50 +135,620 @@ impl Editor {
         self.should_quit
     }
 
-    pub fn open(&mut self, file_path: &Path) -> Result<BufferId> {
-        if let Some(buffer_id) = self.buffers.find_by_file_path(file_path) {
-            let window_id = self.windows.add(buffer_id);
-            self.windows.focus(window_id);
+    pub fn quit(&mut self) {
+        self.should_quit = true;
+    }
+
+    /// Ask the app to suspend to the shell once the current event is handled
+    pub fn suspend(&mut self) {
+        self.should_suspend = true;
+    }
+
+    pub fn take_suspend(&mut self) -> bool {
+        std::mem::take(&mut self.should_suspend)
+    }
+
+    /// Run a shell command in the background, its output opens in a scratch buffer
+    pub fn run_shell(&mut self, cmd: &str) {
+        let cmd = cmd.to_string();
+
+        self.set_status(format!("Running {cmd}"));
+        self.jobs.spawn(shell::run_to_scratch(cmd));
+    }
+
+    pub fn set_status(&mut self, text: impl Into<String>) {
+        self.status = Some(StatusMessage {
+            text: text.into(),
+            severity: Severity::Info,
+        });
+    }
+
+    pub fn set_error(&mut self, text: impl Into<String>) {
+        self.status = Some(StatusMessage {
+            text: text.into(),
+            severity: Severity::Error,
+        });
+    }
+
+    pub fn open(&mut self, file_path: &Path, action: OpenAction) -> Result<BufferId> {
+        self.open_file(file_path, action, true)
+    }
+
+    /// Open a file reading it at once, even a large one, for edits that need the
+    /// whole text right away
+    pub fn open_loaded(&mut self, file_path: &Path, action: OpenAction) -> Result<BufferId> {
+        self.open_file(file_path, action, false)
+    }
+
+    fn open_file(
+        &mut self,
+        file_path: &Path,
+        action: OpenAction,
+        background: bool,
+    ) -> Result<BufferId> {
+        let buffer_id = match self.buffers.find_by_file_path(file_path) {
+            Some(buffer_id) => buffer_id,
+            None => self.load(file_path, background)?,
+        };
+
+        self.show_buffer(buffer_id, action);
+        lsp::attach(self, buffer_id);
+        git::attach(self, buffer_id);
+
+        Ok(buffer_id)
+    }
+
+    /// Open an unnamed buffer filled from stdin
+    pub fn open_stdin(&mut self, action: OpenAction) -> BufferId {
+        let buffer_id = self.buffers.add_loading(None, None);
+        self.load_in_background(buffer_id, io::stdin());
+
+        self.show_buffer(buffer_id, action);
+
+        buffer_id
+    }
+
+    fn load(&mut self, file_path: &Path, background: bool) -> Result<BufferId> {
+        let file_size = std::fs::metadata(file_path)?.len() as usize;
+
+        let buffer_id = if background && file_size > LARGE_FILE_THRESHOLD {
+            let buffer_id = self.buffers.add_loading(Some(file_path), Some(file_size));
+            self.load_in_background(buffer_id, File::open(file_path)?);
+            buffer_id
+        } else {
+            let content = Rope::from_reader(BufReader::new(File::open(file_path)?))?;
+            self.buffers.add(content, Some(file_path))
+        };
 
-            return Ok(buffer_id);
+        Ok(buffer_id)
+    }
+
+    /// Display a buffer according to `action`, creating a window if there is none yet
+    pub fn show_buffer(&mut self, buffer_id: BufferId, action: OpenAction) {
+        if action == OpenAction::Load && !self.windows.is_empty() {
+            return;
         }
 
-        let content = Rope::from_reader(BufReader::new(File::open(file_path)?))?;
+        let window_id = match (action, self.windows.get_focused_mut()) {
+            (OpenAction::Replace, Some(window)) => {
+                window.buffer_id = buffer_id;
+                window.offset = Offset::default();
+                window.id
+            }
+            (OpenAction::HorizontalSplit, _) => self.windows.split(buffer_id, Direction::Vertical),
+            (OpenAction::VerticalSplit, _) => self.windows.split(buffer_id, Direction::Horizontal),
+            _ => self.windows.add(buffer_id),
+        };
 
-        let buffer_id = self.buffers.add(content, Some(file_path));
-        let window_id = self.windows.add(buffer_id);
         self.windows.focus(window_id);
 
-        Ok(buffer_id)
+        let buf = self.buffers.get_mut(buffer_id).unwrap();
+        if !buf.has_cursor(window_id) {
+            buf.set_cursor(window_id, Cursor::default());
+        }
     }
 
-    fn cursor(&self) -> Option<Position> {
-        let focused_window = self.windows.get_focused().unwrap();
-        let buf = self.buffers.get(focused_window.buffer_id).unwrap();
+    /// Close the focused window, quitting once no windows are left
+    pub fn close_window(&mut self, force: bool) -> Result<()> {
+        let Some(window_id) = self.windows.focused_node else {
+            self.quit();
+            return Ok(());
+        };
+
+        if self.windows.count() == 1 && !force {
+            self.ensure_saved()?;
+        }
+
+        self.windows.remove(window_id);
+        if self.windows.is_empty() {
+            self.quit();
+        }
+
+        Ok(())
+    }
+
+    /// Close a buffer, showing another one in its windows.
+    ///
+    /// Fails when it is the last buffer, or has unsaved changes unless forced.
+    pub fn close_buffer(&mut self, buffer_id: BufferId, force: bool) -> Result<()> {
+        let Some(buf) = self.buffers.get(buffer_id) else {
+            bail!("No such buffer");
+        };
+        if buf.is_modified() && !force {
+            bail!("No write since last change (add ! to override)");
+        }
+
+        let ids: Vec<BufferId> = self.buffers.iter().map(|buf| buf.id).collect();
+        let index = ids.iter().position(|id| *id == buffer_id).unwrap();
+        if ids.len() == 1 {
+            bail!("Can't close the last buffer");
+        }
+        let next = ids[(index + 1) % ids.len()];
+
+        for window in self.windows.iter_mut() {
+            if window.buffer_id != buffer_id {
+                continue;
+            }
+            window.buffer_id = next;
+            window.offset = Offset::default();
+
+            let buf = self.buffers.get_mut(next).unwrap();
+            if !buf.has_cursor(window.id) {
+                buf.set_cursor(window.id, Cursor::default());
+            }
+        }
+
+        self.remove_buffer(buffer_id);
+        Ok(())
+    }
+
+    /// Drop a buffer no window shows anymore, telling its language server
+    pub fn remove_buffer(&mut self, buffer_id: BufferId) {
+        lsp::detach(self, buffer_id);
+        self.buffers.remove(buffer_id);
+    }
+
+    /// Fails if any buffer has unsaved changes
+    pub fn ensure_saved(&self) -> Result<()> {
+        if let Some(buf) = self.buffers.iter().find(|buf| buf.is_modified()) {
+            let name = buf
+                .file_path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|| "[No Name]".to_string());
+            bail!("No write since last change for {name} (add ! to override)");
+        }
+        Ok(())
+    }
+
+    /// Write every modified buffer that has a file name
+    pub fn write_all(&mut self, force: bool) -> Result<()> {
+        let mut written = vec![];
+        for buf in self.buffers.iter_mut() {
+            if buf.is_modified() && buf.file_path().is_some() {
+                buf.save(None, force)?;
+                written.push(buf.id);
+            }
+        }
+
+        for buffer_id in written {
+            lsp::did_save(self, buffer_id);
+        }
+        Ok(())
+    }
+
+    /// End the open undo step of every buffer
+    pub fn commit_history(&mut self) {
+        for buf in self.buffers.iter_mut() {
+            buf.commit();
+        }
+    }
+
+    /// Undo the last change in the focused window's buffer
+    pub fn undo(&mut self) {
+        self.step_history(true);
+    }
+
+    /// Redo the last undone change in the focused window's buffer
+    pub fn redo(&mut self) {
+        self.step_history(false);
+    }
+
+    fn step_history(&mut self, undo: bool) {
+        let Some(window) = self.windows.get_focused() else {
+            return;
+        };
+        let buf = self.buffers.get_mut(window.buffer_id).unwrap();
+
+        let result = match undo {
+            true => buf.undo(),
+            false => buf.redo(),
+        };
+
+        match result {
+            Ok(true) => {}
+            Ok(false) if undo => self.set_status("Already at oldest change"),
+            Ok(false) => self.set_status("Already at newest change"),
+            Err(err) => self.set_error(err.to_string()),
+        }
+    }
+
+    /// Pipe whole lines of the focused buffer through a shell command in the background.
+    ///
+    /// They are replaced with its output in one undo step once it succeeds.
+    pub fn filter_lines(&mut self, range: LineRange, cmd: &str) -> Result<()> {
+        let Some(window) = self.windows.get_focused() else {
+            bail!("No buffer to filter");
+        };
+        let buffer_id = window.buffer_id;
+        let buf = self.buffers.get(buffer_id).unwrap();
+
+        if buf.readonly {
+            bail!("Cannot modify, buffer is read-only");
+        }
+        if buf.is_loading() {
+            bail!("Buffer is still loading");
+        }
+
+        let content = buf.content();
+        if range.end >= content.len_lines() {
+            bail!("Invalid range");
+        }
+
+        let chars = content.line_to_char(range.start)..content.line_to_char(range.end + 1);
+        let input = content.slice(chars.clone()).to_string();
+
+        let cmd = cmd.to_string();
+
+        self.set_status(format!("Filtering through {cmd}"));
+        self.jobs.spawn(shell::filter(buffer_id, chars, input, cmd));
+
+        Ok(())
+    }
+
+    /// Enter Visual mode in the focused window, selecting from `anchor` or the cursor
+    pub fn start_visual(&mut self, anchor: Option<usize>) {
+        let Some(window) = self.windows.get_focused_mut() else {
+            return;
+        };
+        let buf = self.buffers.get(window.buffer_id).unwrap();
+
+        window.anchor = Some(anchor.unwrap_or(buf.get_cursor(window.id).range.start));
+        self.mode = Mode::Visual;
+    }
+
+    /// Leave Visual mode, dropping the selection
+    pub fn stop_visual(&mut self) {
+        for window in self.windows.iter_mut() {
+            if let Some(selection) = self
+                .buffers
+                .get(window.buffer_id)
+                .and_then(|buf| window.selection(buf))
+            {
+                window.last_selection = Some(selection);
+            }
+            window.anchor = None;
+        }
+        if self.mode == Mode::Visual {
+            self.mode = Mode::Normal;
+        }
+    }
+
+    /// Move the cursor of the focused window to the character shown at a screen position
+    pub fn place_cursor_at(&mut self, x: u16, y: u16) {
+        let Some(window) = self.windows.get_focused() else {
+            return;
+        };
+        let buf = self.buffers.get_mut(window.buffer_id).unwrap();
+
+        if let Some(char_idx) = window.char_at(buf, x, y) {
+            let content = buf.content().slice(..);
+            let cursor = Cursor {
+                range: char_idx..next_grapheme_boundary(&content, char_idx),
+            };
+            buf.set_cursor(window.id, cursor);
+        }
+    }
+
+    /// Show the next or previous buffer in the focused window
+    pub fn cycle_buffer(&mut self, forward: bool) {
+        let Some(window) = self.windows.get_focused() else {
+            return;
+        };
+
+        let ids: Vec<BufferId> = self.buffers.iter().map(|buf| buf.id).collect();
+        let Some(index) = ids.iter().position(|id| *id == window.buffer_id) else {
+            return;
+        };
+
+        let next = if forward {
+            (index + 1) % ids.len()
+        } else {
+            (index + ids.len() - 1) % ids.len()
+        };
+
+        self.show_buffer(ids[next], OpenAction::Replace);
+    }
+
+    /// Stream a reader into an already created buffer without blocking the event loop
+    fn load_in_background(&self, buffer_id: BufferId, reader: impl Read + Send + 'static) {
+        let tx = self.jobs.sender();
+
+        tokio::task::spawn_blocking(move || {
+            let result = read_chunks(reader, |text, loaded| {
+                let callback: JobCallback = Box::new(move |_, context| {
+                    if let Some(buf) = context.editor.buffers.get_mut(buffer_id) {
+                        buf.append_loaded(&text, loaded);
+                    }
+                });
+                tx.send(callback).is_ok()
+            });
+
+            let error = result.err().map(|err| {
+                tracing::error!("Failed to load buffer {buffer_id:?}: {err}");
+                err.to_string()
+            });
+
+            let _ = tx.send(Box::new(move |_, context| {
+                if let Some(buf) = context.editor.buffers.get_mut(buffer_id) {
+                    buf.loading = None;
+                    if let Some(error) = error {
+                        buf.incomplete = true;
+                        context.editor.set_error(format!(
+                            "Loading stopped, the buffer is incomplete: {error}"
+                        ));
+                        return;
+                    }
+                    lsp::attach(context.editor, buffer_id);
+                    git::attach(context.editor, buffer_id);
+                }
+            }));
+        });
+    }
+
+    /// Screen position of the cursor in the focused window
+    pub fn cursor(&self) -> Option<Position> {
+        let focused_window = self.windows.get_focused()?;
+        let buf = self.buffers.get(focused_window.buffer_id)?;
         let content = buf.content();
 
         let cursor = buf.get_cursor(focused_window.id);
-        let y = content.char_to_line(cursor.range.start) + focused_window.area.y as usize;
+        let line_index = content.char_to_line(cursor.range.start);
 
         let x = {
-            let cur_line_index = content.line_to_char(y);
+            let cur_line_index = content.line_to_char(line_index);
             let line_to_cursor = content.slice(cur_line_index..cursor.range.start);
             width(&line_to_cursor)
-        } + focused_window.area.x as usize;
+        };
+
+        // fillers of diff mode shift the lines down, closed folds up
+        let row = self
+            .diff_mode
+            .as_ref()
+            .and_then(|mode| mode.screen_row(focused_window.id, line_index))
+            .unwrap_or_else(|| {
+                buf.folds
+                    .rows_between(focused_window.offset.vertical, line_index)
+            });
 
         Some(Position {
-            x: x - focused_window.offset.horizontal,
-            y: y - focused_window.offset.vertical,
+            x: focused_window.content_area(buf).x as usize
+                + x.saturating_sub(focused_window.offset.horizontal),
+            y: focused_window.area.y as usize + row,
         })
     }
 }
 
+/// Read in fixed size chunks, splitting only on utf-8 boundaries.
+///
+/// `on_chunk` receives the decoded text and the total bytes read so far,
+/// and returns false to stop reading early.
+fn read_chunks(
+    mut reader: impl Read,
+    mut on_chunk: impl FnMut(String, usize) -> bool,
+) -> io::Result<()> {
+    let mut bytes = vec![0; LOAD_CHUNK_SIZE];
+    let mut pending: Vec<u8> = Vec::new();
+    let mut loaded = 0;
+
+    loop {
+        let read = match reader.read(&mut bytes) {
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if read == 0 {
+            break;
+        }
+        loaded += read;
+        pending.extend_from_slice(&bytes[..read]);
+
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(text) => text.len(),
+            // incomplete character at the end, finish it with the next chunk
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+
+        let rest = pending.split_off(valid);
+        let text = String::from_utf8(std::mem::replace(&mut pending, rest))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        if !on_chunk(text, loaded) {
+            return Ok(());
+        }
+    }
+
+    if !pending.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Operator waiting for the motion it applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    /// `!{motion}`, filter lines through a shell command
+    Filter,
+    /// `={motion}`, indent lines again
+    Reindent,
+    /// `gc{motion}`, comment lines out or back in
+    Comment,
+}
+
+/// Key waiting for the next one to complete it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    Operator(Operator),
+    /// `g` prefix, e.g. `gd`, or `gc` in Visual mode
+    Goto,
+    /// `]` or `[` prefix, e.g. `]c`
+    Bracket(char),
+    /// `d` prefix, `do` and `dp` in diff mode
+    Diff,
+    /// `z` prefix, folding
+    Fold,
+    /// `a` or `i` waiting for the text object, for an operator or the Visual mode selection
+    TextObject {
+        operator: Option<Operator>,
+        around: bool,
+    },
+}
+
 #[derive(Default)]
-pub struct EditorView {}
+pub struct EditorView {
+    pending: Option<Pending>,
+}
+
+/// Open the command prompt with `input` already typed
+fn open_prompt(input: String) -> EventPropagation {
+    EventPropagation::Consume(Some(Box::new(move |components, context| {
+        context.editor.mode = Mode::Command;
+        components.push(Box::new(CommandPrompt::with_input(input)));
+    })))
+}
+
+/// Apply an operator to the lines from `start` to `end`, both included.
+///
+/// A closed fold counts as a single line, so lines of one are covered with all of its lines.
+fn run_operator(
+    operator: Operator,
+    start: usize,
+    end: usize,
+    editor: &mut Editor,
+) -> EventPropagation {
+    let (start, end) = match editor.windows.get_focused() {
+        Some(window) => {
+            let folds = &editor.buffers.get(window.buffer_id).unwrap().folds;
+            (folds.row_start(start), folds.next_row(end) - 1)
+        }
+        None => (start, end),
+    };
+
+    match operator {
+        Operator::Filter => open_prompt(format!("{},{}!", start + 1, end + 1)),
+        Operator::Reindent => {
+            if let Err(err) = indent::reindent(editor, start..end + 1) {
+                editor.set_error(err.to_string());
+            }
+            EventPropagation::Consume(None)
+        }
+        Operator::Comment => {
+            if let Err(err) = comment::toggle(editor, start..end + 1) {
+                editor.set_error(err.to_string());
+            }
+            EventPropagation::Consume(None)
+        }
+    }
+}
 
 impl EditorView {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Apply a pending operator to the lines covered by the motion `event`
+    fn apply_operator(
+        &mut self,
+        operator: Operator,
+        event: &KeyEvent,
+        context: &mut Context,
+    ) -> EventPropagation {
+        let Some(window) = context.editor.windows.get_focused() else {
+            return EventPropagation::Consume(None);
+        };
+        let (window_id, buffer_id) = (window.id, window.buffer_id);
+        let buf = context.editor.buffers.get_mut(buffer_id).unwrap();
+        let pos = buf.get_cursor(window_id).range.start;
+
+        let line = buf.content().char_to_line(pos);
+        let last = buf.content().len_lines().saturating_sub(1);
+
+        let (start, end) = match event.code {
+            KeyCode::Char('!' | '=' | 'h' | 'l' | '0' | '$') => (line, line),
+            KeyCode::Char('j') => (line, buf.folds.next_row(line).min(last)),
+            KeyCode::Char('k') => (buf.folds.prev_row(line).unwrap_or(0), line),
+            KeyCode::Char('c') if operator == Operator::Comment => (line, line),
+            KeyCode::Char('%') => match brackets::matching(buf, pos) {
+                Some(matching) => {
+                    let other = buf.content().char_to_line(matching);
+                    (line.min(other), line.max(other))
+                }
+                None => {
+                    context.editor.set_error("No matching bracket");
+                    return EventPropagation::Consume(None);
+                }
+            },
+            KeyCode::Char(key @ ('a' | 'i')) => {
+                self.pending = Some(Pending::TextObject {
+                    operator: Some(operator),
+                    around: key == 'a',
+                });
+                return EventPropagation::Consume(None);
+            }
+            // anything else cancels the operator
+            _ => return EventPropagation::Consume(None),
+        };
+
+        run_operator(operator, start, end, context.editor)
+    }
+
+    /// Apply a pending operator to the text object `event`, or select it in Visual mode
+    fn apply_text_object(
+        &mut self,
+        operator: Option<Operator>,
+        around: bool,
+        event: &KeyEvent,
+        context: &mut Context,
+    ) -> EventPropagation {
+        let KeyCode::Char(key) = event.code else {
+            return EventPropagation::Consume(None);
+        };
+        let editor = &mut context.editor;
+
+        let Some(operator) = operator else {
+            if let Err(err) = textobject::select(editor, key, around) {
+                editor.set_error(err.to_string());
+            }
+            return EventPropagation::Consume(None);
+        };
+
+        let range = match textobject::range(editor, key, around) {
+            Ok(range) => range,
+            Err(err) => {
+                editor.set_error(err.to_string());
+                return EventPropagation::Consume(None);
+            }
+        };
+        let window = editor.windows.get_focused().unwrap();
+        let content = editor.buffers.get(window.buffer_id).unwrap().content();
+        let start = content.char_to_line(range.start);
+        let end = content.char_to_line(range.end - 1);
+
+        run_operator(operator, start, end, editor)
     }
 
     fn handle_key_events(&mut self, event: &KeyEvent, context: &mut Context) -> EventPropagation {
@@ -95,22 +756,152 @@ impl EditorView {
             editor: context.editor,
         };
 
+        event_context.editor.status = None;
+
+        match self.pending.take() {
+            Some(Pending::Operator(operator)) => {
+                return self.apply_operator(operator, event, &mut event_context);
+            }
+            Some(Pending::TextObject { operator, around }) => {
+                return self.apply_text_object(operator, around, event, &mut event_context);
+            }
+            Some(Pending::Goto) => {
+                let editor = &mut event_context.editor;
+                let result = match event.code {
+                    KeyCode::Char('c') if editor.mode == Mode::Visual => {
+                        let window = editor.windows.get_focused().unwrap();
+                        let buf = editor.buffers.get(window.buffer_id).unwrap();
+                        match window.selection(buf) {
+                            Some(selection) => {
+                                let content = buf.content();
+                                let lines = content.char_to_line(selection.start)
+                                    ..content.char_to_line(selection.end - 1) + 1;
+                                editor.stop_visual();
+                                comment::toggle(editor, lines)
+                            }
+                            None => Ok(()),
+                        }
+                    }
+                    KeyCode::Char('c') => {
+                        self.pending = Some(Pending::Operator(Operator::Comment));
+                        Ok(())
+                    }
+                    _ if editor.mode == Mode::Visual => Ok(()),
+                    KeyCode::Char('d') => lsp::goto_definition(editor),
+                    KeyCode::Char('r') => lsp::references(editor),
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    editor.set_error(err.to_string());
+                }
+                return EventPropagation::Consume(None);
+            }
+            Some(Pending::Diff) => {
+                let editor = &mut event_context.editor;
+                let result = match event.code {
+                    KeyCode::Char('o') => diff_mode::transfer(editor, false),
+                    KeyCode::Char('p') => diff_mode::transfer(editor, true),
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    editor.set_error(err.to_string());
+                }
+                return EventPropagation::Consume(None);
+            }
+            Some(Pending::Fold) => {
+                let editor = &mut event_context.editor;
+                let result = match event.code {
+                    KeyCode::Char('a') => fold::toggle(editor),
+                    KeyCode::Char('c') => fold::close(editor),
+                    KeyCode::Char('o') => fold::open(editor),
+                    KeyCode::Char('M') => fold::close_all(editor),
+                    KeyCode::Char('R') => fold::open_all(editor),
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    editor.set_error(err.to_string());
+                }
+                let scrolloff = editor.config.scrolloff;
+                if let Some(window) = editor.windows.get_focused_mut() {
+                    let buf = editor.buffers.get(window.buffer_id).unwrap();
+                    window.position_cursor_in_view(buf, scrolloff);
+                }
+                return EventPropagation::Consume(None);
+            }
+            Some(Pending::Bracket(bracket)) => {
+                let editor = &mut event_context.editor;
+                let result = match event.code {
+                    KeyCode::Char('c') => git::goto_hunk(editor, bracket == ']'),
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    editor.set_error(err.to_string());
+                }
+                let scrolloff = editor.config.scrolloff;
+                if let Some(window) = editor.windows.get_focused_mut() {
+                    let buf = editor.buffers.get(window.buffer_id).unwrap();
+                    window.position_cursor_in_view(buf, scrolloff);
+                }
+                return EventPropagation::Consume(None);
+            }
+            None => {}
+        }
+
         match event_context.editor.mode {
             Mode::Normal => match event.code {
-                KeyCode::Char('q') => event_context.editor.should_quit = true,
+                KeyCode::Char('w') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    event_context.editor.windows.focus_next()
+                }
+                KeyCode::Char('z') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    event_context.editor.suspend()
+                }
+                KeyCode::Char('p') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    let picker = FilePicker::new(Path::new(""), event_context.editor);
+                    return EventPropagation::Consume(Some(Box::new(|components, _| {
+                        components.push(Box::new(picker));
+                    })));
+                }
+                KeyCode::Char('o') if event.modifiers.contains(KeyModifiers::ALT) => {
+                    if let Err(err) = textobject::expand_selection(event_context.editor) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
+                KeyCode::Char('q') => {
+                    if let Err(err) = event_context.editor.close_window(false) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
                 KeyCode::Char('l') => movements::move_right(&mut event_context),
                 KeyCode::Char('h') => movements::move_left(&mut event_context),
                 KeyCode::Char('j') => movements::move_down(&mut event_context),
                 KeyCode::Char('k') => movements::move_up(&mut event_context),
                 KeyCode::Char('i') => event_context.editor.mode = Mode::Insert,
+                KeyCode::Char('v') => event_context.editor.start_visual(None),
+                KeyCode::Char('u') => event_context.editor.undo(),
+                KeyCode::Char('r') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    event_context.editor.redo()
+                }
                 KeyCode::Char('0') => movements::goto_start_of_line(&mut event_context),
                 KeyCode::Char('$') => movements::goto_end_of_line(&mut event_context),
-                KeyCode::Char(':') => {
-                    return EventPropagation::Consume(Some(Box::new(|components, context| {
-                        context.editor.mode = Mode::Command;
-                        components.push(Box::new(CommandPrompt::new()));
-                    })));
+                KeyCode::Char('%') => {
+                    if let Err(err) = brackets::goto_matching(event_context.editor) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
+                KeyCode::Char('!') => self.pending = Some(Pending::Operator(Operator::Filter)),
+                KeyCode::Char('=') => self.pending = Some(Pending::Operator(Operator::Reindent)),
+                KeyCode::Char('g') => self.pending = Some(Pending::Goto),
+                KeyCode::Char('d') => self.pending = Some(Pending::Diff),
+                KeyCode::Char('z') => self.pending = Some(Pending::Fold),
+                KeyCode::Char(bracket @ (']' | '[')) => {
+                    self.pending = Some(Pending::Bracket(bracket))
                 }
+                KeyCode::Char('K') => {
+                    if let Err(err) = lsp::hover(event_context.editor) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
+                KeyCode::Char(':') => return open_prompt(String::new()),
                 //KeyCode::Char('w') => text::move_word_forward(&mut event_context),
                 //KeyCode::Char('b') => text::move_word_backward(&mut event_context),
                 //KeyCode::Char(num @ ('1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9')) => {
@@ -121,20 +912,169 @@ impl EditorView {
                 }
             },
             Mode::Insert => match event.code {
-                KeyCode::Char(char) => movements::insert_char(&mut event_context, char),
-                KeyCode::Enter => movements::insert_new_line(&mut event_context),
-                KeyCode::Backspace => movements::delete_char(&mut event_context),
+                KeyCode::Char(char @ ('n' | 'p'))
+                    if event.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    return completion::open(event_context.editor, char == 'p');
+                }
+                KeyCode::Tab if completion::has_prefix(event_context.editor) => {
+                    return completion::open(event_context.editor, false);
+                }
+                KeyCode::Tab => movements::insert_char(&mut event_context, '\t'),
+                KeyCode::Char(char) => autopair::insert_char(&mut event_context, char),
+                KeyCode::Enter => autopair::insert_new_line(&mut event_context),
+                KeyCode::Backspace => autopair::delete_char(&mut event_context),
                 KeyCode::Esc => event_context.editor.mode = Mode::Normal,
                 _ => {}
             },
-            Mode::Visual => todo!(),
+            Mode::Visual => match event.code {
+                KeyCode::Char('o') if event.modifiers.contains(KeyModifiers::ALT) => {
+                    if let Err(err) = textobject::expand_selection(event_context.editor) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
+                KeyCode::Char('i') if event.modifiers.contains(KeyModifiers::ALT) => {
+                    if let Err(err) = textobject::shrink_selection(event_context.editor) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
+                KeyCode::Char('g') => self.pending = Some(Pending::Goto),
+                KeyCode::Char(key @ ('a' | 'i')) => {
+                    self.pending = Some(Pending::TextObject {
+                        operator: None,
+                        around: key == 'a',
+                    })
+                }
+                KeyCode::Char('l') => movements::move_right(&mut event_context),
+                KeyCode::Char('h') => movements::move_left(&mut event_context),
+                KeyCode::Char('j') => movements::move_down(&mut event_context),
+                KeyCode::Char('k') => movements::move_up(&mut event_context),
+                KeyCode::Char('0') => movements::goto_start_of_line(&mut event_context),
+                KeyCode::Char('$') => movements::goto_end_of_line(&mut event_context),
+                KeyCode::Char('%') => {
+                    if let Err(err) = brackets::goto_matching(event_context.editor) {
+                        event_context.editor.set_error(err.to_string());
+                    }
+                }
+                KeyCode::Esc | KeyCode::Char('v') => event_context.editor.stop_visual(),
+                KeyCode::Char('!') => {
+                    event_context.editor.stop_visual();
+                    return open_prompt("'<,'>!".to_string());
+                }
+                KeyCode::Char('=') => {
+                    let editor = &mut event_context.editor;
+                    let window = editor.windows.get_focused().unwrap();
+                    let buf = editor.buffers.get(window.buffer_id).unwrap();
+                    if let Some(selection) = window.selection(buf) {
+                        let content = buf.content();
+                        let lines = content.char_to_line(selection.start)
+                            ..content.char_to_line(selection.end - 1) + 1;
+                        editor.stop_visual();
+                        if let Err(err) = indent::reindent(editor, lines) {
+                            editor.set_error(err.to_string());
+                        }
+                    }
+                }
+                KeyCode::Char(':') => {
+                    event_context.editor.stop_visual();
+                    return open_prompt("'<,'>".to_string());
+                }
+                _ => {}
+            },
             Mode::Search => todo!(),
             _ => {}
         }
 
-        let window = event_context.editor.windows.get_focused_mut().unwrap();
-        let buf = event_context.editor.buffers.get(window.buffer_id).unwrap();
-        window.position_cursor_in_view(buf, 12);
+        let scrolloff = event_context.editor.config.scrolloff;
+        if let Some(window) = event_context.editor.windows.get_focused_mut() {
+            let buf = event_context.editor.buffers.get(window.buffer_id).unwrap();
+            window.position_cursor_in_view(buf, scrolloff);
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    /// Insert pasted text as one undo step, bypassing per-key handling
+    fn handle_paste(&mut self, text: &str, context: &mut Context) -> EventPropagation {
+        let mut event_context = Context {
+            editor: context.editor,
+        };
+        let mode = event_context.editor.mode;
+        if mode != Mode::Normal && mode != Mode::Insert {
+            return EventPropagation::Ignore(None);
+        }
+
+        event_context.editor.status = None;
+
+        // terminals send line breaks as carriage returns
+        let text = match text.contains('\r') {
+            true => Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n")),
+            false => Cow::Borrowed(text),
+        };
+
+        event_context.editor.commit_history();
+        movements::insert_text(&mut event_context, &text);
+        event_context.editor.commit_history();
+
+        // like `p`, Normal mode ends on the last pasted character
+        if mode == Mode::Normal && !text.is_empty() {
+            movements::move_left(&mut event_context);
+        }
+
+        let scrolloff = event_context.editor.config.scrolloff;
+        if let Some(window) = event_context.editor.windows.get_focused_mut() {
+            let buf = event_context.editor.buffers.get(window.buffer_id).unwrap();
+            window.position_cursor_in_view(buf, scrolloff);
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    fn handle_mouse_events(
+        &mut self,
+        event: &MouseEvent,
+        context: &mut Context,
+    ) -> EventPropagation {
+        let editor = &mut context.editor;
+
+        let Some(window_id) = editor.windows.window_at(event.column, event.row) else {
+            return EventPropagation::Ignore(None);
+        };
+
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                editor.status = None;
+                editor.stop_visual();
+                editor.windows.focus(window_id);
+                editor.place_cursor_at(event.column, event.row);
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                // keep selecting in the window the drag started in
+                if editor.windows.focused_node != Some(window_id) {
+                    return EventPropagation::Consume(None);
+                }
+                if editor.mode != Mode::Visual {
+                    let anchor = editor.windows.get_focused().and_then(|window| {
+                        let buf = editor.buffers.get(window.buffer_id)?;
+                        Some(buf.get_cursor(window.id).range.start)
+                    });
+                    editor.start_visual(anchor);
+                }
+                editor.place_cursor_at(event.column, event.row);
+            }
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                let lines = match event.kind {
+                    MouseEventKind::ScrollDown => MOUSE_SCROLL_LINES,
+                    _ => -MOUSE_SCROLL_LINES,
+                };
+
+                let scrolloff = editor.config.scrolloff;
+                let window = editor.windows.get_mut(window_id).unwrap();
+                let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+                window.scroll(buf, lines, scrolloff);
+            }
+            _ => return EventPropagation::Ignore(None),
+        }
 
         EventPropagation::Consume(None)
     }
@@ -148,7 +1088,8 @@ impl Component for EditorView {
     ) -> EventPropagation {
         match event {
             Event::Key(key_event) => self.handle_key_events(key_event, context),
-            Event::Mouse(_mouse_event) => EventPropagation::Ignore(None),
+            Event::Mouse(mouse_event) => self.handle_mouse_events(mouse_event, context),
+            Event::Paste(text) => self.handle_paste(text, context),
             _ => EventPropagation::Ignore(None),
         }
     }
@@ -160,60 +1101,358 @@ impl Component for EditorView {
     fn render(
         &self,
         f: &mut crate::terminal::Frame<'_>,
-        _area: Rect,
+        area: Rect,
         context: &mut crate::components::Context,
     ) {
+        // the bracket matching the one at the cursor of each window, which may parse the buffer
+        let windows: Vec<_> = context
+            .editor
+            .windows
+            .iter()
+            .map(|window| (window.id, window.buffer_id))
+            .collect();
+        let matching_brackets: Vec<_> = windows
+            .into_iter()
+            .map(|(window_id, buffer_id)| {
+                let buf = context.editor.buffers.get_mut(buffer_id).unwrap();
+                let pos = buf.get_cursor(window_id).range.start;
+                brackets::matching(buf, pos).map(|pos| buf.content().char_to_byte(pos))
+            })
+            .collect();
         let editor = &context.editor;
 
-        for window in editor.windows.iter() {
+        for (window, matching_bracket) in editor.windows.iter().zip(matching_brackets) {
             let buf = editor.buffers.get(window.buffer_id).unwrap();
 
-            let buf_layout = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints(vec![Constraint::Percentage(100), Constraint::Length(2)])
-                .split(window.area);
+            let text_area = window.text_area();
+            let status_area = Rect {
+                y: text_area.bottom(),
+                height: window.area.height - text_area.height,
+                ..window.area
+            };
 
-            let window = editor.windows.get_by_buffer_id(buf.id).unwrap();
             let content = buf.content().slice(..);
 
+            // line shown on each row, fillers of diff mode and closed folds leave lines out
+            let height = text_area.height as usize;
+            let diff = editor
+                .diff_mode
+                .as_ref()
+                .and_then(|mode| Some((mode, mode.side(window.id)?)));
+            let rows: Vec<Option<usize>> = match diff {
+                Some((mode, side)) => (mode.top_row..mode.top_row + height)
+                    .map(|row| mode.line_at_row(side, row))
+                    .collect(),
+                None => buf
+                    .folds
+                    .rows(window.offset.vertical, height, content.len_lines()),
+            };
+
             let cursor = buf.get_cursor(window.id);
             let range = {
                 let last_line = content.len_lines().saturating_sub(1);
-                let last_visible_line = (window.offset.vertical + window.area.height as usize)
-                    .saturating_sub(1)
+                let first_visible_line = rows.iter().flatten().min().copied().unwrap_or(last_line);
+                let last_visible_line = rows
+                    .iter()
+                    .flatten()
+                    .max()
+                    .map_or(last_line, |&line| buf.folds.next_row(line) - 1)
                     .min(last_line);
 
-                let start = content.line_to_byte(window.offset.vertical.min(last_line));
+                let start = content.line_to_byte(first_visible_line.min(last_line));
                 let end = content.line_to_byte(last_visible_line + 1);
                 start..end
             };
-            let colors = buf.highlight.colors(content.slice(..), range.clone());
+            let colors = buf
+                .highlight
+                .as_ref()
+                .map(|highlight| highlight.colors(content, range))
+                .unwrap_or_default();
 
             let status_line = StatusLine {
                 content,
                 cursor,
                 mode: editor.mode,
+                loading: buf.loading,
+                readonly: buf.readonly,
+            };
+
+            let selection = window
+                .selection(buf)
+                .map(|chars| content.char_to_byte(chars.start)..content.char_to_byte(chars.end));
+
+            let content_area = window.content_area(buf);
+            let blame_area = Rect {
+                width: window.blame_width(buf),
+                ..text_area
+            };
+            let sign_area = Rect {
+                x: blame_area.right(),
+                width: content_area.x - blame_area.right(),
+                ..text_area
             };
+            let diagnostics = line_diagnostics(&buf.diagnostics, &rows);
 
             let text = RenderableText {
                 content,
                 colors,
+                selection,
+                matching_bracket,
+                diagnostics: &diagnostics,
                 offset: window.offset,
-                _cursor: cursor,
+                rows: Some(rows.clone()),
+                folds: Some(&buf.folds),
             };
 
             // TODO: Handle other buffer/windows if included
-            f.render_widget(text, buf_layout[0]);
-            f.render_widget(status_line, buf_layout[1]);
+            if !sign_area.is_empty() {
+                let signs = SignColumn {
+                    diagnostics: &diagnostics,
+                    hunks: buf.git.as_ref().map_or(&[], |diff| &diff.hunks),
+                    rows: &rows,
+                };
+                f.render_widget(signs, sign_area);
+            }
+            if let Some(blame) = buf.blame.as_ref().filter(|_| !blame_area.is_empty()) {
+                let panel = BlamePanel { blame, rows: &rows };
+                f.render_widget(panel, blame_area);
+            }
+            f.render_widget(text, content_area);
+            if let Some((mode, side)) = diff {
+                let highlights = DiffHighlights {
+                    mode,
+                    side,
+                    content,
+                    horizontal: window.offset.horizontal,
+                };
+                f.render_widget(highlights, content_area);
+            }
+            f.render_widget(status_line, status_area);
+        }
+
+        if let Some(status) = editor.status.as_ref().filter(|_| area.height > 0) {
+            let style = match status.severity {
+                Severity::Info => Style::default(),
+                Severity::Error => Style::default().fg(Color::Red),
+            };
+            let y = area.y + area.height.saturating_sub(1);
+            f.buffer_mut()
+                .set_stringn(area.x, y, &status.text, area.width as usize, style);
+        }
+    }
+}
+
+/// Most severe diagnostic on a line
+struct LineDiagnostic<'a> {
+    line: usize,
+    severity: DiagnosticSeverity,
+    message: &'a str,
+}
+
+/// Diagnostics of the `height` lines from `first_line`, one per line
+fn line_diagnostics<'a>(
+    diagnostics: &'a [Diagnostic],
+    rows: &[Option<usize>],
+) -> Vec<LineDiagnostic<'a>> {
+    let mut lines: Vec<LineDiagnostic> = vec![];
+
+    for diagnostic in diagnostics {
+        let line = diagnostic.range.start.line as usize;
+        if !rows.contains(&Some(line)) {
+            continue;
+        }
+
+        let current = LineDiagnostic {
+            line,
+            severity: lsp::severity(diagnostic),
+            message: diagnostic.message.lines().next().unwrap_or_default(),
+        };
+        match lines.iter_mut().find(|other| other.line == line) {
+            Some(other) if other.severity <= current.severity => {}
+            Some(other) => *other = current,
+            None => lines.push(current),
         }
     }
+
+    lines
 }
 
-struct RenderableText<'a> {
+/// Sign and color of a diagnostic severity
+fn severity_sign(severity: DiagnosticSeverity) -> (char, Color) {
+    match severity {
+        DiagnosticSeverity::ERROR => ('E', Color::Red),
+        DiagnosticSeverity::WARNING => ('W', Color::Yellow),
+        DiagnosticSeverity::INFORMATION => ('I', Color::Blue),
+        _ => ('H', Color::Cyan),
+    }
+}
+
+/// Sign and color of a git hunk
+fn hunk_sign(kind: HunkKind) -> (char, Color) {
+    match kind {
+        HunkKind::Added => ('+', Color::Green),
+        HunkKind::Changed => ('~', Color::Yellow),
+        HunkKind::Removed => ('_', Color::Red),
+    }
+}
+
+/// Diagnostics in the first column, git changes in the second
+struct SignColumn<'a> {
+    diagnostics: &'a [LineDiagnostic<'a>],
+    hunks: &'a [Hunk],
+    /// Line shown on each row
+    rows: &'a [Option<usize>],
+}
+
+impl Widget for SignColumn<'_> {
+    fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        buf.set_style(area, Style::default().bg(Color::Rgb(25, 23, 36)));
+
+        let row = |line: usize| {
+            let row = self.rows.iter().position(|&row| row == Some(line))?;
+            Some(area.y + row as u16)
+        };
+
+        for diagnostic in self.diagnostics {
+            let (sign, color) = severity_sign(diagnostic.severity);
+            if let Some(y) = row(diagnostic.line) {
+                buf.set_string(area.x, y, sign.to_string(), Style::default().fg(color));
+            }
+        }
+
+        for hunk in self.hunks {
+            let (sign, color) = hunk_sign(hunk.kind());
+            let lines = match hunk.kind() {
+                HunkKind::Removed => hunk.first_line()..hunk.first_line() + 1,
+                _ => hunk.new.clone(),
+            };
+            for y in lines.filter_map(row) {
+                buf.set_string(area.x + 1, y, sign.to_string(), Style::default().fg(color));
+            }
+        }
+    }
+}
+
+/// Commits of the lines shown next to them, scrolling along with the text
+struct BlamePanel<'a> {
+    blame: &'a Blame,
+    /// Line shown on each row
+    rows: &'a [Option<usize>],
+}
+
+impl Widget for BlamePanel<'_> {
+    fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        buf.set_style(area, Style::default().bg(Color::Rgb(25, 23, 36)));
+
+        let now = git::now();
+        let width = area.width.saturating_sub(1) as usize;
+        for (y, line) in (area.top()..area.bottom()).zip(self.rows) {
+            let Some(line) = *line else {
+                continue;
+            };
+            let (label, color) = match self.blame.line(line) {
+                Some(entry) => (entry.label(now), Color::Gray),
+                None => ("Not committed yet".to_string(), Color::DarkGray),
+            };
+            buf.set_stringn(area.x, y, label, width, Style::default().fg(color));
+        }
+    }
+}
+
+/// Backgrounds of the changed lines and fillers of a side in diff mode, drawn over its text
+struct DiffHighlights<'a> {
+    mode: &'a DiffMode,
+    side: usize,
+    content: RopeSlice<'a>,
+    horizontal: usize,
+}
+
+impl Widget for DiffHighlights<'_> {
+    fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let left = self.horizontal;
+        let right = left + area.width as usize;
+
+        for (y, row) in (area.top()..area.bottom()).zip(self.mode.top_row..) {
+            let Some(row) = self.mode.rows.get(row).filter(|row| row.changed) else {
+                continue;
+            };
+            let line_area = Rect {
+                y,
+                height: 1,
+                ..area
+            };
+
+            let Some(line) = row.lines[self.side] else {
+                let filler = "-".repeat(area.width as usize);
+                let style = Style::default().fg(Color::DarkGray).bg(DIFF_DELETE_COLOR);
+                buf.set_string(area.x, y, filler, style);
+                continue;
+            };
+            if row.lines[1 - self.side].is_none() {
+                buf.set_style(line_area, Style::default().bg(DIFF_ADD_COLOR));
+                continue;
+            }
+            buf.set_style(line_area, Style::default().bg(DIFF_CHANGE_COLOR));
+
+            let inline = &row.inline[self.side];
+            let mut x = 0;
+            for (index, char) in self.content.line(line).chars().enumerate() {
+                if char == '\n' || char == '\r' || x >= right {
+                    break;
+                }
+                let char_width = char.width().unwrap_or(0);
+                if x >= left && inline.iter().any(|range| range.contains(&index)) {
+                    for column in x..(x + char_width).min(right) {
+                        buf.get_mut(area.x + (column - left) as u16, y)
+                            .set_bg(DIFF_TEXT_COLOR);
+                    }
+                }
+                x += char_width;
+            }
+        }
+    }
+}
+
+/// Highlighted text of a buffer from its scroll offset
+pub struct RenderableText<'a> {
     content: RopeSlice<'a>,
     colors: Vec<HighlightInfo>,
-    _cursor: &'a Cursor,
+    /// Bytes of the Visual mode selection
+    selection: Option<Range<usize>>,
+    /// Byte of the bracket matching the one at the cursor
+    matching_bracket: Option<usize>,
+    /// Shown after the end of their line
+    diagnostics: &'a [LineDiagnostic<'a>],
     offset: Offset,
+    /// Line shown on each row, `None` for fillers of [`DiffMode`]
+    rows: Option<Vec<Option<usize>>>,
+    /// Closed folds are shown as a summary on their first line
+    folds: Option<&'a Folds>,
+}
+
+impl<'a> RenderableText<'a> {
+    /// Text from the first line, without selection or diagnostics, e.g. for previews
+    pub fn new(content: RopeSlice<'a>, colors: Vec<HighlightInfo>) -> Self {
+        Self {
+            content,
+            colors,
+            selection: None,
+            matching_bracket: None,
+            diagnostics: &[],
+            offset: Offset::default(),
+            rows: None,
+            folds: None,
+        }
+    }
 }
 
 impl Widget for RenderableText<'_> {
@@ -224,77 +1463,136 @@ impl Widget for RenderableText<'_> {
         buf.set_style(area, Style::default().bg(Color::Rgb(25, 23, 36)));
 
         let content = self.content;
-        let start = content.line_to_char(self.offset.vertical);
-        let end = if let Ok(char_index) =
-            content.try_line_to_char(self.offset.vertical + area.height as usize)
-        {
-            char_index
-        } else {
-            content.len_chars()
+        let first_line = self.offset.vertical;
+        let last_line = (first_line + area.height as usize).min(content.len_lines());
+
+        let left = self.offset.horizontal;
+        let right = left + area.width as usize;
+
+        let mut colors = ColorCursor::new(&self.colors);
+
+        let lines = match self.rows {
+            Some(rows) => rows,
+            None => (first_line..last_line).map(Some).collect(),
         };
 
-        let mut x: u16 = 0;
-        let mut y: u16 = 0;
-        let mut style = Style::default();
+        // only walk the visible part of each visible line
+        for (y, line_index) in lines.into_iter().enumerate() {
+            let Some(line_index) = line_index.filter(|&line| line < content.len_lines()) else {
+                continue;
+            };
 
-        for (index, char) in content.slice(start..end).chars().enumerate() {
-            if char == '\n' {
-                y += 1;
-                x = 0;
+            if let Some(fold) = self.folds.and_then(|folds| folds.closed_at(line_index)) {
+                let summary = format!(
+                    "+-- {} lines: {}",
+                    fold.len(),
+                    content.line(line_index).to_string().trim()
+                );
+                let line_area = Rect {
+                    y: area.y + y as u16,
+                    height: 1,
+                    ..area
+                };
+                buf.set_style(line_area, Style::default().bg(FOLD_COLOR));
+                buf.set_stringn(
+                    area.x,
+                    line_area.y,
+                    summary,
+                    area.width as usize,
+                    Style::default().fg(Color::Gray),
+                );
                 continue;
             }
+            let mut byte_index = content.line_to_byte(line_index);
+            let mut x = 0;
 
-            if x >= area.width {
-                continue;
+            for char in content.line(line_index).chars() {
+                if char == '\n' || char == '\r' || x >= right {
+                    break;
+                }
+
+                let char_width = char.width().unwrap_or(0);
+
+                if x >= left && char_width > 0 && x + char_width <= right {
+                    let mut style =
+                        Style::default().fg(colors.color_at(byte_index).unwrap_or(Color::White));
+                    if self
+                        .selection
+                        .as_ref()
+                        .is_some_and(|selection| selection.contains(&byte_index))
+                    {
+                        style = style.bg(SELECTION_COLOR);
+                    }
+                    if self.matching_bracket == Some(byte_index) {
+                        style = style.bg(MATCH_COLOR).bold();
+                    }
+
+                    buf.set_string(
+                        area.x + (x - left) as u16,
+                        area.y + y as u16,
+                        char.to_string(),
+                        style,
+                    );
+                }
+
+                x += char_width;
+                byte_index += char.len_utf8();
             }
 
-            if let Some(c) = self
-                .colors
+            if let Some(diagnostic) = self
+                .diagnostics
                 .iter()
-                .find(|x| x.range.contains(&(index + start)))
+                .find(|diagnostic| diagnostic.line == line_index)
             {
-                style = style.fg(c.color);
-            } else {
-                style = style.fg(Color::White);
+                // the message starts two columns after the line, if that is on screen
+                let start = (x + 2).max(left);
+                if start < right {
+                    let (_, color) = severity_sign(diagnostic.severity);
+                    buf.set_stringn(
+                        area.x + (start - left) as u16,
+                        area.y + y as u16,
+                        diagnostic.message,
+                        right - start,
+                        Style::default().fg(color),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Looks up highlight colors for increasing byte positions in a single pass
+struct ColorCursor<'a> {
+    colors: &'a [HighlightInfo],
+    next: usize,
+    active: Vec<usize>,
+}
+
+impl<'a> ColorCursor<'a> {
+    /// `colors` must be sorted by start byte
+    fn new(colors: &'a [HighlightInfo]) -> Self {
+        Self {
+            colors,
+            next: 0,
+            active: Vec::new(),
+        }
+    }
+
+    /// Color of the first highlight containing `byte_index`.
+    /// Must be called with non decreasing byte indexes.
+    fn color_at(&mut self, byte_index: usize) -> Option<Color> {
+        while let Some(info) = self.colors.get(self.next) {
+            if info.range.start > byte_index {
+                break;
             }
+            self.active.push(self.next);
+            self.next += 1;
+        }
 
-            buf.set_string(x + area.x, y + area.y, char.to_string(), style);
-
-            x += 1;
-        }
-
-        //let colors = buf.highlight.colors(content.slice(..), range.clone());
-        //
-        //let mut text = Text::default();
-        //let mut buffer = ratatui::buffer::Buffer {
-        //    area,
-        //    content: todo!(),
-        //};
-        //
-        //for (index, line) in content
-        //    .slice(content.byte_to_line(range.start)..content.byte_to_line(range.end))
-        //    .lines()
-        //    .enumerate()
-        //{
-        //    let mut style = Style::default();
-        //
-        //    let mut render_line = Line::default();
-        //
-        //    for (index, byte) in line.bytes().enumerate() {
-        //        if let Some(color) = colors
-        //            .iter()
-        //            .find(|x| x.range.start <= index && index > x.range.end)
-        //        {
-        //            style = style.patch(color.color);
-        //        } else {
-        //            style = Style::default();
-        //        }
-        //
-        //        render_line.push_span(Span::styled(byte.to_string(), style));
-        //    }
-        //
-        //    text.push_line(render_line);
-        //}
+        let colors = self.colors;
+        self.active.retain(|&i| colors[i].range.end > byte_index);
+
+        self.active.first().map(|&i| colors[i].color)
     }
 }
 
@@ -302,6 +1600,8 @@ struct StatusLine<'a> {
     content: RopeSlice<'a>,
     cursor: &'a Cursor,
     mode: Mode,
+    loading: Option<Loading>,
+    readonly: bool,
 }
 
 impl Widget for StatusLine<'_> {
@@ -331,14 +1631,203 @@ impl Widget for StatusLine<'_> {
 
         let mode = Span::from(self.mode.to_string());
 
+        let loading = match self.loading {
+            Some(loading) => match loading.percent() {
+                Some(percent) => Span::from(format!("loading {percent}% ")),
+                None => Span::from(format!("loading {} KiB ", loading.loaded / 1024)),
+            },
+            None => Span::default(),
+        };
+
+        let readonly = match self.readonly {
+            true => Span::from("[RO] "),
+            false => Span::default(),
+        };
+
         let space = Span::from(format!(
             "{:>w$}",
             "",
-            w = buffer_layout[1].width as usize - line_info.width() - mode.width()
+            w = (buffer_layout[1].width as usize).saturating_sub(
+                line_info.width() + mode.width() + loading.width() + readonly.width()
+            )
         ));
 
-        let status_line = Line::from(vec![space, mode, line_info]);
+        let status_line = Line::from(vec![space, readonly, loading, mode, line_info]);
 
         buf.set_line(area.x, area.y, &status_line, area.width);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{MouseButton, MouseEventKind};
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::test_harness::{file, TestApp};
+
+    #[tokio::test]
+    async fn refuses_edits_while_loading() {
+        let content = "0123456789abcdef\n".repeat(LOAD_CHUNK_SIZE / 16);
+        let (dir, mut app) =
+            TestApp::with_files(&[("small.txt", "small\n"), ("large.txt", &content)]).await;
+
+        let path = dir.path().join("large.txt");
+        app.app.editor.open(&path, OpenAction::Replace).unwrap();
+        app.wait_for_job().await;
+        assert!(app.buffer().is_loading());
+
+        app.keys("ix").await;
+        let status = app.app.editor.status.as_ref().unwrap();
+        assert_eq!(status.text, "Buffer is still loading");
+        app.keys("<Esc>").await;
+
+        // nothing to undo that could bring back the partly loaded text
+        app.app.wait_for_loads().await;
+        app.keys("u").await;
+        assert!(!app.buffer().is_loading());
+        assert_eq!(app.text(), content);
+    }
+
+    #[tokio::test]
+    async fn click_places_cursor_past_wide_characters() {
+        let file = file("a世界b\nsecond\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        // right half of 界
+        app.mouse(MouseEventKind::Down(MouseButton::Left), 4, 0)
+            .await;
+        assert_eq!(app.cursor(), (0, 2));
+
+        // past the end of the line
+        app.mouse(MouseEventKind::Down(MouseButton::Left), 40, 1)
+            .await;
+        assert_eq!(app.cursor(), (1, 5));
+    }
+
+    #[tokio::test]
+    async fn drag_selects_in_visual_mode() {
+        let file = file("hello world\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.mouse(MouseEventKind::Down(MouseButton::Left), 2, 0)
+            .await;
+        app.mouse(MouseEventKind::Drag(MouseButton::Left), 6, 0)
+            .await;
+
+        assert_eq!(app.app.editor.mode, Mode::Visual);
+        let window = app.app.editor.windows.get_focused().unwrap();
+        assert_eq!(window.selection(app.buffer()), Some(2..7));
+
+        app.keys("<Esc>").await;
+        assert_eq!(app.app.editor.mode, Mode::Normal);
+        let window = app.app.editor.windows.get_focused().unwrap();
+        assert_eq!(window.selection(app.buffer()), None);
+    }
+
+    #[tokio::test]
+    async fn wheel_scrolls_and_keeps_cursor_in_view() {
+        let content: String = (0..100).map(|i| format!("line {i}\n")).collect();
+        let file = file(&content);
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        for _ in 0..10 {
+            app.mouse(MouseEventKind::ScrollDown, 0, 0).await;
+        }
+
+        let window = app.app.editor.windows.get_focused().unwrap();
+        assert_eq!(window.offset.vertical, 30);
+        assert!(app.screen_line(0).starts_with("line 30 "));
+        assert!(app.cursor().0 >= 30);
+    }
+
+    #[tokio::test]
+    async fn paste_is_one_undo_step() {
+        let file = file("end\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("ia").await;
+        app.event(Event::Paste("one\r\n  two ".to_string())).await;
+        app.keys("b<Esc>").await;
+        assert_eq!(app.text(), "aone\n  two bend\n");
+
+        app.keys("u").await;
+        assert_eq!(app.text(), "aone\n  two end\n");
+        app.keys("u").await;
+        assert_eq!(app.text(), "aend\n");
+        app.keys("u").await;
+        assert_eq!(app.text(), "end\n");
+
+        // Normal mode paste is its own step and ends on the last pasted character
+        app.event(Event::Paste("xy".to_string())).await;
+        assert_eq!(app.text(), "xyend\n");
+        assert_eq!(app.cursor(), (0, 1));
+
+        app.keys("u").await;
+        assert_eq!(app.text(), "end\n");
+        app.keys("<C-r>").await;
+        assert_eq!(app.text(), "xyend\n");
+    }
+
+    #[tokio::test]
+    async fn pastes_large_text() {
+        let file = file("\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        let text = "0123456789abcdef\n".repeat(512 * 1024);
+        app.event(Event::Paste(text.clone())).await;
+
+        assert_eq!(app.buffer().content().len_bytes(), text.len() + 1);
+        assert_eq!(app.cursor(), (512 * 1024 - 1, 16));
+    }
+
+    #[tokio::test]
+    async fn click_focuses_split() {
+        let first = file("first\n");
+        let second = file("second\n");
+        let mut app = TestApp::new(&[
+            "--clean",
+            "-O",
+            first.path().to_str().unwrap(),
+            second.path().to_str().unwrap(),
+        ])
+        .await;
+
+        app.mouse(MouseEventKind::Down(MouseButton::Left), 43, 0)
+            .await;
+
+        assert_eq!(app.text(), "second\n");
+        assert_eq!(app.cursor(), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn partly_loaded_buffer_is_not_written() {
+        let file = file("");
+        let mut bytes = "a\n".repeat(LOAD_CHUNK_SIZE).into_bytes();
+        bytes.extend_from_slice(b"\xff\n");
+        std::fs::write(file.path(), &bytes).unwrap();
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        assert!(app.buffer().incomplete);
+        assert!(app.buffer().content().len_bytes() < bytes.len());
+        assert!(app
+            .app
+            .editor
+            .status
+            .as_ref()
+            .unwrap()
+            .text
+            .starts_with("Loading stopped"));
+
+        app.keys(":w<CR>").await;
+        assert!(app
+            .app
+            .editor
+            .status
+            .as_ref()
+            .unwrap()
+            .text
+            .contains("partly loaded"));
+        assert_eq!(std::fs::read(file.path()).unwrap(), bytes);
+    }
+}
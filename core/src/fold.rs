@@ -0,0 +1,335 @@
+//! Ranges of lines closed into a single summary line
+
+use std::ops::Range;
+
+use color_eyre::eyre::{bail, Result};
+use ropey::Rope;
+
+use crate::{buffer::Buffer, components::Context, editor::Editor, movements};
+
+/// Closed folds of a buffer as line ranges, the end line excluded, possibly nested
+#[derive(Debug, Default)]
+pub struct Folds {
+    closed: Vec<Range<usize>>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    /// Whether a closed fold hides the line, the first line of a fold stays visible
+    pub fn hidden(&self, line: usize) -> bool {
+        self.closed
+            .iter()
+            .any(|fold| fold.start < line && line < fold.end)
+    }
+
+    /// Outermost closed fold shown as a summary at the line
+    pub fn closed_at(&self, line: usize) -> Option<Range<usize>> {
+        if self.hidden(line) {
+            return None;
+        }
+        self.closed
+            .iter()
+            .filter(|fold| fold.start == line)
+            .max_by_key(|fold| fold.end)
+            .cloned()
+    }
+
+    /// First line of the row showing a line, the summary line of the fold hiding it
+    pub fn row_start(&self, line: usize) -> usize {
+        self.closed
+            .iter()
+            .filter(|fold| fold.start < line && line < fold.end)
+            .map(|fold| fold.start)
+            .min()
+            .unwrap_or(line)
+    }
+
+    /// First line of the row below the one showing a line
+    pub fn next_row(&self, line: usize) -> usize {
+        let start = self.row_start(line);
+        self.closed_at(start).map_or(start + 1, |fold| fold.end)
+    }
+
+    /// First line of the row above the one showing a line
+    pub fn prev_row(&self, line: usize) -> Option<usize> {
+        let start = self.row_start(line);
+        (start > 0).then(|| self.row_start(start - 1))
+    }
+
+    /// Line shown on each of `height` rows from `first_line`, `None` past the last line
+    pub fn rows(&self, first_line: usize, height: usize, len_lines: usize) -> Vec<Option<usize>> {
+        let mut line = self.row_start(first_line);
+        (0..height)
+            .map(|_| {
+                let row = Some(line).filter(|&line| line < len_lines);
+                line = self.next_row(line);
+                row
+            })
+            .collect()
+    }
+
+    /// Rows from the one showing `from` down to the one showing `to`
+    pub fn rows_between(&self, from: usize, to: usize) -> usize {
+        if self.closed.is_empty() {
+            return to.saturating_sub(from);
+        }
+        let to = self.row_start(to);
+        let mut line = self.row_start(from);
+        let mut rows = 0;
+        while line < to {
+            line = self.next_row(line);
+            rows += 1;
+        }
+        rows
+    }
+
+    /// First line of the row `rows` above the one showing a line
+    pub fn rows_up(&self, line: usize, rows: usize) -> usize {
+        if self.closed.is_empty() {
+            return line.saturating_sub(rows);
+        }
+        let mut line = self.row_start(line);
+        for _ in 0..rows {
+            match self.prev_row(line) {
+                Some(prev) => line = prev,
+                None => break,
+            }
+        }
+        line
+    }
+
+    fn close(&mut self, fold: Range<usize>) {
+        if !self.closed.contains(&fold) {
+            self.closed.push(fold);
+        }
+    }
+
+    /// Open the closed folds containing the line, returns whether there were any
+    fn open(&mut self, line: usize) -> bool {
+        let len = self.closed.len();
+        self.closed.retain(|fold| !fold.contains(&line));
+        self.closed.len() != len
+    }
+
+    pub fn open_all(&mut self) {
+        self.closed.clear();
+    }
+
+    /// Keep the folds on their lines after `count` lines were inserted before line `at`
+    pub fn insert_lines(&mut self, at: usize, count: usize) {
+        for fold in self.closed.iter_mut() {
+            if fold.start >= at {
+                fold.start += count;
+                fold.end += count;
+            } else if fold.end > at {
+                fold.end += count;
+            }
+        }
+    }
+
+    /// Keep the folds on their lines after the lines were removed, folds losing their first
+    /// line are opened
+    pub fn remove_lines(&mut self, lines: Range<usize>) {
+        let count = lines.len();
+        self.closed.retain_mut(|fold| {
+            if fold.end <= lines.start {
+                true
+            } else if fold.start >= lines.end {
+                fold.start -= count;
+                fold.end -= count;
+                true
+            } else if fold.start < lines.start && lines.end <= fold.end {
+                fold.end -= count;
+                fold.len() > 1
+            } else {
+                false
+            }
+        });
+    }
+}
+
+/// Line ranges that can be folded: syntax nodes for Rust, indented blocks otherwise
+fn foldable(buf: &mut Buffer) -> Vec<Range<usize>> {
+    match buf.syntax() {
+        Some(highlight) => highlight.fold_ranges(),
+        None => indent_ranges(buf.content()),
+    }
+}
+
+/// Lines followed by more indented ones fold them, blank lines in between included
+fn indent_ranges(content: &Rope) -> Vec<Range<usize>> {
+    let indents: Vec<Option<usize>> = content
+        .lines()
+        .map(|line| {
+            let text = line.to_string();
+            let trimmed = text.trim_start();
+            (!trimmed.trim_end().is_empty()).then(|| text.len() - trimmed.len())
+        })
+        .collect();
+
+    let mut ranges = vec![];
+    // lines waiting for the end of their block, with their indentation
+    let mut open: Vec<(usize, usize)> = vec![];
+    let mut last_text_line = 0;
+
+    for (line, indent) in indents.iter().enumerate() {
+        let Some(indent) = *indent else {
+            continue;
+        };
+        while let Some(&(start, start_indent)) = open.last() {
+            if start_indent < indent {
+                break;
+            }
+            open.pop();
+            if last_text_line > start {
+                ranges.push(start..last_text_line + 1);
+            }
+        }
+        open.push((line, indent));
+        last_text_line = line;
+    }
+    for (start, _) in open.into_iter().rev() {
+        if last_text_line > start {
+            ranges.push(start..last_text_line + 1);
+        }
+    }
+
+    ranges.sort_by_key(|range| (range.start, usize::MAX - range.end));
+    ranges
+}
+
+/// Cursor line of the focused window and its buffer
+fn cursor_line(editor: &mut Editor) -> Result<(usize, &mut Buffer)> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let window_id = window.id;
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    let line = buf
+        .content()
+        .char_to_line(buf.get_cursor(window_id).range.start);
+    Ok((line, buf))
+}
+
+/// Close the innermost open fold at the cursor, `zc`
+pub fn close(editor: &mut Editor) -> Result<()> {
+    let (line, buf) = cursor_line(editor)?;
+    let line = buf.folds.row_start(line);
+
+    // a closed fold at the cursor closes the one around it
+    let fold = foldable(buf)
+        .into_iter()
+        .filter(|fold| fold.contains(&line) && !buf.folds.closed.contains(fold))
+        .min_by_key(|fold| fold.len());
+    let Some(fold) = fold else {
+        bail!("No fold found");
+    };
+
+    let start = fold.start;
+    buf.folds.close(fold);
+    movements::goto_line(&mut Context { editor }, start + 1, None);
+    Ok(())
+}
+
+/// Open the closed folds at the cursor, `zo`
+pub fn open(editor: &mut Editor) -> Result<()> {
+    let (line, buf) = cursor_line(editor)?;
+    if !buf.folds.open(line) {
+        bail!("No fold found");
+    }
+    Ok(())
+}
+
+/// Open the fold at the cursor if it is closed, close it otherwise, `za`
+pub fn toggle(editor: &mut Editor) -> Result<()> {
+    let (line, buf) = cursor_line(editor)?;
+    match buf.folds.closed_at(line).is_some() || buf.folds.hidden(line) {
+        true => open(editor),
+        false => close(editor),
+    }
+}
+
+/// Close every fold of the focused buffer, `zM`
+pub fn close_all(editor: &mut Editor) -> Result<()> {
+    let (line, buf) = cursor_line(editor)?;
+    for fold in foldable(buf) {
+        buf.folds.close(fold);
+    }
+    let start = buf.folds.row_start(line);
+    movements::goto_line(&mut Context { editor }, start + 1, None);
+    Ok(())
+}
+
+/// Open every fold of the focused buffer, `zR`
+pub fn open_all(editor: &mut Editor) -> Result<()> {
+    let (_, buf) = cursor_line(editor)?;
+    buf.folds.open_all();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    #[test]
+    fn folds_indented_blocks() {
+        let content = Rope::from("a:\n  b\n\n  c:\n    d\ne\n");
+
+        assert_eq!(indent_ranges(&content), vec![0..5, 3..5]);
+    }
+
+    #[tokio::test]
+    async fn closes_functions_and_skips_them() {
+        let text =
+            "fn one() {\n    let a = 1;\n    let b = 2;\n}\n\nfn two() {\n    let c = 3;\n}\n";
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", text)]).await;
+
+        app.keys("jzc").await;
+        assert_eq!(app.cursor(), (0, 0));
+        assert!(app.screen_line(0).starts_with("+-- 4 lines: fn one() {"));
+        assert!(app.screen_line(1).trim().is_empty());
+        assert!(app.screen_line(2).starts_with("fn two() {"));
+
+        // moving down steps over the closed lines
+        app.keys("j").await;
+        assert_eq!(app.cursor(), (4, 0));
+        app.keys("k").await;
+        assert_eq!(app.cursor(), (0, 0));
+
+        app.keys("zajjjjjjzc").await;
+        assert_eq!(app.cursor(), (5, 0));
+        assert!(app.screen_line(5).starts_with("+-- 3 lines: fn two() {"));
+
+        // every fold of the buffer
+        app.keys("zRzM").await;
+        assert!(app.screen_line(0).starts_with("+-- 4 lines: fn one() {"));
+        assert!(app.screen_line(2).starts_with("+-- 3 lines: fn two() {"));
+
+        // folds stay on their lines when lines are added above
+        app.keys("kki<CR><Esc>").await;
+        assert!(app.screen_line(1).starts_with("+-- 4 lines: fn one() {"));
+        assert!(app.screen_line(3).starts_with("+-- 3 lines: fn two() {"));
+    }
+
+    #[tokio::test]
+    async fn operators_cover_closed_folds() {
+        let text = "fn one() {\n    a();\n}\nb();\nc();\n";
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", text)]).await;
+
+        app.keys("jzcgcc").await;
+        assert_eq!(app.text(), "// fn one() {\n//     a();\n// }\nb();\nc();\n");
+
+        // a motion from the fold goes to the row below it
+        app.keys("gccgcj").await;
+        assert_eq!(
+            app.text(),
+            "// fn one() {\n//     a();\n// }\n// b();\nc();\n"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{bail, eyre, Result};
+use tokio::{process::Command, time::timeout};
+
+use crate::{
+    buffer::BufferId,
+    components::{Components, Context},
+    config::Formatter,
+    diff,
+    editor::Editor,
+    shell,
+};
+
+/// Format a buffer in the background with the formatter configured for its language.
+///
+/// The output is applied as minimal edits in one undo step so cursors stay on
+/// the text they were on, then `then` gets the outcome on the main loop. The
+/// buffer is left untouched when the formatter fails or the text changed in the
+/// meantime. Returns false, without calling `then`, when no formatter is configured.
+pub fn format_buffer(
+    editor: &mut Editor,
+    buffer_id: BufferId,
+    then: impl FnOnce(Result<()>, &mut Components, &mut Context) + Send + 'static,
+) -> bool {
+    let Some(buf) = editor.buffers.get(buffer_id) else {
+        return false;
+    };
+    let Some(formatter) = buf
+        .language()
+        .and_then(|language| editor.config.formatters.get(language.name))
+        .cloned()
+    else {
+        return false;
+    };
+
+    let input = buf.content().to_string();
+    editor.jobs.spawn_then(
+        run(formatter, input.clone()),
+        move |output, components, context| {
+            let result =
+                output.and_then(|output| apply(context.editor, buffer_id, &input, &output));
+            then(result, components, context);
+        },
+    );
+
+    true
+}
+
+/// Replace `input` with the formatted `output` in one undo step
+fn apply(editor: &mut Editor, buffer_id: BufferId, input: &str, output: &str) -> Result<()> {
+    let Some(buf) = editor.buffers.get_mut(buffer_id) else {
+        bail!("Buffer was closed while formatting");
+    };
+    if *buf.content() != input {
+        bail!("Text changed while formatting, not replaced");
+    }
+
+    let edits = diff::edits(input, output);
+    if edits.is_empty() {
+        return Ok(());
+    }
+
+    buf.commit();
+    let result = buf.apply_edits(&edits);
+    buf.commit();
+    result
+}
+
+/// Feed `input` to the formatter and return what it printed, failing on a
+/// nonzero exit or once its timeout passed
+pub async fn run(formatter: Formatter, input: String) -> Result<String> {
+    let mut command = Command::new(&formatter.command);
+    command.args(&formatter.args).kill_on_drop(true);
+
+    // dropping the run on timeout kills the formatter
+    let limit = Duration::from_millis(formatter.timeout);
+    let output = match timeout(limit, shell::run(command, Some(input.clone()))).await {
+        Ok(output) => output.map_err(|err| eyre!("Failed to run {}: {err}", formatter.command))?,
+        Err(_) => bail!(
+            "{} timed out after {}ms",
+            formatter.command,
+            formatter.timeout
+        ),
+    };
+
+    if !output.success() {
+        bail!("{} failed: {}", formatter.command, output.error_message());
+    }
+
+    // most likely a formatter writing files instead of stdout
+    if output.stdout.is_empty() && !input.is_empty() {
+        bail!("{} printed nothing", formatter.command);
+    }
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    fn formatter(command: &str, args: &[&str]) -> Formatter {
+        Formatter {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            timeout: 2000,
+        }
+    }
+
+    /// Format Rust files with `formatter`
+    fn set_formatter(app: &mut TestApp, formatter: Formatter) {
+        let formatters = &mut app.app.editor.config.formatters;
+        formatters.insert("rust".to_string(), formatter);
+    }
+
+    #[tokio::test]
+    async fn formats_keeping_the_cursor_on_its_text() {
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", "a  b\nkeep\n")]).await;
+        set_formatter(&mut app, formatter("sed", &["s/  / /"]));
+
+        app.keys("jl:format<CR>").await;
+        app.wait_for_job().await;
+
+        assert_eq!(app.text(), "a b\nkeep\n");
+        assert_eq!(app.cursor(), (1, 1));
+
+        app.keys("u").await;
+        assert_eq!(app.text(), "a  b\nkeep\n");
+    }
+
+    #[tokio::test]
+    async fn failing_formatter_leaves_buffer() {
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", "a  b\n")]).await;
+        set_formatter(&mut app, formatter("sh", &["-c", "echo bad >&2; exit 1"]));
+
+        app.keys(":format<CR>").await;
+        app.wait_for_job().await;
+        assert_eq!(app.text(), "a  b\n");
+        assert_eq!(
+            app.app.editor.status.as_ref().unwrap().text,
+            "sh failed: shell returned 1: bad"
+        );
+
+        let mut slow = formatter("sleep", &["5"]);
+        slow.timeout = 50;
+        assert_eq!(
+            run(slow, "text".to_string()).await.unwrap_err().to_string(),
+            "sleep timed out after 50ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn formats_on_save() {
+        let (dir, mut app) = TestApp::with_files(&[("main.rs", "a  b\n")]).await;
+        set_formatter(&mut app, formatter("sed", &["s/  / /"]));
+        app.app.editor.config.format_on_save = true;
+
+        app.keys(":w<CR>").await;
+        app.wait_for_job().await;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("main.rs")).unwrap(),
+            "a b\n"
+        );
+
+        // a failing formatter still writes the text as it is
+        set_formatter(&mut app, formatter("sh", &["-c", "echo bad >&2; exit 1"]));
+        app.keys("ix<Esc>:wq<CR>").await;
+        app.wait_for_job().await;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("main.rs")).unwrap(),
+            "xa b\n"
+        );
+        assert!(app.app.editor.should_quit());
+    }
+}
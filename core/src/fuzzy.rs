@@ -0,0 +1,134 @@
+//! Fuzzy matching of typed patterns against candidates, e.g. `mnrs` against `main.rs`
+
+const MATCH: i64 = 16;
+/// Bonus for a match right after the previous one
+const CONSECUTIVE: i64 = 12;
+/// Bonus for a match at the start of a word or path component
+const BOUNDARY: i64 = 10;
+/// Penalty per skipped char between two matches
+const GAP: i64 = 1;
+/// Most skipped chars before the first match that are penalized
+const MAX_LEADING_GAP: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Higher is better
+    pub score: i64,
+    /// Chars of the candidate matched by the pattern
+    pub indices: Vec<usize>,
+}
+
+/// Match `pattern` as a subsequence of `candidate`.
+///
+/// Case is ignored unless the pattern contains an uppercase letter.
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<Match> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    if pattern.is_empty() {
+        return Some(Match {
+            score: 0,
+            indices: vec![],
+        });
+    }
+
+    let ignore_case = !pattern.iter().any(|char| char.is_uppercase());
+    let eq = |a: char, b: char| match ignore_case {
+        true => a == b || a.to_lowercase().eq(b.to_lowercase()),
+        false => a == b,
+    };
+
+    // first the earliest end of a match, then walk back for its latest start
+    // to find a tight window, then match greedily inside it
+    let mut next = 0;
+    let mut end = None;
+    for (index, &char) in candidate.iter().enumerate() {
+        if next < pattern.len() && eq(char, pattern[next]) {
+            next += 1;
+            if next == pattern.len() {
+                end = Some(index);
+                break;
+            }
+        }
+    }
+    let end = end?;
+
+    let mut remaining = pattern.len();
+    let mut start = end;
+    for index in (0..=end).rev() {
+        if eq(candidate[index], pattern[remaining - 1]) {
+            remaining -= 1;
+            if remaining == 0 {
+                start = index;
+                break;
+            }
+        }
+    }
+
+    let mut indices = Vec::with_capacity(pattern.len());
+    for (index, &char) in candidate.iter().enumerate().take(end + 1).skip(start) {
+        if indices.len() < pattern.len() && eq(char, pattern[indices.len()]) {
+            indices.push(index);
+        }
+    }
+
+    let mut score = -(start.min(MAX_LEADING_GAP) as i64) * GAP;
+    for (n, &index) in indices.iter().enumerate() {
+        score += MATCH;
+        if is_boundary(&candidate, index) {
+            score += BOUNDARY;
+        }
+        if candidate[index] == pattern[n] {
+            score += 1;
+        }
+        if n > 0 {
+            let previous = indices[n - 1];
+            match index - previous {
+                1 => score += CONSECUTIVE,
+                distance => score -= (distance - 1) as i64 * GAP,
+            }
+        }
+    }
+
+    Some(Match { score, indices })
+}
+
+fn is_boundary(candidate: &[char], index: usize) -> bool {
+    let Some(&previous) = index.checked_sub(1).and_then(|index| candidate.get(index)) else {
+        return true;
+    };
+    let char = candidate[index];
+
+    matches!(previous, '/' | '\\' | '_' | '-' | '.' | ' ' | ':')
+        || (previous.is_lowercase() && char.is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn score(pattern: &str, candidate: &str) -> i64 {
+        fuzzy_match(pattern, candidate).unwrap().score
+    }
+
+    #[test]
+    fn ranks_tight_and_boundary_matches_first() {
+        assert_eq!(
+            fuzzy_match("mnrs", "src/main.rs").unwrap().indices,
+            vec![4, 7, 9, 10]
+        );
+        assert_eq!(fuzzy_match("xyz", "main.rs"), None);
+
+        assert!(score("main", "main.rs") > score("main", "my_animal_in"));
+        assert!(score("fb", "foo_bar") > score("fb", "fabric"));
+        assert!(score("ab", "xxab") > score("ab", "axxb"));
+    }
+
+    #[test]
+    fn uppercase_makes_matching_case_sensitive() {
+        assert!(fuzzy_match("hm", "HashMap").is_some());
+        assert!(fuzzy_match("HM", "HashMap").is_some());
+        assert!(fuzzy_match("HM", "hashmap").is_none());
+    }
+}
@@ -0,0 +1,704 @@
+//! Changes of buffers compared to the git index, shown as signs next to the text
+
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::{bail, Result};
+use ropey::Rope;
+use tokio::process::Command;
+
+use crate::{
+    buffer::{Buffer, BufferId, LARGE_FILE_THRESHOLD},
+    components::Context,
+    diff::{line_hunks, lines, rediff, LineHunk},
+    editor::{Editor, OpenAction},
+    movements, shell,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HunkKind {
+    Added,
+    Changed,
+    Removed,
+}
+
+/// Lines of the index replaced by lines of the buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl LineHunk for Hunk {
+    fn from_lines([old, new]: [Range<usize>; 2]) -> Self {
+        Self { old, new }
+    }
+
+    fn lines(&self) -> [Range<usize>; 2] {
+        [self.old.clone(), self.new.clone()]
+    }
+
+    fn lines_mut(&mut self) -> [&mut Range<usize>; 2] {
+        [&mut self.old, &mut self.new]
+    }
+}
+
+impl Hunk {
+    pub fn kind(&self) -> HunkKind {
+        match (self.old.is_empty(), self.new.is_empty()) {
+            (true, _) => HunkKind::Added,
+            (_, true) => HunkKind::Removed,
+            _ => HunkKind::Changed,
+        }
+    }
+
+    /// Line the hunk is shown on, removed lines are marked on the line above
+    pub fn first_line(&self) -> usize {
+        match self.new.is_empty() {
+            true => self.new.start.saturating_sub(1),
+            false => self.new.start,
+        }
+    }
+
+    /// Whether the hunk is shown on `line`
+    pub fn contains(&self, line: usize) -> bool {
+        self.new.contains(&line) || self.first_line() == line
+    }
+}
+
+/// Diff of a buffer against the version of its file in the index
+pub struct Diff {
+    /// Path from the repository root, used in patches
+    repo_path: String,
+    /// Lines in the index, with their line breaks
+    base: Vec<String>,
+    /// Lines of the buffer as of `version`
+    lines: Vec<String>,
+    /// Buffer version the hunks were computed for
+    version: Option<usize>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl Diff {
+    fn new(repo_path: String, base: &str) -> Self {
+        Self {
+            repo_path,
+            base: base.split_inclusive('\n').map(String::from).collect(),
+            lines: vec![],
+            version: None,
+            hunks: vec![],
+        }
+    }
+
+    /// Follow a changed content, only the changed lines are diffed again
+    fn update(&mut self, content: &Rope, version: usize) {
+        match self.version.replace(version) {
+            Some(previous) if previous == version => {}
+            Some(_) => {
+                rediff(&self.base, &mut self.lines, content, &mut self.hunks, 1);
+            }
+            None => {
+                self.lines = lines(content);
+                self.hunks = line_hunks(&self.base, &self.lines);
+            }
+        }
+    }
+
+    /// Unified diff of one hunk without context, for `git apply --unidiff-zero`
+    fn patch(&self, hunk: &Hunk, content: &Rope) -> String {
+        // with no lines a range starts at the line before it
+        let start = |range: &Range<usize>| match range.is_empty() {
+            true => range.start,
+            false => range.start + 1,
+        };
+        let old_start = start(&hunk.old);
+        let new_start = start(&(hunk.old.start..hunk.old.start + hunk.new.len()));
+
+        let path = &self.repo_path;
+        let mut patch = format!(
+            "diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n@@ -{old_start},{} +{new_start},{} @@\n",
+            hunk.old.len(),
+            hunk.new.len()
+        );
+
+        let mut push_line = |sign: char, line: &str| {
+            patch.push(sign);
+            patch.push_str(line);
+            if !line.ends_with('\n') {
+                patch.push_str("\n\\ No newline at end of file\n");
+            }
+        };
+        for line in &self.base[hunk.old.clone()] {
+            push_line('-', line);
+        }
+        for line in hunk.new.clone() {
+            push_line('+', &content.line(line).to_string());
+        }
+
+        patch
+    }
+}
+
+fn git(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).kill_on_drop(true);
+    command
+}
+
+/// Directory and file name git is asked about
+fn location(buf: &Buffer) -> Option<(PathBuf, String)> {
+    let path = buf.file_path()?;
+    let name = path.file_name()?.to_str()?.to_string();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Some((dir, name))
+}
+
+/// Path from the repository root and content in the index, `None` for untracked files
+async fn read_index(dir: &Path, name: &str) -> Option<(String, String)> {
+    let mut prefix = git(dir);
+    prefix.args(["rev-parse", "--show-prefix"]);
+    let prefix = shell::run(prefix, None).await.ok()?;
+
+    let mut show = git(dir);
+    show.arg("show").arg(format!(":./{name}"));
+    let base = shell::run(show, None).await.ok()?;
+
+    if !prefix.success() || !base.success() {
+        return None;
+    }
+    Some((format!("{}{name}", prefix.stdout.trim()), base.stdout))
+}
+
+/// Read the index version of the buffer's file in the background to diff against it
+pub fn attach(editor: &mut Editor, buffer_id: BufferId) {
+    let Some(buf) = editor.buffers.get(buffer_id) else {
+        return;
+    };
+    if buf.content().len_bytes() > LARGE_FILE_THRESHOLD {
+        return;
+    }
+    let Some((dir, name)) = location(buf) else {
+        return;
+    };
+
+    let tx = editor.jobs.sender();
+    let running = editor.jobs.running().start();
+    tokio::spawn(async move {
+        // nothing to show for untracked files
+        if let Some((repo_path, base)) = read_index(&dir, &name).await {
+            let _ = tx.send(Box::new(move |_, context| {
+                set_base(context.editor, buffer_id, repo_path, &base);
+            }));
+        }
+        drop(running);
+    });
+}
+
+fn set_base(editor: &mut Editor, buffer_id: BufferId, repo_path: String, base: &str) {
+    if let Some(buf) = editor.buffers.get_mut(buffer_id) {
+        let mut diff = Diff::new(repo_path, base);
+        diff.update(buf.content(), buf.version());
+        buf.git = Some(diff);
+    }
+}
+
+/// Recompute the hunks of buffers changed since the last call
+pub fn update(editor: &mut Editor) {
+    for buf in editor.buffers.iter_mut() {
+        let version = buf.version();
+        let content = buf.content().clone();
+        if let Some(diff) = buf.git.as_mut() {
+            diff.update(&content, version);
+        }
+        if let Some(blame) = buf.blame.as_mut() {
+            blame.update(&content, version);
+        }
+    }
+}
+
+/// Hunk shown on the cursor line of the focused window
+fn hunk_at_cursor(editor: &Editor) -> Result<(BufferId, Hunk)> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let Some(diff) = buf.git.as_ref() else {
+        bail!("Not tracked by git");
+    };
+
+    let line = buf
+        .content()
+        .char_to_line(buf.get_cursor(window.id).range.start);
+    match diff.hunks.iter().find(|hunk| hunk.contains(line)) {
+        Some(hunk) => Ok((buf.id, hunk.clone())),
+        None => bail!("No hunk at the cursor"),
+    }
+}
+
+/// Move the cursor to the start of the next or previous hunk
+pub fn goto_hunk(editor: &mut Editor, forward: bool) -> Result<()> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let hunks = buf
+        .git
+        .as_ref()
+        .map(|diff| &diff.hunks[..])
+        .unwrap_or_default();
+    let line = buf
+        .content()
+        .char_to_line(buf.get_cursor(window.id).range.start);
+
+    let target = match forward {
+        true => hunks
+            .iter()
+            .map(Hunk::first_line)
+            .find(|&start| start > line),
+        false => hunks
+            .iter()
+            .map(Hunk::first_line)
+            .rfind(|&start| start < line),
+    };
+    let Some(target) = target else {
+        bail!("No more hunks");
+    };
+
+    movements::goto_line(&mut Context { editor }, target + 1, None);
+    Ok(())
+}
+
+/// Removed and added lines of the hunk at the cursor
+pub fn preview_hunk(editor: &Editor) -> Result<String> {
+    let (buffer_id, hunk) = hunk_at_cursor(editor)?;
+    let buf = editor.buffers.get(buffer_id).unwrap();
+    let diff = buf.git.as_ref().unwrap();
+
+    let removed = diff.base[hunk.old.clone()]
+        .iter()
+        .map(|line| format!("-{}", line.trim_end_matches('\n')));
+    let added = hunk.new.clone().map(|line| {
+        let line = buf.content().line(line).to_string();
+        format!("+{}", line.trim_end_matches('\n'))
+    });
+
+    Ok(removed.chain(added).collect::<Vec<_>>().join("\n"))
+}
+
+/// Replace the hunk at the cursor with the lines in the index, as one undo step
+pub fn revert_hunk(editor: &mut Editor) -> Result<()> {
+    let (buffer_id, hunk) = hunk_at_cursor(editor)?;
+    let buf = editor.buffers.get_mut(buffer_id).unwrap();
+    let text = buf.git.as_ref().unwrap().base[hunk.old.clone()].concat();
+
+    let content = buf.content();
+    let start = content.line_to_char(hunk.new.start.min(content.len_lines()));
+    let end = content.line_to_char(hunk.new.end.min(content.len_lines()));
+
+    buf.commit();
+    let result = buf
+        .remove(start..end)
+        .and_then(|_| buf.insert(start, &text));
+    buf.commit();
+    result?;
+
+    movements::goto_line(&mut Context { editor }, hunk.new.start + 1, None);
+    Ok(())
+}
+
+/// Add the hunk at the cursor to the index in the background
+pub fn stage_hunk(editor: &mut Editor) -> Result<()> {
+    let (buffer_id, hunk) = hunk_at_cursor(editor)?;
+    let buf = editor.buffers.get(buffer_id).unwrap();
+    let diff = buf.git.as_ref().unwrap();
+    let patch = diff.patch(&hunk, buf.content());
+    let Some((dir, name)) = location(buf) else {
+        bail!("No file name");
+    };
+
+    let tx = editor.jobs.sender();
+    tokio::spawn(async move {
+        let mut apply = git(&dir);
+        apply.args(["apply", "--cached", "--unidiff-zero", "-"]);
+        let result = shell::run(apply, Some(patch)).await;
+        let index = read_index(&dir, &name).await;
+
+        let _ = tx.send(Box::new(move |_, context| {
+            let editor = &mut context.editor;
+            match result {
+                Ok(output) if output.success() => editor.set_status("Hunk staged"),
+                Ok(output) => editor.set_error(output.error_message()),
+                Err(err) => editor.set_error(format!("Failed to run git: {err}")),
+            }
+            if let Some((repo_path, base)) = index {
+                set_base(editor, buffer_id, repo_path, &base);
+            }
+        }));
+    });
+
+    Ok(())
+}
+
+/// Commit that last changed a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameLine {
+    pub hash: String,
+    pub author: String,
+    /// Seconds since the Unix epoch
+    pub time: i64,
+}
+
+impl BlameLine {
+    /// Lines not committed yet are blamed on a hash of zeros
+    pub fn is_committed(&self) -> bool {
+        self.hash.bytes().any(|byte| byte != b'0')
+    }
+
+    /// Short hash, author and relative date, as shown next to the line
+    pub fn label(&self, now: i64) -> String {
+        if !self.is_committed() {
+            return "Not committed yet".to_string();
+        }
+        let author: String = self.author.chars().take(BLAME_AUTHOR_WIDTH).collect();
+        format!(
+            "{} {author:<BLAME_AUTHOR_WIDTH$} {}",
+            &self.hash[..self.hash.len().min(7)],
+            relative_date(now - self.time)
+        )
+    }
+}
+
+/// Columns of the author name in blame labels
+const BLAME_AUTHOR_WIDTH: usize = 12;
+
+/// `3 days ago` for an age in seconds
+fn relative_date(seconds: i64) -> String {
+    let units = [
+        (365 * 24 * 3600, "year"),
+        (30 * 24 * 3600, "month"),
+        (7 * 24 * 3600, "week"),
+        (24 * 3600, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ];
+    for (length, unit) in units {
+        let count = seconds / length;
+        match count {
+            0 => continue,
+            1 => return format!("1 {unit} ago"),
+            _ => return format!("{count} {unit}s ago"),
+        }
+    }
+    "just now".to_string()
+}
+
+/// Lines of `git blame --porcelain` mapped to the commits that last changed them
+fn parse_blame(output: &str) -> Vec<BlameLine> {
+    let mut commits: HashMap<&str, (String, i64)> = HashMap::new();
+    let mut lines = vec![];
+    let mut hash = "";
+
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            let (author, time) = commits.get(hash).cloned().unwrap_or_default();
+            lines.push(BlameLine {
+                hash: hash.to_string(),
+                author,
+                time,
+            });
+        } else if let Some(author) = line.strip_prefix("author ") {
+            commits.entry(hash).or_default().0 = author.to_string();
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            commits.entry(hash).or_default().1 = time.parse().unwrap_or_default();
+        } else if let Some((first, _)) = line.split_once(' ') {
+            // a header starts with the hash of the line's commit
+            if first.len() == 40 && first.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                hash = first;
+            }
+        }
+    }
+
+    lines
+}
+
+/// Commits of the lines of a buffer, kept aligned with its lines while it is edited
+pub struct Blame {
+    /// Lines of the blamed content
+    blamed: Vec<String>,
+    entries: Vec<BlameLine>,
+    /// Lines of the buffer at `version`
+    lines: Vec<String>,
+    /// Buffer version the alignment was computed for
+    version: Option<usize>,
+    /// Blamed lines replaced by lines edited since, ordered
+    hunks: Vec<Hunk>,
+}
+
+impl Blame {
+    fn new(blamed: Vec<String>, entries: Vec<BlameLine>) -> Self {
+        Self {
+            blamed,
+            entries,
+            lines: vec![],
+            version: None,
+            hunks: vec![],
+        }
+    }
+
+    /// Follow a changed content, only the changed lines are diffed again
+    fn update(&mut self, content: &Rope, version: usize) {
+        match self.version.replace(version) {
+            Some(previous) if previous == version => {}
+            Some(_) => {
+                rediff(&self.blamed, &mut self.lines, content, &mut self.hunks, 1);
+            }
+            None => {
+                self.lines = lines(content);
+                self.hunks = line_hunks(&self.blamed, &self.lines);
+            }
+        }
+    }
+
+    /// Commit of a line of the buffer, `None` when edited since blaming
+    pub fn line(&self, line: usize) -> Option<&BlameLine> {
+        if line >= self.lines.len() {
+            return None;
+        }
+        let index = self.hunks.partition_point(|hunk| hunk.new.end <= line);
+        if self
+            .hunks
+            .get(index)
+            .is_some_and(|hunk| hunk.new.contains(&line))
+        {
+            return None;
+        }
+
+        // between hunks the lines are a fixed offset apart
+        let blamed = match index {
+            0 => line,
+            _ => {
+                let hunk = &self.hunks[index - 1];
+                line + hunk.old.end - hunk.new.end
+            }
+        };
+        self.entries.get(blamed)
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Show or hide the commits of the lines of the focused buffer next to them
+pub fn toggle_blame(editor: &mut Editor) -> Result<()> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    if buf.blame.take().is_some() {
+        return Ok(());
+    }
+    let Some((dir, name)) = location(buf) else {
+        bail!("No file name");
+    };
+
+    // blame the buffer as it is, lines not saved yet show as not committed
+    let buffer_id = buf.id;
+    let content = buf.content().clone();
+    let tx = editor.jobs.sender();
+    tokio::spawn(async move {
+        let mut blame = git(&dir);
+        blame
+            .args(["blame", "--porcelain", "--contents", "-", "--"])
+            .arg(&name);
+        let result = shell::run(blame, Some(content.to_string())).await;
+
+        let _ = tx.send(Box::new(move |_, context| {
+            let editor = &mut context.editor;
+            let output = match result {
+                Ok(output) if output.success() => output,
+                Ok(output) => return editor.set_error(output.error_message()),
+                Err(err) => return editor.set_error(format!("Failed to run git: {err}")),
+            };
+            if let Some(buf) = editor.buffers.get_mut(buffer_id) {
+                let mut blame = Blame::new(lines(&content), parse_blame(&output.stdout));
+                blame.update(buf.content(), buf.version());
+                buf.blame = Some(blame);
+            }
+        }));
+    });
+
+    Ok(())
+}
+
+/// Open the changes of the commit of the cursor line in a read-only scratch buffer
+pub fn show_blamed_commit(editor: &mut Editor) -> Result<()> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let Some(blame) = buf.blame.as_ref() else {
+        bail!("No blame shown, run :blame first");
+    };
+    let line = buf
+        .content()
+        .char_to_line(buf.get_cursor(window.id).range.start);
+    let Some(entry) = blame.line(line).filter(|entry| entry.is_committed()) else {
+        bail!("Not committed yet");
+    };
+    let Some((dir, _)) = location(buf) else {
+        bail!("No file name");
+    };
+
+    let hash = entry.hash.clone();
+    let tx = editor.jobs.sender();
+    tokio::spawn(async move {
+        let mut show = git(&dir);
+        show.args(["show", "--no-color", &hash]);
+        let result = shell::run(show, None).await;
+
+        let _ = tx.send(Box::new(move |_, context| {
+            let editor = &mut context.editor;
+            let output = match result {
+                Ok(output) if output.success() => output,
+                Ok(output) => return editor.set_error(output.error_message()),
+                Err(err) => return editor.set_error(format!("Failed to run git: {err}")),
+            };
+
+            let buffer_id = editor.buffers.add(Rope::from(output.stdout), None);
+            editor.buffers.get_mut(buffer_id).unwrap().readonly = true;
+            editor.show_buffer(buffer_id, OpenAction::HorizontalSplit);
+            editor.set_status(format!("git show {}", &hash[..7]));
+        }));
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use super::attach;
+    use crate::test_harness::TestApp;
+
+    fn run_git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Put the files of `dir` in the index of a new repository and wait for the signs
+    async fn track(dir: &Path, app: &mut TestApp) {
+        run_git(dir, &["init", "--quiet"]);
+        run_git(dir, &["add", "."]);
+
+        let buffer_id = app.buffer().id;
+        attach(&mut app.app.editor, buffer_id);
+        app.app.wait_for_jobs().await;
+        assert!(app.buffer().git.is_some());
+    }
+
+    #[tokio::test]
+    async fn shows_signs_and_moves_between_hunks() {
+        let (dir, mut app) = TestApp::with_files(&[("notes.txt", "one\ntwo\nthree\nfour\n")]).await;
+        track(dir.path(), &mut app).await;
+
+        app.keys("jinew<CR><Esc>jjix<Esc>").await;
+        assert_eq!(app.text(), "one\nnew\ntwo\nthree\nxfour\n");
+        assert!(app.screen_line(1).starts_with(" +new"));
+        assert!(app.screen_line(4).starts_with(" ~xfour"));
+
+        app.keys("[c").await;
+        assert_eq!(app.cursor(), (1, 0));
+        app.keys("]c").await;
+        assert_eq!(app.cursor(), (4, 0));
+        app.keys("]c").await;
+        assert_eq!(
+            app.app.editor.status.as_ref().unwrap().text,
+            "No more hunks"
+        );
+
+        app.keys(":previewhunk<CR>").await;
+        assert!((0..24).any(|y| app.screen_line(y).contains("-four")));
+        assert!((0..24).any(|y| app.screen_line(y).contains("+xfour")));
+
+        app.keys("<Esc>:reverthunk<CR>").await;
+        assert_eq!(app.text(), "one\nnew\ntwo\nthree\nfour\n");
+        app.keys("u").await;
+        assert_eq!(app.text(), "one\nnew\ntwo\nthree\nxfour\n");
+    }
+
+    #[tokio::test]
+    async fn stages_the_hunk_at_the_cursor() {
+        let (dir, mut app) = TestApp::with_files(&[("notes.txt", "one\ntwo\nthree\n")]).await;
+        track(dir.path(), &mut app).await;
+
+        app.keys("jinew<CR><Esc>jix<Esc>").await;
+        assert_eq!(app.text(), "one\nnew\ntwo\nxthree\n");
+        app.keys("kk:stagehunk<CR>").await;
+        app.wait_for_job().await;
+        assert_eq!(app.app.editor.status.as_ref().unwrap().text, "Hunk staged");
+
+        let staged = run_git(dir.path(), &["show", ":notes.txt"]);
+        assert_eq!(staged, "one\nnew\ntwo\nthree\n");
+        // only the unstaged change is left
+        assert_eq!(app.buffer().git.as_ref().unwrap().hunks.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn blames_lines_and_opens_their_commit() {
+        let (dir, mut app) = TestApp::with_files(&[("notes.txt", "one\ntwo\n")]).await;
+        track(dir.path(), &mut app).await;
+        let author = [
+            "-c",
+            "user.name=Ann Author",
+            "-c",
+            "user.email=ann@example.com",
+        ];
+        run_git(
+            dir.path(),
+            &[&author[..], &["commit", "--quiet", "-m", "First lines"]].concat(),
+        );
+        let hash = run_git(dir.path(), &["rev-parse", "--short=7", "HEAD"]);
+
+        app.keys(":blame<CR>").await;
+        app.wait_for_job().await;
+        let label = format!("{} Ann Author", hash.trim());
+        assert!(app.screen_line(0).starts_with(&label));
+        assert!(app.screen_line(0).contains("one"));
+
+        // the panel stays aligned with the lines it blames
+        app.keys("inew<CR><Esc>").await;
+        assert!(app.screen_line(0).starts_with("Not committed yet"));
+        assert!(app.screen_line(1).starts_with(&label));
+        assert!(app.screen_line(1).contains("one"));
+
+        app.keys("u").await;
+        assert!(app.screen_line(0).starts_with(&label));
+        assert!(app.screen_line(1).contains("two"));
+
+        app.keys(":blamecommit<CR>").await;
+        app.wait_for_job().await;
+        assert!(app.buffer().readonly);
+        assert!(app.text().contains("First lines"));
+        assert!(app.text().contains("+two"));
+    }
+}
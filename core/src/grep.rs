@@ -0,0 +1,509 @@
+//! Regex search of the files below a directory, streamed into a picker
+
+use std::{
+    cell::Cell,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use ignore::{WalkBuilder, WalkState};
+use ratatui::{
+    layout::Rect,
+    style::Style,
+    text::Line,
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+use regex::{Regex, RegexBuilder};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    buffer::LARGE_FILE_THRESHOLD,
+    components::{Component, Context, EventPropagation, Position},
+    editor::Editor,
+    fuzzy,
+    jobs::{JobCallback, Running, RunningGuard},
+    picker::{highlighted, overlay_area},
+    popup::{BACKGROUND, SELECTED},
+    quickfix::{self, QuickfixEntry},
+    terminal::{Event, Frame},
+};
+
+/// Matches collected by one search, it stops once they are found
+const MAX_RESULTS: usize = 10_000;
+/// Longest line text kept for a match
+const MAX_LINE_CHARS: usize = 300;
+/// Bytes checked for a NUL to skip binary files
+const BINARY_CHECK_BYTES: usize = 8 * 1024;
+/// Least time between two redraws while results stream in
+const REDRAW_INTERVAL: Duration = Duration::from_millis(30);
+
+/// Line matching the pattern
+pub struct Found {
+    pub entry: QuickfixEntry,
+    /// Chars of the entry's text matched by the pattern
+    pub matched: Range<usize>,
+}
+
+/// Search running on background threads, stopped once dropped
+struct Search {
+    results: Arc<Mutex<Vec<Found>>>,
+    done: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Pattern matching case insensitively unless it contains an uppercase letter
+pub fn smart_case_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(!pattern.chars().any(char::is_uppercase))
+        .build()
+}
+
+impl Search {
+    /// Search the files below `root` that are not ignored, in parallel
+    fn start(
+        root: &Path,
+        regex: Regex,
+        tx: UnboundedSender<JobCallback>,
+        running: RunningGuard,
+    ) -> Self {
+        let search = Self {
+            results: Arc::default(),
+            done: Arc::default(),
+            cancelled: Arc::default(),
+        };
+
+        let walk_root = match root.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => root.to_path_buf(),
+        };
+        let results = search.results.clone();
+        let done = search.done.clone();
+        let cancelled = search.cancelled.clone();
+        let last_redraw = Arc::new(Mutex::new(Instant::now()));
+        let redraw = move || {
+            let job: JobCallback = Box::new(|_, _| {});
+            let _ = tx.send(job);
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let walk = WalkBuilder::new(&walk_root)
+                // ignore files apply outside of git repositories too
+                .require_git(false)
+                .build_parallel();
+
+            walk.run(|| {
+                let (regex, results, cancelled) = (&regex, &results, &cancelled);
+                let (last_redraw, redraw) = (&last_redraw, &redraw);
+                Box::new(move |entry| {
+                    if cancelled.load(Ordering::Relaxed) {
+                        return WalkState::Quit;
+                    }
+                    let Ok(entry) = entry else {
+                        return WalkState::Continue;
+                    };
+                    if !entry
+                        .file_type()
+                        .is_some_and(|file_type| file_type.is_file())
+                    {
+                        return WalkState::Continue;
+                    }
+
+                    let found = search_file(entry.path(), regex);
+                    if found.is_empty() {
+                        return WalkState::Continue;
+                    }
+
+                    let mut results = results.lock().unwrap();
+                    let room = MAX_RESULTS - results.len();
+                    let full = found.len() >= room;
+                    results.extend(found.into_iter().take(room));
+                    drop(results);
+
+                    let mut last_redraw = last_redraw.lock().unwrap();
+                    if last_redraw.elapsed() >= REDRAW_INTERVAL {
+                        *last_redraw = Instant::now();
+                        redraw();
+                    }
+
+                    match full {
+                        true => WalkState::Quit,
+                        false => WalkState::Continue,
+                    }
+                })
+            });
+
+            done.store(true, Ordering::Relaxed);
+            redraw();
+            drop(running);
+        });
+
+        search
+    }
+}
+
+impl Drop for Search {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Matching lines of a text file, the first match of each
+fn search_file(path: &Path, regex: &Regex) -> Vec<Found> {
+    let too_large =
+        std::fs::metadata(path).map_or(true, |meta| meta.len() > LARGE_FILE_THRESHOLD as u64);
+    if too_large {
+        return vec![];
+    }
+    let Ok(bytes) = std::fs::read(path) else {
+        return vec![];
+    };
+    if bytes[..bytes.len().min(BINARY_CHECK_BYTES)].contains(&0) {
+        return vec![];
+    }
+    let Ok(text) = std::str::from_utf8(&bytes) else {
+        return vec![];
+    };
+
+    let path = path.strip_prefix(".").unwrap_or(path);
+    text.lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let found = regex.find(line)?;
+            let start = line[..found.start()].chars().count();
+            let end = start + found.as_str().chars().count();
+
+            Some(Found {
+                entry: QuickfixEntry {
+                    path: path.to_path_buf(),
+                    line: index + 1,
+                    column: start + 1,
+                    text: line.chars().take(MAX_LINE_CHARS).collect(),
+                },
+                matched: start..end,
+            })
+        })
+        .collect()
+}
+
+/// Picker searching as the pattern is typed.
+///
+/// Enter jumps to the selected match. Once closed the matches are the quickfix
+/// list, to step through them with `:cnext` and `:cprev`.
+pub struct GrepPicker {
+    root: PathBuf,
+    query: String,
+    search: Option<Search>,
+    /// Why the pattern could not be searched
+    error: Option<String>,
+    selected: usize,
+    /// Results sorted so far, they arrive unordered from the threads
+    sorted: Cell<usize>,
+    tx: UnboundedSender<JobCallback>,
+    running: Running,
+}
+
+impl GrepPicker {
+    /// Picker searching `pattern` below `root`, an empty path searches the working directory
+    pub fn new(root: &Path, pattern: &str, editor: &Editor) -> Self {
+        let mut picker = Self {
+            root: root.to_path_buf(),
+            query: pattern.to_string(),
+            search: None,
+            error: None,
+            selected: 0,
+            sorted: Cell::new(0),
+            tx: editor.jobs.sender(),
+            running: editor.jobs.running(),
+        };
+        picker.restart();
+        picker
+    }
+
+    /// Search the current query, dropping the previous search
+    fn restart(&mut self) {
+        self.search = None;
+        self.error = None;
+        self.selected = 0;
+        self.sorted.set(0);
+        if self.query.is_empty() {
+            return;
+        }
+
+        match smart_case_regex(&self.query) {
+            Ok(regex) => {
+                self.search = Some(Search::start(
+                    &self.root,
+                    regex,
+                    self.tx.clone(),
+                    self.running.start(),
+                ))
+            }
+            Err(_) => self.error = Some("Invalid pattern".to_string()),
+        }
+    }
+
+    /// Results ordered by file and position
+    fn results(&self) -> Option<MutexGuard<'_, Vec<Found>>> {
+        let mut results = self.search.as_ref()?.results.lock().unwrap();
+        if self.sorted.get() != results.len() {
+            results.sort_by(|a, b| {
+                (&a.entry.path, a.entry.line, a.entry.column).cmp(&(
+                    &b.entry.path,
+                    b.entry.line,
+                    b.entry.column,
+                ))
+            });
+            self.sorted.set(results.len());
+        }
+        Some(results)
+    }
+
+    fn move_selection(&mut self, forward: bool) {
+        let len = self.results().map_or(0, |results| results.len());
+        if len == 0 {
+            return;
+        }
+        self.selected = match forward {
+            true => (self.selected + 1) % len,
+            false => (self.selected + len - 1) % len,
+        };
+    }
+
+    /// Close the picker, making the results the quickfix list, and jump to the selected one
+    fn close(&self, jump: bool) -> EventPropagation {
+        let entries: Vec<QuickfixEntry> = self
+            .results()
+            .map(|results| results.iter().map(|found| found.entry.clone()).collect())
+            .unwrap_or_default();
+        let selected = self.selected;
+        let title = format!("grep {}", self.query);
+
+        EventPropagation::Consume(Some(Box::new(move |components, context| {
+            components.pop();
+            if entries.is_empty() {
+                return;
+            }
+
+            context.editor.quickfix.set(title, entries);
+            if jump {
+                if let Err(err) = quickfix::jump(context.editor, selected) {
+                    context.editor.set_error(err.to_string());
+                }
+            }
+        })))
+    }
+}
+
+impl Component for GrepPicker {
+    fn handle_events(&mut self, event: &Event, _context: &mut Context) -> EventPropagation {
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Paste(text) => {
+                self.query.push_str(text.lines().next().unwrap_or_default());
+                self.restart();
+                return EventPropagation::Consume(None);
+            }
+            _ => return EventPropagation::Consume(None),
+        };
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (key.code, ctrl) {
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), true) => return self.close(false),
+            (KeyCode::Enter, _) => return self.close(true),
+            (KeyCode::Char('n'), true) | (KeyCode::Down | KeyCode::Tab, _) => {
+                self.move_selection(true)
+            }
+            (KeyCode::Char('p'), true) | (KeyCode::Up | KeyCode::BackTab, _) => {
+                self.move_selection(false)
+            }
+            (KeyCode::Backspace, _) => {
+                self.query.pop();
+                self.restart();
+            }
+            (KeyCode::Char(char), false) => {
+                self.query.push(char);
+                self.restart();
+            }
+            _ => {}
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    fn cursor(&self, area: Rect, _editor: &mut Editor) -> Option<Position> {
+        let area = overlay_area(area);
+        Some(Position {
+            x: area.x as usize + 3 + self.query.chars().count(),
+            y: area.bottom().saturating_sub(2) as usize,
+        })
+    }
+
+    fn render(&self, f: &mut Frame<'_>, area: Rect, _context: &mut Context) {
+        let area = overlay_area(area);
+        let results = self.results();
+
+        let title = match (&self.error, &results) {
+            (Some(error), _) => format!(" Grep: {error} "),
+            (None, Some(results)) => {
+                let searching = match self.search.as_ref().unwrap().done.load(Ordering::Relaxed) {
+                    true => "",
+                    false => "…",
+                };
+                format!(" Grep {} results{searching} ", results.len())
+            }
+            (None, None) => " Grep ".to_string(),
+        };
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .style(Style::default().bg(BACKGROUND));
+        let inner = block.inner(area);
+        f.render_widget(Clear, area);
+        f.render_widget(block, area);
+        if inner.height == 0 {
+            return;
+        }
+
+        // the pattern is typed on the last line, results are listed above it
+        let visible = inner.height.saturating_sub(1) as usize;
+        let first = (self.selected + 1).saturating_sub(visible);
+        let lines: Vec<Line> = results
+            .iter()
+            .flat_map(|results| results.iter())
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .map(|(row, found)| {
+                let entry = QuickfixEntry {
+                    path: found
+                        .entry
+                        .path
+                        .strip_prefix(&self.root)
+                        .unwrap_or(&found.entry.path)
+                        .to_path_buf(),
+                    ..found.entry.clone()
+                };
+                let label = entry.label();
+                let prefix = label.chars().count() - entry.text.chars().count();
+                let matched = fuzzy::Match {
+                    score: 0,
+                    indices: (found.matched.start + prefix..found.matched.end + prefix).collect(),
+                };
+
+                let line = highlighted(&label, &matched);
+                match row == self.selected {
+                    true => line.style(Style::default().bg(SELECTED)),
+                    false => line,
+                }
+            })
+            .collect();
+        f.render_widget(Paragraph::new(lines), inner);
+
+        let prompt_area = Rect {
+            y: inner.bottom() - 1,
+            height: 1,
+            ..inner
+        };
+        f.render_widget(Paragraph::new(format!("> {}", self.query)), prompt_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::test_harness::TestApp;
+
+    fn screen_contains(app: &mut TestApp, text: &str) -> bool {
+        (0..24).any(|y| app.screen_line(y).contains(text))
+    }
+
+    /// Project with an ignored build directory, the app starts on `src/b.rs`
+    const PROJECT: &[(&str, &str)] = &[
+        ("src/b.rs", "Alpha\n"),
+        ("src/a.rs", "fn alpha() {}\nlet b = alpha();\n"),
+        ("target/c.rs", "alpha\n"),
+        (".gitignore", "target/\n"),
+    ];
+
+    #[tokio::test]
+    async fn jumps_to_matches_and_steps_through_them() {
+        let (dir, mut app) = TestApp::with_files(PROJECT).await;
+
+        app.keys(&format!(":grepin {} alpha<CR>", dir.path().display()))
+            .await;
+        while !screen_contains(&mut app, "Grep 3 results ") {
+            app.wait_for_job().await;
+        }
+
+        app.keys("<CR>").await;
+        assert!(app.buffer().file_path().unwrap().ends_with("src/a.rs"));
+        assert_eq!(app.cursor(), (0, 3));
+
+        app.keys(":cnext<CR>").await;
+        assert_eq!(app.cursor(), (1, 8));
+
+        app.keys(":cn<CR>").await;
+        assert!(app.buffer().file_path().unwrap().ends_with("src/b.rs"));
+        assert_eq!(app.cursor(), (0, 0));
+
+        app.keys(":cn<CR>").await;
+        assert_eq!(
+            app.app.editor.status.as_ref().unwrap().text,
+            "No more items"
+        );
+
+        app.keys(":cprev<CR>").await;
+        assert!(app.buffer().file_path().unwrap().ends_with("src/a.rs"));
+        assert_eq!(app.cursor(), (1, 8));
+    }
+
+    #[tokio::test]
+    async fn searches_again_while_typing() {
+        let (dir, mut app) = TestApp::with_files(PROJECT).await;
+
+        // an uppercase letter makes the search case sensitive
+        app.keys(&format!(":grepin {} Al<CR>", dir.path().display()))
+            .await;
+        while !screen_contains(&mut app, "Grep 1 results ") {
+            app.wait_for_job().await;
+        }
+        assert!(screen_contains(&mut app, "src/b.rs:1:1: Alpha"));
+
+        app.keys("(").await;
+        assert!(screen_contains(&mut app, "Grep: Invalid pattern"));
+
+        app.keys("<BS><BS><BS>let").await;
+        while !screen_contains(&mut app, "Grep 1 results ") {
+            app.wait_for_job().await;
+        }
+        assert!(screen_contains(&mut app, "src/a.rs:2:1: let b = alpha();"));
+
+        // the results stay in the quickfix list
+        app.keys("<Esc>").await;
+        assert_eq!(app.app.editor.quickfix.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn takes_the_whole_argument_as_pattern() {
+        let (dir, mut app) = TestApp::with_files(PROJECT).await;
+
+        app.keys(&format!(":grepin {} let b =<CR>", dir.path().display()))
+            .await;
+        while !screen_contains(&mut app, "Grep 1 results ") {
+            app.wait_for_job().await;
+        }
+        assert!(screen_contains(&mut app, "src/a.rs:2:1: let b = alpha();"));
+
+        // the working directory when no directory is given
+        app.keys("<Esc>:grep fn alpha<CR>").await;
+        assert!(screen_contains(&mut app, "│> fn alpha "));
+    }
+}
@@ -0,0 +1,187 @@
+//! Indentation of lines following the syntax tree, or copied from the line above without one
+
+use std::ops::Range;
+
+use color_eyre::eyre::{bail, Result};
+use ropey::Rope;
+use syntax::Highlight;
+use text::next_grapheme_boundary;
+
+use crate::{buffer::Buffer, cursor::Cursor, editor::Editor};
+
+/// Leading spaces and tabs of a line
+pub fn leading(content: &Rope, line: usize) -> String {
+    content
+        .line(line)
+        .chars()
+        .take_while(|char| *char == ' ' || *char == '\t')
+        .collect()
+}
+
+fn is_blank(content: &Rope, line: usize) -> bool {
+    content.line(line).chars().all(char::is_whitespace)
+}
+
+/// Closest line above with text
+fn line_above(content: &Rope, line: usize) -> Option<usize> {
+    (0..line).rev().find(|&above| !is_blank(content, above))
+}
+
+/// One level of indentation, a tab when `indent` is made of tabs
+pub fn unit(indent: &str, width: usize) -> String {
+    match indent.starts_with('\t') {
+        true => "\t".to_string(),
+        false => " ".repeat(width),
+    }
+}
+
+/// Indentation of a line from the one of the line `above`, a level more or less for every
+/// indent level the syntax tree puts between them
+fn relative(
+    content: &Rope,
+    syntax: Option<&Highlight>,
+    line: usize,
+    above: Option<(usize, &str)>,
+    width: usize,
+) -> String {
+    let base = above.map_or("", |(_, indent)| indent);
+    let Some(syntax) = syntax else {
+        return base.to_string();
+    };
+
+    let levels = syntax.indent_level(content.slice(..), line) as isize
+        - above.map_or(0, |(above, _)| {
+            syntax.indent_level(content.slice(..), above)
+        }) as isize;
+    let unit = unit(base, width);
+
+    let mut indent = base.to_string();
+    if levels > 0 {
+        indent.push_str(&unit.repeat(levels as usize));
+    }
+    for _ in levels..0 {
+        indent.truncate(indent.len().saturating_sub(unit.len()));
+    }
+    indent
+}
+
+/// Indentation for a line, e.g. one just opened
+pub fn indentation(buf: &mut Buffer, line: usize, width: usize) -> String {
+    let content = buf.content().clone();
+    let above = line_above(&content, line).map(|above| (above, leading(&content, above)));
+    let above = above
+        .as_ref()
+        .map(|(line, indent)| (*line, indent.as_str()));
+    relative(&content, buf.syntax(), line, above, width)
+}
+
+/// Indent the lines with text again, each following the indentation given to the one above.
+///
+/// The cursor stays on its char.
+pub fn reindent(editor: &mut Editor, lines: Range<usize>) -> Result<()> {
+    let width = editor.config.indent_width;
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    if buf.readonly {
+        bail!("Cannot modify, buffer is read-only");
+    }
+    let content = buf.content().clone();
+    let cursor = buf.get_cursor(window.id).range.start;
+    let cursor_line = content.char_to_line(cursor);
+    let syntax = buf.syntax();
+
+    let mut above = line_above(&content, lines.start).map(|line| (line, leading(&content, line)));
+    let mut changes = vec![];
+    for line in lines.filter(|&line| !is_blank(&content, line)) {
+        let current = above
+            .as_ref()
+            .map(|(line, indent)| (*line, indent.as_str()));
+        let indent = relative(&content, syntax, line, current, width);
+        let old = leading(&content, line);
+        if indent != old {
+            changes.push((line, old.chars().count(), indent.clone()));
+        }
+        above = Some((line, indent));
+    }
+
+    // from the bottom, so the lines above keep their chars
+    let mut shift = 0;
+    for (line, old, indent) in changes.into_iter().rev() {
+        let start = content.line_to_char(line);
+        if old > 0 {
+            buf.remove(start..start + old)?;
+        }
+        if !indent.is_empty() {
+            buf.insert(start, &indent)?;
+        }
+        if line == cursor_line {
+            shift = indent.chars().count() as isize - old as isize;
+        }
+    }
+
+    let column = cursor - content.line_to_char(cursor_line);
+    let content = buf.content().slice(..);
+    let pos = content.line_to_char(cursor_line) + (column as isize + shift).max(0) as usize;
+    buf.set_cursor(
+        window.id,
+        Cursor {
+            range: pos..next_grapheme_boundary(&content, pos),
+        },
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::test_harness::{file, TestApp};
+
+    #[tokio::test]
+    async fn indents_new_lines_and_closers() {
+        let (_dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn main() {\n    let a = [1];\n}\n")]).await;
+
+        app.keys("j0llllllllllllli<CR><Esc>li<CR><Esc>").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    let a = [\n        1\n    ];\n}\n"
+        );
+        assert_eq!(app.cursor(), (3, 4));
+
+        // a closer typed on a blank line goes back to its opener
+        let (_dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn main() {\n    if a {\n        b\n\n}\n")]).await;
+        app.keys("jjji        }<Esc>").await;
+        assert_eq!(app.text(), "fn main() {\n    if a {\n        b\n    }\n}\n");
+
+        // without a syntax tree the line above is copied
+        let file = file("  ab\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+        app.keys("$hi<CR><Esc>").await;
+        assert_eq!(app.text(), "  a\n  b\n");
+    }
+
+    #[tokio::test]
+    async fn reindents_lines() {
+        let text = "fn main() {\nlet a = [\n1,\n];\nif a {\nb\n}\n}\n";
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", text)]).await;
+
+        app.keys("j=j").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    let a = [\n        1,\n];\nif a {\nb\n}\n}\n"
+        );
+
+        app.keys("jjvjjjj=").await;
+        assert_eq!(
+            app.text(),
+            "fn main() {\n    let a = [\n        1,\n    ];\n    if a {\n        b\n    }\n}\n"
+        );
+
+        app.keys("u").await;
+        assert!(app.text().ends_with("];\nif a {\nb\n}\n}\n"));
+    }
+}
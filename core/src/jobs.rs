@@ -0,0 +1,114 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::components::{Components, Context};
+
+/// Callback sent back to the main loop by a background job.
+///
+/// Runs on the main thread with full access to the editor and components.
+pub type JobCallback = Box<dyn FnOnce(&mut Components, &mut Context) + Send>;
+
+/// Counts the jobs whose result is still to come, so a headless run can wait for them.
+///
+/// Long lived tasks, like language servers, are not counted.
+#[derive(Clone, Default)]
+pub struct Running(Arc<AtomicUsize>);
+
+impl Running {
+    /// Count a job as running until the guard is dropped, after it sent its last callback
+    pub fn start(&self) -> RunningGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        RunningGuard(self.0.clone())
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.0.load(Ordering::SeqCst) == 0
+    }
+}
+
+pub struct RunningGuard(Arc<AtomicUsize>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Channel used by background tasks to hand results back to the event loop
+pub struct Jobs {
+    tx: UnboundedSender<JobCallback>,
+    rx: UnboundedReceiver<JobCallback>,
+    running: Running,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            tx,
+            rx,
+            running: Running::default(),
+        }
+    }
+
+    /// Sender that can be moved into a background task
+    pub fn sender(&self) -> UnboundedSender<JobCallback> {
+        self.tx.clone()
+    }
+
+    /// Run a job in the background and hand its callback to the main loop once done
+    pub fn spawn(&self, job: impl Future<Output = JobCallback> + Send + 'static) {
+        let tx = self.sender();
+        let guard = self.running.start();
+
+        tokio::spawn(async move {
+            let _ = tx.send(job.await);
+            drop(guard);
+        });
+    }
+
+    /// Run a future in the background and apply its output with `then` on the main loop
+    pub fn spawn_then<T: Send + 'static>(
+        &self,
+        job: impl Future<Output = T> + Send + 'static,
+        then: impl FnOnce(T, &mut Components, &mut Context) + Send + 'static,
+    ) {
+        self.spawn(async move {
+            let output = job.await;
+            let callback: JobCallback =
+                Box::new(move |components, context| then(output, components, context));
+            callback
+        });
+    }
+
+    pub fn running(&self) -> Running {
+        self.running.clone()
+    }
+
+    /// Whether every counted job sent its callbacks
+    pub fn is_idle(&self) -> bool {
+        self.running.is_idle()
+    }
+
+    pub async fn next(&mut self) -> Option<JobCallback> {
+        self.rx.recv().await
+    }
+
+    /// Next callback if a job already finished
+    pub fn try_next(&mut self) -> Option<JobCallback> {
+        self.rx.try_recv().ok()
+    }
+}
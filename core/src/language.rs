@@ -0,0 +1,120 @@
+use std::path::Path;
+
+/// Language known to the editor, selected by file name
+#[derive(Debug, PartialEq, Eq)]
+pub struct Language {
+    /// Name used to configure the language, e.g. `rust`
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Full file names, for files without a telling extension
+    pub file_names: &'static [&'static str],
+    /// Openers of auto-pairs left alone, e.g. `'` also starting Rust lifetimes
+    pub unpaired: &'static [char],
+    /// Token starting a comment running to the end of the line, e.g. `//`
+    pub line_comment: Option<&'static str>,
+    /// Tokens around a block comment, used by `gc` when there is no line comment
+    pub block_comment: Option<(&'static str, &'static str)>,
+}
+
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        extensions: &["rs"],
+        file_names: &[],
+        unpaired: &['\''],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "toml",
+        extensions: &["toml"],
+        file_names: &["Cargo.lock"],
+        unpaired: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+    },
+    Language {
+        name: "json",
+        extensions: &["json", "json5"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "python",
+        extensions: &["py", "pyi"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+    },
+    Language {
+        name: "javascript",
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "typescript",
+        extensions: &["ts", "mts", "cts", "tsx"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "go",
+        extensions: &["go"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "c",
+        extensions: &["c", "h"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "cpp",
+        extensions: &["cpp", "cc", "cxx", "hpp", "hh"],
+        file_names: &[],
+        unpaired: &[],
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+    },
+    Language {
+        name: "shell",
+        extensions: &["sh", "bash", "zsh"],
+        file_names: &[".bashrc", ".zshrc", ".envrc"],
+        unpaired: &[],
+        line_comment: Some("#"),
+        block_comment: None,
+    },
+    Language {
+        name: "markdown",
+        extensions: &["md", "markdown"],
+        file_names: &[],
+        unpaired: &['\''],
+        line_comment: None,
+        block_comment: Some(("<!--", "-->")),
+    },
+];
+
+impl Language {
+    pub fn from_path(path: &Path) -> Option<&'static Language> {
+        let file_name = path.file_name()?.to_str()?;
+        let extension = path.extension().and_then(|extension| extension.to_str());
+
+        LANGUAGES.iter().find(|language| {
+            language.file_names.contains(&file_name)
+                || extension.is_some_and(|extension| language.extensions.contains(&extension))
+        })
+    }
+}
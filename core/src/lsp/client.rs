@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use color_eyre::eyre::{eyre, Result};
+use lsp_types::{
+    notification::{self, Notification},
+    request::Request,
+    PublishDiagnosticsParams, ShowMessageParams,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufReader},
+    process::{Child, Command},
+    sync::{mpsc, oneshot},
+};
+
+use super::transport::{read_message, write_message};
+use crate::{config::LanguageServer, jobs::JobCallback};
+
+type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value>>>>>;
+
+/// Connection to a running language server.
+///
+/// Messages are written by a background task in the order they were sent, so
+/// a request always sees the changes notified before it.
+pub struct Client {
+    /// Command the server was started with, used in messages
+    pub name: String,
+    outgoing: mpsc::UnboundedSender<Value>,
+    pending: PendingRequests,
+    next_id: AtomicU64,
+    /// Killed when the client is dropped
+    _process: Option<Child>,
+}
+
+impl Client {
+    /// Spawn the server process in `root` and talk to it over stdio
+    pub fn start(
+        server: &LanguageServer,
+        root: &Path,
+        jobs: mpsc::UnboundedSender<JobCallback>,
+    ) -> Result<Self> {
+        let mut process = Command::new(&server.command)
+            .args(&server.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| eyre!("Failed to start {}: {err}", server.command))?;
+
+        let stdin = process.stdin.take().unwrap();
+        let stdout = process.stdout.take().unwrap();
+
+        let mut client = Self::connect(&server.command, stdout, stdin, jobs);
+        client._process = Some(process);
+        Ok(client)
+    }
+
+    /// Talk to a server over any pair of streams
+    pub fn connect(
+        name: &str,
+        reader: impl AsyncRead + Unpin + Send + 'static,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+        jobs: mpsc::UnboundedSender<JobCallback>,
+    ) -> Self {
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<Value>();
+        let pending = PendingRequests::default();
+
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let mut writer = writer;
+            while let Some(message) = outgoing_rx.recv().await {
+                if write_message(&mut writer, &message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        tokio::spawn({
+            let pending = pending.clone();
+            let outgoing = outgoing.clone();
+            let name = name.clone();
+            async move {
+                let mut reader = BufReader::new(reader);
+                loop {
+                    match read_message(&mut reader).await {
+                        Ok(Some(message)) => {
+                            handle_message(message, &pending, &outgoing, &jobs);
+                        }
+                        Ok(None) => break,
+                        Err(err) => {
+                            tracing::error!("Invalid message from {name}: {err}");
+                            break;
+                        }
+                    }
+                }
+
+                // waiting requests fail once their senders are gone
+                pending.lock().unwrap().clear();
+                tracing::warn!("{name} exited");
+            }
+        });
+
+        Self {
+            name,
+            outgoing,
+            pending,
+            next_id: AtomicU64::new(0),
+            _process: None,
+        }
+    }
+
+    /// Send a request right away and return a future resolving to its response
+    pub fn request<R: Request>(
+        &self,
+        params: R::Params,
+    ) -> impl Future<Output = Result<R::Result>> + Send + 'static {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let sent = self
+            .outgoing
+            .send(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": R::METHOD,
+                "params": params,
+            }))
+            .is_ok();
+        let name = self.name.clone();
+
+        async move {
+            let exited = || eyre!("{name} exited");
+            if !sent {
+                return Err(exited());
+            }
+            let result = rx.await.map_err(|_| exited())??;
+            Ok(serde_json::from_value(result)?)
+        }
+    }
+
+    pub fn notify<N: Notification>(&self, params: N::Params) {
+        let _ = self.outgoing.send(json!({
+            "jsonrpc": "2.0",
+            "method": N::METHOD,
+            "params": params,
+        }));
+    }
+}
+
+/// Route a message from the server to the request waiting for it, or to the
+/// main loop
+fn handle_message(
+    message: Value,
+    pending: &PendingRequests,
+    outgoing: &mpsc::UnboundedSender<Value>,
+    jobs: &mpsc::UnboundedSender<JobCallback>,
+) {
+    let id = message.get("id").cloned();
+    let method = message.get("method").and_then(Value::as_str);
+
+    match (id, method) {
+        // response
+        (Some(id), None) => {
+            let Some(tx) = id
+                .as_u64()
+                .and_then(|id| pending.lock().unwrap().remove(&id))
+            else {
+                return;
+            };
+            let result = match message.get("error") {
+                Some(error) => Err(eyre!(
+                    "{}",
+                    error["message"].as_str().unwrap_or("request failed")
+                )),
+                None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+            };
+            let _ = tx.send(result);
+        }
+        // request from the server, answered with empty results so it isn't left waiting
+        (Some(id), Some(method)) => {
+            let result = match method {
+                "workspace/configuration" => {
+                    let items = message["params"]["items"].as_array().map_or(0, Vec::len);
+                    Value::Array(vec![Value::Null; items])
+                }
+                _ => Value::Null,
+            };
+            let _ = outgoing.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+        }
+        (None, Some(method)) => {
+            if let Some(callback) = handle_notification(method, message["params"].clone()) {
+                let _ = jobs.send(callback);
+            }
+        }
+        (None, None) => {}
+    }
+}
+
+fn handle_notification(method: &str, params: Value) -> Option<JobCallback> {
+    match method {
+        notification::PublishDiagnostics::METHOD => {
+            let params: PublishDiagnosticsParams = serde_json::from_value(params).ok()?;
+            Some(Box::new(move |_, context| {
+                super::set_diagnostics(context.editor, params);
+            }))
+        }
+        notification::ShowMessage::METHOD => {
+            let params: ShowMessageParams = serde_json::from_value(params).ok()?;
+            Some(Box::new(move |_, context| {
+                context.editor.set_status(params.message);
+            }))
+        }
+        _ => None,
+    }
+}
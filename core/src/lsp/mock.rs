@@ -0,0 +1,211 @@
+//! Tiny language server answering from the words of the open documents.
+//!
+//! It backs the client tests and is built as the `mock-lsp` binary to try the
+//! client by hand. Every `TODO` is reported as a warning, hover shows the word
+//! under the cursor, its first occurrence is the definition and every
+//! occurrence in an open document is a reference. Completion always offers
+//! `mock_completion`.
+
+use std::{collections::HashMap, io};
+
+use ropey::Rope;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+
+use super::transport::{read_message, write_message};
+
+/// Answer messages until the client sends `exit` or closes the stream
+pub async fn serve(
+    reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut documents: HashMap<String, Rope> = HashMap::new();
+
+    while let Some(message) = read_message(&mut reader).await? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 2,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "renameProvider": true,
+                    "completionProvider": {},
+                }
+            }),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                documents.insert(uri.clone(), Rope::from_str(text));
+                publish_diagnostics(&mut writer, &uri, &documents[&uri]).await?;
+                continue;
+            }
+            "textDocument/didChange" => {
+                let document = documents.entry(uri.clone()).or_default();
+                for change in params["contentChanges"].as_array().into_iter().flatten() {
+                    apply_change(document, change);
+                }
+                publish_diagnostics(&mut writer, &uri, document).await?;
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                continue;
+            }
+            "textDocument/hover" => match word_at(&documents, params) {
+                Some(word) => json!({
+                    "contents": {
+                        "kind": "markdown",
+                        "value": format!("```\n{word}\n```\n{} characters", word.chars().count()),
+                    }
+                }),
+                None => Value::Null,
+            },
+            "textDocument/definition" => match word_at(&documents, params) {
+                Some(word) => occurrences(&documents[&uri], &word)
+                    .first()
+                    .map(|range| json!({ "uri": uri, "range": range }))
+                    .unwrap_or(Value::Null),
+                None => Value::Null,
+            },
+            "textDocument/references" => match word_at(&documents, params) {
+                Some(word) => {
+                    let mut uris: Vec<&String> = documents.keys().collect();
+                    uris.sort();
+                    uris.into_iter()
+                        .flat_map(|uri| {
+                            occurrences(&documents[uri], &word)
+                                .into_iter()
+                                .map(move |range| json!({ "uri": uri, "range": range }))
+                        })
+                        .collect()
+                }
+                None => Value::Null,
+            },
+            "textDocument/rename" => match word_at(&documents, params) {
+                Some(word) => {
+                    let edits: Vec<Value> = occurrences(&documents[&uri], &word)
+                        .into_iter()
+                        .map(|range| json!({ "range": range, "newText": params["newName"] }))
+                        .collect();
+                    json!({ "changes": { uri: edits } })
+                }
+                None => Value::Null,
+            },
+            "textDocument/completion" => json!([{ "label": "mock_completion" }]),
+            "exit" => return Ok(()),
+            _ => Value::Null,
+        };
+
+        // notifications don't get a response
+        if let Some(id) = message.get("id") {
+            let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            write_message(&mut writer, &response).await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn publish_diagnostics(
+    writer: &mut (impl AsyncWrite + Unpin),
+    uri: &str,
+    document: &Rope,
+) -> io::Result<()> {
+    let diagnostics: Vec<Value> = occurrences(document, "TODO")
+        .into_iter()
+        .map(|range| json!({ "range": range, "severity": 2, "message": "unfinished work" }))
+        .collect();
+
+    let notification = json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    });
+    write_message(writer, &notification).await
+}
+
+fn apply_change(document: &mut Rope, change: &Value) {
+    let text = change["text"].as_str().unwrap_or_default();
+
+    if change.get("range").is_none() {
+        *document = Rope::from_str(text);
+        return;
+    }
+
+    let start = char_index(document, &change["range"]["start"]);
+    let end = char_index(document, &change["range"]["end"]);
+    document.remove(start..end);
+    document.insert(start, text);
+}
+
+fn char_index(document: &Rope, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    if line >= document.len_lines() {
+        return document.len_chars();
+    }
+
+    let utf16 = document.char_to_utf16_cu(document.line_to_char(line)) + character;
+    document.utf16_cu_to_char(utf16.min(document.len_utf16_cu()))
+}
+
+fn position(document: &Rope, char_idx: usize) -> Value {
+    let line = document.char_to_line(char_idx);
+    let line_start = document.char_to_utf16_cu(document.line_to_char(line));
+    json!({
+        "line": line,
+        "character": document.char_to_utf16_cu(char_idx) - line_start,
+    })
+}
+
+fn is_word_char(char: char) -> bool {
+    char.is_alphanumeric() || char == '_'
+}
+
+/// Word at the position of a request
+fn word_at(documents: &HashMap<String, Rope>, params: &Value) -> Option<String> {
+    let document = documents.get(params["textDocument"]["uri"].as_str()?)?;
+    let char_idx = char_index(document, &params["position"]);
+
+    let mut start = char_idx;
+    while start > 0 && is_word_char(document.char(start - 1)) {
+        start -= 1;
+    }
+    let mut end = char_idx;
+    while end < document.len_chars() && is_word_char(document.char(end)) {
+        end += 1;
+    }
+
+    (start < end).then(|| document.slice(start..end).to_string())
+}
+
+/// Ranges of every whole word occurrence
+fn occurrences(document: &Rope, word: &str) -> Vec<Value> {
+    let text = document.to_string();
+    let mut ranges = vec![];
+
+    for (byte, _) in text.match_indices(word) {
+        let before = text[..byte].chars().next_back();
+        let after = text[byte + word.len()..].chars().next();
+        if before.is_some_and(is_word_char) || after.is_some_and(is_word_char) {
+            continue;
+        }
+
+        let start = document.byte_to_char(byte);
+        let end = start + word.chars().count();
+        ranges.push(json!({
+            "start": position(document, start),
+            "end": position(document, end),
+        }));
+    }
+
+    ranges
+}
@@ -0,0 +1,1023 @@
+//! Language server protocol client: one server per language and workspace root,
+//! fed the changes of every buffer it knows
+
+pub mod client;
+#[cfg(test)]
+pub mod mock;
+mod transport;
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use lsp_types::{
+    notification, request, ClientCapabilities, ClientInfo, CompletionClientCapabilities,
+    CompletionItemCapability, CompletionParams, CompletionResponse, CompletionTextEdit, Diagnostic,
+    DocumentChangeOperation, DocumentChanges, GotoDefinitionParams, GotoDefinitionResponse,
+    HoverClientCapabilities, HoverContents, HoverParams, InitializeParams, InitializedParams,
+    Location, MarkedString, MarkupKind, OneOf, Position, PublishDiagnosticsClientCapabilities,
+    ReferenceContext, ReferenceParams, RenameParams, ServerCapabilities,
+    TextDocumentClientCapabilities, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+    TextDocumentItem, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncClientCapabilities, TextDocumentSyncKind, TextEdit, Url,
+    VersionedTextDocumentIdentifier, WorkspaceEdit, WorkspaceFolder,
+};
+use ropey::Rope;
+use text::next_grapheme_boundary;
+
+use self::client::Client;
+use crate::{
+    buffer::BufferId,
+    completion::{self, CompletionItem, Items},
+    cursor::Cursor,
+    diff::Edit,
+    editor::{Editor, OpenAction},
+    popup::{Menu, Popup},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerId(usize);
+
+enum ServerState {
+    /// Waiting for the initialize response, with the buffers to open once it arrives
+    Starting(Vec<BufferId>),
+    Running(Box<ServerCapabilities>),
+    Failed,
+}
+
+pub struct Server {
+    /// Language name the server was configured for
+    pub language: String,
+    pub root: PathBuf,
+    pub client: Arc<Client>,
+    state: ServerState,
+}
+
+/// Buffer synced with a server
+struct Document {
+    server: ServerId,
+    uri: Url,
+    version: i32,
+}
+
+/// Running language servers and the buffers they were told about
+#[derive(Default)]
+pub struct Registry {
+    /// Servers are only started once enabled, so headless runs and tests don't spawn them
+    enabled: bool,
+    servers: Vec<Server>,
+    documents: HashMap<BufferId, Document>,
+    /// Languages whose server could not be started, to not retry on every file
+    failed: Vec<String>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn find(&self, language: &str, root: &Path) -> Option<ServerId> {
+        self.servers
+            .iter()
+            .position(|server| server.language == language && server.root == root)
+            .map(ServerId)
+    }
+
+    fn get(&self, id: ServerId) -> &Server {
+        &self.servers[id.0]
+    }
+
+    /// Server the buffer is synced with
+    pub fn server_for(&self, buffer_id: BufferId) -> Option<&Server> {
+        let document = self.documents.get(&buffer_id)?;
+        Some(self.get(document.server))
+    }
+}
+
+impl Server {
+    pub fn capabilities(&self) -> Option<&ServerCapabilities> {
+        match &self.state {
+            ServerState::Running(capabilities) => Some(capabilities),
+            _ => None,
+        }
+    }
+
+    fn sync_kind(&self) -> TextDocumentSyncKind {
+        match self
+            .capabilities()
+            .and_then(|capabilities| capabilities.text_document_sync.as_ref())
+        {
+            Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+            Some(TextDocumentSyncCapability::Options(options)) => {
+                options.change.unwrap_or(TextDocumentSyncKind::NONE)
+            }
+            None => TextDocumentSyncKind::NONE,
+        }
+    }
+}
+
+/// Start servers for every open buffer, and for buffers opened from now on
+pub fn enable(editor: &mut Editor) {
+    editor.lsp.enabled = true;
+
+    let buffer_ids: Vec<BufferId> = editor.buffers.iter().map(|buf| buf.id).collect();
+    for buffer_id in buffer_ids {
+        attach(editor, buffer_id);
+    }
+}
+
+/// Sync a buffer with the server of its language, starting it if needed
+pub fn attach(editor: &mut Editor, buffer_id: BufferId) {
+    if !editor.lsp.enabled || editor.lsp.documents.contains_key(&buffer_id) {
+        return;
+    }
+    let Some(buf) = editor.buffers.get(buffer_id) else {
+        return;
+    };
+    // attached once loading finished
+    if buf.is_loading() {
+        return;
+    }
+    let (Some(language), Some(path)) = (buf.language(), buf.file_path()) else {
+        return;
+    };
+    let Some(config) = editor.config.language_servers.get(language.name) else {
+        return;
+    };
+    if editor
+        .lsp
+        .failed
+        .iter()
+        .any(|failed| failed == language.name)
+    {
+        return;
+    }
+
+    let path = absolute(path);
+    let root = find_root(&path, &config.roots);
+
+    let server_id = match editor.lsp.find(language.name, &root) {
+        Some(server_id) => server_id,
+        None => match Client::start(config, &root, editor.jobs.sender()) {
+            Ok(client) => add_server(editor, language.name, &root, client),
+            Err(err) => {
+                editor.lsp.failed.push(language.name.to_string());
+                editor.set_status(err.to_string());
+                return;
+            }
+        },
+    };
+
+    match &mut editor.lsp.servers[server_id.0].state {
+        ServerState::Starting(waiting) => waiting.push(buffer_id),
+        ServerState::Running(_) => open_document(editor, buffer_id, server_id),
+        ServerState::Failed => {}
+    }
+}
+
+/// Register a connected server and start the initialize handshake
+pub fn add_server(editor: &mut Editor, language: &str, root: &Path, client: Client) -> ServerId {
+    let client = Arc::new(client);
+    let server_id = ServerId(editor.lsp.servers.len());
+
+    let root_uri = Url::from_file_path(root).ok();
+    #[allow(deprecated)]
+    let params = InitializeParams {
+        process_id: Some(std::process::id()),
+        root_uri: root_uri.clone(),
+        workspace_folders: root_uri.map(|uri| {
+            vec![WorkspaceFolder {
+                uri,
+                name: root
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            }]
+        }),
+        capabilities: client_capabilities(),
+        client_info: Some(ClientInfo {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        }),
+        ..Default::default()
+    };
+    let initialize = client.request::<request::Initialize>(params);
+
+    editor
+        .jobs
+        .spawn_then(initialize, move |result, _, context| {
+            initialized(
+                context.editor,
+                server_id,
+                result.map(|result| result.capabilities),
+            );
+        });
+
+    editor.lsp.servers.push(Server {
+        language: language.to_string(),
+        root: root.to_path_buf(),
+        client,
+        state: ServerState::Starting(vec![]),
+    });
+
+    server_id
+}
+
+fn client_capabilities() -> ClientCapabilities {
+    ClientCapabilities {
+        text_document: Some(TextDocumentClientCapabilities {
+            synchronization: Some(TextDocumentSyncClientCapabilities {
+                did_save: Some(true),
+                ..Default::default()
+            }),
+            hover: Some(HoverClientCapabilities {
+                content_format: Some(vec![MarkupKind::PlainText, MarkupKind::Markdown]),
+                ..Default::default()
+            }),
+            publish_diagnostics: Some(PublishDiagnosticsClientCapabilities::default()),
+            completion: Some(CompletionClientCapabilities {
+                completion_item: Some(CompletionItemCapability {
+                    snippet_support: Some(false),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            definition: Some(Default::default()),
+            references: Some(Default::default()),
+            rename: Some(Default::default()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Finish the handshake and open the buffers that waited for it
+fn initialized(editor: &mut Editor, server_id: ServerId, result: Result<ServerCapabilities>) {
+    let server = &mut editor.lsp.servers[server_id.0];
+
+    let capabilities = match result {
+        Ok(capabilities) => capabilities,
+        Err(err) => {
+            server.state = ServerState::Failed;
+            let message = format!("{} failed to initialize: {err}", server.client.name);
+            editor.set_error(message);
+            return;
+        }
+    };
+
+    server
+        .client
+        .notify::<notification::Initialized>(InitializedParams {});
+
+    let ServerState::Starting(waiting) = std::mem::replace(
+        &mut server.state,
+        ServerState::Running(Box::new(capabilities)),
+    ) else {
+        return;
+    };
+    for buffer_id in waiting {
+        open_document(editor, buffer_id, server_id);
+    }
+}
+
+fn open_document(editor: &mut Editor, buffer_id: BufferId, server_id: ServerId) {
+    let Some(buf) = editor.buffers.get_mut(buffer_id) else {
+        return;
+    };
+    let (Some(language), Some(path)) = (buf.language(), buf.file_path()) else {
+        return;
+    };
+    let Ok(uri) = Url::from_file_path(absolute(path)) else {
+        return;
+    };
+
+    buf.track_changes();
+    editor
+        .lsp
+        .get(server_id)
+        .client
+        .notify::<notification::DidOpenTextDocument>(lsp_types::DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: language.name.to_string(),
+                version: 0,
+                text: buf.content().to_string(),
+            },
+        });
+
+    editor.lsp.documents.insert(
+        buffer_id,
+        Document {
+            server: server_id,
+            uri,
+            version: 0,
+        },
+    );
+}
+
+/// Tell the server a buffer was closed, its changes are no longer sent
+pub fn detach(editor: &mut Editor, buffer_id: BufferId) {
+    let Some(document) = editor.lsp.documents.remove(&buffer_id) else {
+        return;
+    };
+    editor
+        .lsp
+        .get(document.server)
+        .client
+        .notify::<notification::DidCloseTextDocument>(lsp_types::DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: document.uri },
+        });
+}
+
+/// Send the changes made to synced buffers since the last call
+pub fn sync(editor: &mut Editor) {
+    let Registry {
+        servers, documents, ..
+    } = &mut editor.lsp;
+
+    for (buffer_id, document) in documents.iter_mut() {
+        let Some(buf) = editor.buffers.get_mut(*buffer_id) else {
+            continue;
+        };
+        let changes = buf.take_changes();
+        if changes.is_empty() {
+            continue;
+        }
+
+        let server = &servers[document.server.0];
+        let content_changes = match server.sync_kind() {
+            TextDocumentSyncKind::INCREMENTAL => changes,
+            TextDocumentSyncKind::FULL => vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: buf.content().to_string(),
+            }],
+            _ => continue,
+        };
+
+        document.version += 1;
+        server.client.notify::<notification::DidChangeTextDocument>(
+            lsp_types::DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: document.uri.clone(),
+                    version: document.version,
+                },
+                content_changes,
+            },
+        );
+    }
+}
+
+/// Tell the server a buffer was written, servers like rust-analyzer check the project then
+pub fn did_save(editor: &mut Editor, buffer_id: BufferId) {
+    sync(editor);
+
+    let Some(document) = editor.lsp.documents.get(&buffer_id) else {
+        return;
+    };
+    editor
+        .lsp
+        .get(document.server)
+        .client
+        .notify::<notification::DidSaveTextDocument>(lsp_types::DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: document.uri.clone(),
+            },
+            text: None,
+        });
+}
+
+/// Store diagnostics published for a document
+pub fn set_diagnostics(editor: &mut Editor, params: lsp_types::PublishDiagnosticsParams) {
+    let Some(buffer_id) = find_buffer(editor, &params.uri) else {
+        return;
+    };
+    if let Some(buf) = editor.buffers.get_mut(buffer_id) {
+        buf.diagnostics = params.diagnostics;
+    }
+}
+
+/// Show documentation for the symbol under the cursor in a popup
+pub fn hover(editor: &mut Editor) -> Result<()> {
+    let (client, position) = cursor_position(editor)?;
+    let response = client.request::<request::HoverRequest>(HoverParams {
+        text_document_position_params: position,
+        work_done_progress_params: Default::default(),
+    });
+
+    editor
+        .jobs
+        .spawn_then(response, |result, components, context| {
+            let text = match result {
+                Ok(hover) => hover.map(|hover| hover_text(hover.contents)),
+                Err(err) => return context.editor.set_error(err.to_string()),
+            };
+            match text.filter(|text| !text.trim().is_empty()) {
+                Some(text) => components.push(Box::new(Popup::new(text))),
+                None => context.editor.set_status("No hover information"),
+            }
+        });
+
+    Ok(())
+}
+
+/// Plain text of hover contents, without markdown code fences
+fn hover_text(contents: HoverContents) -> String {
+    let marked = |marked: MarkedString| match marked {
+        MarkedString::String(text) => text,
+        MarkedString::LanguageString(code) => code.value,
+    };
+
+    let text = match contents {
+        HoverContents::Scalar(scalar) => marked(scalar),
+        HoverContents::Array(array) => array
+            .into_iter()
+            .map(marked)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        HoverContents::Markup(markup) => markup.value,
+    };
+
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Ask the server of the buffer for completions, added to `items` once they arrive
+pub fn complete(editor: &mut Editor, request: &completion::Request, items: Items) {
+    sync(editor);
+
+    let Some(document) = editor.lsp.documents.get(&request.buffer_id) else {
+        return;
+    };
+    let server = editor.lsp.get(document.server);
+    let Some(capabilities) = server.capabilities() else {
+        return;
+    };
+    if capabilities.completion_provider.is_none() {
+        return;
+    }
+
+    let buf = editor.buffers.get(request.buffer_id).unwrap();
+    let response = server
+        .client
+        .request::<request::Completion>(CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: document.uri.clone(),
+                },
+                position: position(buf.content(), request.cursor),
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        });
+
+    let (buffer_id, word_start) = (request.buffer_id, request.word_start);
+    let tx = editor.jobs.sender();
+    tokio::spawn(async move {
+        let list = match response.await {
+            Ok(Some(CompletionResponse::Array(list))) => list,
+            Ok(Some(CompletionResponse::List(list))) => list.items,
+            _ => return,
+        };
+
+        let _ = tx.send(Box::new(move |_, context| {
+            let Some(buf) = context.editor.buffers.get(buffer_id) else {
+                return;
+            };
+
+            let completions = list.into_iter().map(|item| {
+                // only edits replacing the text before the cursor are supported
+                let (start, text) = match item.text_edit {
+                    Some(CompletionTextEdit::Edit(edit)) => {
+                        (char_index(buf.content(), edit.range.start), edit.new_text)
+                    }
+                    Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                        (char_index(buf.content(), edit.insert.start), edit.new_text)
+                    }
+                    None => (
+                        word_start,
+                        item.insert_text.unwrap_or_else(|| item.label.clone()),
+                    ),
+                };
+                CompletionItem {
+                    label: item.label,
+                    text,
+                    start,
+                    kind: "lsp",
+                }
+            });
+            items.lock().unwrap().extend(completions);
+        }));
+    });
+}
+
+/// Jump to the definition of the symbol under the cursor, listing them when there are several
+pub fn goto_definition(editor: &mut Editor) -> Result<()> {
+    let (client, position) = cursor_position(editor)?;
+    let response = client.request::<request::GotoDefinition>(GotoDefinitionParams {
+        text_document_position_params: position,
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+
+    let locations = async move {
+        response.await.map(|response| match response {
+            Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+            Some(GotoDefinitionResponse::Array(locations)) => locations,
+            Some(GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| Location::new(link.target_uri, link.target_selection_range))
+                .collect(),
+            None => vec![],
+        })
+    };
+    editor
+        .jobs
+        .spawn_then(locations, |result, components, context| {
+            show_locations(components, context.editor, result, "No definition found");
+        });
+
+    Ok(())
+}
+
+/// List the references of the symbol under the cursor
+pub fn references(editor: &mut Editor) -> Result<()> {
+    let (client, position) = cursor_position(editor)?;
+    let response = client.request::<request::References>(ReferenceParams {
+        text_document_position: position,
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+        context: ReferenceContext {
+            include_declaration: true,
+        },
+    });
+
+    editor
+        .jobs
+        .spawn_then(response, |result, components, context| {
+            let result = result.map(Option::unwrap_or_default);
+            show_locations(components, context.editor, result, "No references found");
+        });
+
+    Ok(())
+}
+
+/// Jump to a single location, or let the user pick one from a menu
+fn show_locations(
+    components: &mut crate::components::Components,
+    editor: &mut Editor,
+    result: Result<Vec<Location>>,
+    not_found: &str,
+) {
+    let locations = match result {
+        Ok(locations) => locations,
+        Err(err) => return editor.set_error(err.to_string()),
+    };
+
+    match locations.as_slice() {
+        [] => editor.set_status(not_found),
+        [location] => {
+            if let Err(err) = jump_to(editor, location) {
+                editor.set_error(err.to_string());
+            }
+        }
+        _ => {
+            let items = locations
+                .iter()
+                .map(|location| location_label(editor, location))
+                .collect();
+            components.push(Box::new(Menu::new(items, move |context, index| {
+                if let Err(err) = jump_to(context.editor, &locations[index]) {
+                    context.editor.set_error(err.to_string());
+                }
+            })));
+        }
+    }
+}
+
+/// `path:line:column: text` of a location
+fn location_label(editor: &Editor, location: &Location) -> String {
+    let Ok(path) = location.uri.to_file_path() else {
+        return location.uri.to_string();
+    };
+    let line = location.range.start.line as usize;
+
+    let text = match find_buffer(editor, &location.uri).and_then(|id| editor.buffers.get(id)) {
+        Some(buf) => buf.get_line(line).map(|line| line.to_string()),
+        None => std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| content.lines().nth(line).map(str::to_string)),
+    }
+    .unwrap_or_default();
+
+    let display = std::env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.clone());
+
+    format!(
+        "{}:{}:{}: {}",
+        display.display(),
+        line + 1,
+        location.range.start.character + 1,
+        text.trim()
+    )
+}
+
+/// Show a location in the focused window, opening its file if needed
+pub fn jump_to(editor: &mut Editor, location: &Location) -> Result<()> {
+    let buffer_id = match find_buffer(editor, &location.uri) {
+        Some(buffer_id) => {
+            editor.show_buffer(buffer_id, OpenAction::Replace);
+            buffer_id
+        }
+        None => {
+            let path = location
+                .uri
+                .to_file_path()
+                .map_err(|_| eyre!("Not a file: {}", location.uri))?;
+            editor.open(&path, OpenAction::Replace)?
+        }
+    };
+
+    let scrolloff = editor.config.scrolloff;
+    let Some(window) = editor.windows.get_focused_mut() else {
+        return Ok(());
+    };
+    let buf = editor.buffers.get_mut(buffer_id).unwrap();
+
+    let start = char_index(buf.content(), location.range.start);
+    let end = next_grapheme_boundary(&buf.content().slice(..), start);
+    buf.set_cursor(window.id, Cursor::with_range(start, end));
+    window.position_cursor_in_view(buf, scrolloff);
+
+    Ok(())
+}
+
+/// Rename the symbol under the cursor everywhere the server knows about
+pub fn rename(editor: &mut Editor, new_name: &str) -> Result<()> {
+    let (client, position) = cursor_position(editor)?;
+    let response = client.request::<request::Rename>(RenameParams {
+        text_document_position: position,
+        new_name: new_name.to_string(),
+        work_done_progress_params: Default::default(),
+    });
+
+    editor.jobs.spawn_then(response, |result, _, context| {
+        let editor = &mut context.editor;
+        match result.and_then(|edit| apply_workspace_edit(editor, edit.unwrap_or_default())) {
+            Ok(0) => editor.set_status("Nothing to rename"),
+            Ok(files) => editor.set_status(format!("Renamed in {files} files")),
+            Err(err) => editor.set_error(err.to_string()),
+        }
+    });
+
+    Ok(())
+}
+
+/// Apply the text edits of a workspace edit, one undo step per buffer.
+///
+/// Files that aren't open yet are loaded. Returns the number of changed files.
+pub fn apply_workspace_edit(editor: &mut Editor, edit: WorkspaceEdit) -> Result<usize> {
+    let mut changes: Vec<(Url, Vec<TextEdit>)> = vec![];
+
+    match edit.document_changes {
+        Some(DocumentChanges::Edits(edits)) => {
+            for edit in edits {
+                changes.push((edit.text_document.uri, text_edits(edit.edits)));
+            }
+        }
+        // creating, renaming and deleting files is not supported
+        Some(DocumentChanges::Operations(operations)) => {
+            for operation in operations {
+                if let DocumentChangeOperation::Edit(edit) = operation {
+                    changes.push((edit.text_document.uri, text_edits(edit.edits)));
+                }
+            }
+        }
+        None => changes.extend(edit.changes.unwrap_or_default()),
+    }
+
+    // every file is read before the first edit, so an error leaves all of them untouched
+    let mut buffer_ids = vec![];
+    for (uri, _) in &changes {
+        let buffer_id = match find_buffer(editor, uri) {
+            Some(buffer_id) => buffer_id,
+            None => {
+                let path = uri.to_file_path().map_err(|_| eyre!("Not a file: {uri}"))?;
+                editor.open_loaded(&path, OpenAction::Load)?
+            }
+        };
+        if editor.buffers.get(buffer_id).unwrap().is_loading() {
+            bail!("{} is still loading", uri.path());
+        }
+        buffer_ids.push(buffer_id);
+    }
+
+    for ((_, text_edits), buffer_id) in changes.iter().zip(buffer_ids) {
+        let buf = editor.buffers.get_mut(buffer_id).unwrap();
+
+        let mut edits: Vec<Edit> = text_edits
+            .iter()
+            .map(|edit| Edit {
+                range: char_index(buf.content(), edit.range.start)
+                    ..char_index(buf.content(), edit.range.end),
+                text: edit.new_text.clone(),
+            })
+            .collect();
+        edits.sort_by_key(|edit| edit.range.start);
+
+        buf.commit();
+        let result = buf.apply_edits(&edits);
+        buf.commit();
+        result?;
+    }
+
+    Ok(changes.len())
+}
+
+fn text_edits(edits: Vec<OneOf<TextEdit, lsp_types::AnnotatedTextEdit>>) -> Vec<TextEdit> {
+    edits
+        .into_iter()
+        .map(|edit| match edit {
+            OneOf::Left(edit) => edit,
+            OneOf::Right(annotated) => annotated.text_edit,
+        })
+        .collect()
+}
+
+/// Client and cursor position of the focused buffer, after sending its pending changes
+fn cursor_position(editor: &mut Editor) -> Result<(Arc<Client>, TextDocumentPositionParams)> {
+    sync(editor);
+
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let Some(document) = editor.lsp.documents.get(&window.buffer_id) else {
+        bail!("No language server for this buffer");
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let char_idx = buf.get_cursor(window.id).range.start;
+
+    Ok((
+        editor.lsp.get(document.server).client.clone(),
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier {
+                uri: document.uri.clone(),
+            },
+            position: position(buf.content(), char_idx),
+        },
+    ))
+}
+
+/// Buffer of a document, synced or not
+fn find_buffer(editor: &Editor, uri: &Url) -> Option<BufferId> {
+    if let Some((buffer_id, _)) = editor
+        .lsp
+        .documents
+        .iter()
+        .find(|(_, document)| &document.uri == uri)
+    {
+        return Some(*buffer_id);
+    }
+
+    let path = uri.to_file_path().ok()?;
+    editor
+        .buffers
+        .iter()
+        .find(|buf| buf.file_path().is_some_and(|file| absolute(file) == path))
+        .map(|buf| buf.id)
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_path_buf(),
+    })
+}
+
+/// Closest ancestor directory containing one of the `markers`, or the file's directory
+fn find_root(path: &Path, markers: &[String]) -> PathBuf {
+    let dir = path.parent().unwrap_or(path);
+
+    dir.ancestors()
+        .find(|ancestor| markers.iter().any(|marker| ancestor.join(marker).exists()))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// Protocol position of a char index, counting UTF-16 code units in the line
+pub fn position(content: &Rope, char_idx: usize) -> Position {
+    let char_idx = char_idx.min(content.len_chars());
+    let line = content.char_to_line(char_idx);
+    let line_start = content.line_to_char(line);
+
+    let character = content.char_to_utf16_cu(char_idx) - content.char_to_utf16_cu(line_start);
+    Position::new(line as u32, character as u32)
+}
+
+/// Char index of a protocol position, clamped to the end of its line
+pub fn char_index(content: &Rope, position: Position) -> usize {
+    let line = position.line as usize;
+    if line >= content.len_lines() {
+        return content.len_chars();
+    }
+
+    let line_start = content.line_to_char(line);
+    let line_end = line_start + content.line(line).len_chars();
+    let line_end = match content.line(line).chars().last() {
+        Some('\n') => line_end - 1,
+        _ => line_end,
+    };
+
+    let utf16 = content.char_to_utf16_cu(line_start) + position.character as usize;
+    content
+        .utf16_cu_to_char(utf16.min(content.len_utf16_cu()))
+        .min(line_end)
+}
+
+/// Severity of a diagnostic, errors when the server didn't say
+pub fn severity(diagnostic: &Diagnostic) -> lsp_types::DiagnosticSeverity {
+    diagnostic
+        .severity
+        .unwrap_or(lsp_types::DiagnosticSeverity::ERROR)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::{buffer::LARGE_FILE_THRESHOLD, test_harness::TestApp};
+
+    /// Sync the buffer of the app, a Rust file in `dir`, with the mock server
+    async fn connect_mock_server(dir: &Path, app: &mut TestApp) {
+        let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+        let (server_reader, server_writer) = tokio::io::split(server_side);
+        tokio::spawn(mock::serve(server_reader, server_writer));
+
+        let editor = &mut app.app.editor;
+        let (reader, writer) = tokio::io::split(client_side);
+        let client = Client::connect("mock", reader, writer, editor.jobs.sender());
+        add_server(editor, "rust", &absolute(dir), client);
+        enable(editor);
+
+        wait_until(app, |app| {
+            app.app.editor.lsp.server_for(app.buffer().id).is_some()
+        })
+        .await;
+    }
+
+    /// Apply finished jobs until `done` holds
+    async fn wait_until(app: &mut TestApp, mut done: impl FnMut(&mut TestApp) -> bool) {
+        while !done(app) {
+            tokio::time::timeout(Duration::from_secs(5), app.wait_for_job())
+                .await
+                .expect("no job finished in time");
+        }
+    }
+
+    fn screen_contains(app: &mut TestApp, text: &str) -> bool {
+        (0..crate::test_harness::HEIGHT).any(|y| app.screen_line(y).contains(text))
+    }
+
+    #[test]
+    fn converts_utf16_positions() {
+        let content = Rope::from("a😀b\nxy");
+
+        assert_eq!(position(&content, 2), Position::new(0, 3));
+        assert_eq!(position(&content, 4), Position::new(1, 0));
+        assert_eq!(char_index(&content, Position::new(0, 3)), 2);
+        // past the end of a line or the text
+        assert_eq!(char_index(&content, Position::new(0, 40)), 3);
+        assert_eq!(char_index(&content, Position::new(7, 0)), 6);
+    }
+
+    #[tokio::test]
+    async fn syncs_changes_and_shows_diagnostics() {
+        let (dir, mut app) = TestApp::with_files(&[("main.rs", "fn main() {}\n")]).await;
+        connect_mock_server(dir.path(), &mut app).await;
+        assert!(app.buffer().diagnostics.is_empty());
+
+        // the server only sees a whole word TODO if every change reached it
+        app.keys("i// TODOX<BS><CR><Esc>").await;
+        wait_until(&mut app, |app| !app.buffer().diagnostics.is_empty()).await;
+
+        let diagnostic = &app.buffer().diagnostics[0];
+        assert_eq!(diagnostic.range.start, Position::new(0, 3));
+        assert_eq!(app.screen_line(0).trim_end(), "W // TODO  unfinished work");
+        assert!(app.screen_line(1).starts_with("  fn main() {}"));
+
+        app.keys("u").await;
+        wait_until(&mut app, |app| app.buffer().diagnostics.is_empty()).await;
+        assert!(app.screen_line(0).starts_with("fn main() {}"));
+    }
+
+    #[tokio::test]
+    async fn hovers_and_jumps_to_definition() {
+        let (dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn target() {}\nfn main() { target(); }\n")]).await;
+        connect_mock_server(dir.path(), &mut app).await;
+
+        app.keys("jllllllllllllK").await;
+        wait_until(&mut app, |app| screen_contains(app, "6 characters")).await;
+        assert!(screen_contains(&mut app, "│target"));
+
+        // the popup closes on the next key
+        app.keys("gd").await;
+        wait_until(&mut app, |app| app.cursor() == (0, 3)).await;
+        assert!(!screen_contains(&mut app, "6 characters"));
+    }
+
+    #[tokio::test]
+    async fn lists_references_and_renames() {
+        let (dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn target() {}\nfn main() { target(); }\n")]).await;
+        connect_mock_server(dir.path(), &mut app).await;
+
+        app.keys("lllgr").await;
+        wait_until(&mut app, |app| screen_contains(app, "main.rs:2:13:")).await;
+
+        app.keys("j<CR>").await;
+        assert_eq!(app.cursor(), (1, 12));
+
+        app.keys(":rename goal<CR>").await;
+        wait_until(&mut app, |app| app.text().contains("goal")).await;
+        assert_eq!(app.text(), "fn goal() {}\nfn main() { goal(); }\n");
+
+        app.keys("u").await;
+        assert_eq!(app.text(), "fn target() {}\nfn main() { target(); }\n");
+    }
+
+    #[tokio::test]
+    async fn closed_buffers_leave_the_server() {
+        let (dir, mut app) = TestApp::with_files(&[
+            ("a.rs", "fn target() {}\nfn main() { target(); }\n"),
+            ("b.rs", "fn other() { target(); }\n"),
+        ])
+        .await;
+        connect_mock_server(dir.path(), &mut app).await;
+
+        let b = dir.path().join("b.rs");
+        app.keys(&format!(":e {}<CR>", b.display())).await;
+        wait_until(&mut app, |app| {
+            app.app.editor.lsp.server_for(app.buffer().id).is_some()
+        })
+        .await;
+        app.keys(":bn<CR>lllgr").await;
+        wait_until(&mut app, |app| screen_contains(app, "b.rs:1:14:")).await;
+
+        app.keys("<Esc>:bn<CR>:bd<CR>").await;
+        assert_eq!(app.text(), "fn target() {}\nfn main() { target(); }\n");
+
+        app.keys("gr").await;
+        wait_until(&mut app, |app| screen_contains(app, "a.rs:2:13:")).await;
+        assert!(!screen_contains(&mut app, "b.rs:"));
+    }
+
+    #[tokio::test]
+    async fn edits_large_files_once_read_whole() {
+        let large = format!(
+            "fn target() {{}}\n{}",
+            "// filler\n".repeat(LARGE_FILE_THRESHOLD / 10)
+        );
+        let (dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn main() {}\n"), ("large.rs", &large)]).await;
+
+        let uri = Url::from_file_path(dir.path().join("large.rs")).unwrap();
+        let range = lsp_types::Range::new(Position::new(0, 3), Position::new(0, 9));
+        let edit = WorkspaceEdit::new(HashMap::from([(
+            uri.clone(),
+            vec![TextEdit::new(range, "goal".to_string())],
+        )]));
+        apply_workspace_edit(&mut app.app.editor, edit).unwrap();
+
+        let editor = &app.app.editor;
+        let buf = editor
+            .buffers
+            .get(find_buffer(editor, &uri).unwrap())
+            .unwrap();
+        assert!(!buf.is_loading());
+        assert_eq!(*buf.content(), large.replacen("target", "goal", 1));
+    }
+
+    #[tokio::test]
+    async fn completes_from_the_server() {
+        let (dir, mut app) = TestApp::with_files(&[("main.rs", "fn main() {}\n")]).await;
+        connect_mock_server(dir.path(), &mut app).await;
+
+        app.keys("imock<C-n>").await;
+        wait_until(&mut app, |app| screen_contains(app, "mock_completion")).await;
+
+        app.keys("<CR>").await;
+        assert_eq!(app.text(), "mock_completionfn main() {}\n");
+    }
+}
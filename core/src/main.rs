@@ -1,14 +1,38 @@
 pub mod app;
+pub mod autopair;
+pub mod brackets;
 pub mod buffer;
 pub mod cli;
+pub mod commands;
+pub mod comment;
+pub mod completion;
 pub mod components;
 pub mod config;
 pub mod cursor;
+pub mod diff;
+pub mod diff_mode;
 pub mod editor;
+pub mod fold;
+pub mod format;
+pub mod fuzzy;
+pub mod git;
+pub mod grep;
+pub mod indent;
+pub mod jobs;
+pub mod language;
+pub mod lsp;
 pub mod mode;
 pub mod movements;
+pub mod picker;
+pub mod popup;
 pub mod prompt;
+pub mod quickfix;
+pub mod script;
+pub mod shell;
 pub mod terminal;
+#[cfg(test)]
+pub mod test_harness;
+pub mod textobject;
 pub mod utils;
 pub mod window;
 
@@ -5,7 +5,9 @@ use text::{
     prev_grapheme_boundary_nth, width,
 };
 
-use crate::{components::Context, cursor::Cursor};
+use regex::Regex;
+
+use crate::{components::Context, cursor::Cursor, indent};
 
 pub fn move_right_nth(context: &mut Context, count: usize) {
     let focused_window = context.editor.windows.get_focused().unwrap();
@@ -44,7 +46,10 @@ pub fn move_up(context: &mut Context) {
     let content = &buf.content().slice(..);
 
     let cur_line_index = content.char_to_line(cursor.range.start);
-    let new_line_index = cmp::max(0, cur_line_index.saturating_sub(n));
+    // closed folds count as one line
+    let new_line_index = (0..n).fold(cur_line_index, |line, _| {
+        buf.folds.prev_row(line).unwrap_or(line)
+    });
 
     let cur_col = {
         let cur_line_index = content.line_to_char(cur_line_index);
@@ -78,7 +83,11 @@ pub fn move_down(context: &mut Context) {
     let lines_len = content.len_lines().saturating_sub(1);
 
     let cur_line_index = content.char_to_line(cursor.range.start);
-    let new_line_index = cmp::min(cur_line_index + n, lines_len);
+    // closed folds count as one line
+    let new_line_index = (0..n).fold(cur_line_index, |line, _| {
+        let next = cmp::min(buf.folds.next_row(line), lines_len);
+        buf.folds.row_start(next)
+    });
 
     let cur_col = {
         let cur_line_index = content.line_to_char(cur_line_index);
@@ -127,14 +136,57 @@ pub fn insert_char(context: &mut Context, char: char) {
         .unwrap();
     let cursor_pos = buf.get_cursor(focused_window.id).range.start;
 
-    let content = buf.content_mut();
-    content.insert_char(cursor_pos, char);
+    if let Err(err) = buf.insert(cursor_pos, char.encode_utf8(&mut [0; 4])) {
+        context.editor.set_error(err.to_string());
+        return;
+    }
+
+    // a closer typed first on a line goes back to the indentation of its opener
+    let content = buf.content();
+    let line = content.char_to_line(cursor_pos);
+    let closes_line = matches!(char, '}' | ')' | ']')
+        && content
+            .slice(content.line_to_char(line)..cursor_pos)
+            .chars()
+            .all(|char| char == ' ' || char == '\t');
 
     move_right(context);
+
+    if closes_line {
+        if let Err(err) = indent::reindent(context.editor, line..line + 1) {
+            context.editor.set_error(err.to_string());
+        }
+    }
+}
+
+/// Insert text before the cursor as a single edit, leaving the cursor after it
+pub fn insert_text(context: &mut Context, text: &str) {
+    let focused_window = context.editor.windows.get_focused().unwrap();
+    let buf = context
+        .editor
+        .buffers
+        .get_mut(focused_window.buffer_id)
+        .unwrap();
+    let cursor_pos = buf.get_cursor(focused_window.id).range.start;
+
+    if let Err(err) = buf.insert(cursor_pos, text) {
+        context.editor.set_error(err.to_string());
+        return;
+    }
+
+    let content = &buf.content().slice(..);
+    let index = cursor_pos + text.chars().count();
+    let cursor = Cursor {
+        range: index..next_grapheme_boundary(content, index),
+    };
+    buf.set_cursor(focused_window.id, cursor);
 }
 
+/// Break the line at the cursor, the new line is indented like the syntax tree or the line
+/// above says
 #[inline]
 pub fn insert_new_line(context: &mut Context) {
+    let width = context.editor.config.indent_width;
     let focused_window = context.editor.windows.get_focused().unwrap();
     let buf = context
         .editor
@@ -143,15 +195,53 @@ pub fn insert_new_line(context: &mut Context) {
         .unwrap();
     let cursor_pos = buf.get_cursor(focused_window.id).range.start;
 
-    let content = buf.content_mut();
-    content.insert_char(cursor_pos, '\n');
+    // blanks moving to the new line are replaced by its indentation
+    let blanks = buf
+        .content()
+        .chars_at(cursor_pos)
+        .take_while(|char| *char == ' ' || *char == '\t')
+        .count();
+    let result = match blanks {
+        0 => Ok(()),
+        _ => buf.remove(cursor_pos..cursor_pos + blanks),
+    }
+    .and_then(|_| buf.insert(cursor_pos, "\n"));
+    if let Err(err) = result {
+        context.editor.set_error(err.to_string());
+        return;
+    }
+
+    let line = buf.content().char_to_line(cursor_pos) + 1;
+    let indent = indent::indentation(buf, line, width);
+    let line_start = buf.content().line_to_char(line);
+    if let Err(err) = buf.insert(line_start, &indent) {
+        context.editor.set_error(err.to_string());
+        return;
+    }
 
-    move_down(context);
-    goto_start_of_line(context);
+    let content = &buf.content().slice(..);
+    let index = line_start + indent.chars().count();
+    let cursor = Cursor {
+        range: index..next_grapheme_boundary(content, index),
+    };
+    buf.set_cursor(focused_window.id, cursor);
 }
 
 #[inline]
 pub fn delete_char(context: &mut Context) {
+    let focused_window = context.editor.windows.get_focused().unwrap();
+    let buf = context
+        .editor
+        .buffers
+        .get(focused_window.buffer_id)
+        .unwrap();
+    if buf.readonly {
+        context
+            .editor
+            .set_error("Cannot modify, buffer is read-only");
+        return;
+    }
+
     move_left(context);
 
     let focused_window = context.editor.windows.get_focused().unwrap();
@@ -162,7 +252,9 @@ pub fn delete_char(context: &mut Context) {
         .unwrap();
     let cursor_pos = buf.get_cursor(focused_window.id).range.start;
 
-    buf.content_mut().remove(cursor_pos..cursor_pos + 1);
+    if let Err(err) = buf.remove(cursor_pos..cursor_pos + 1) {
+        context.editor.set_error(err.to_string());
+    }
 }
 
 #[inline]
@@ -213,3 +305,92 @@ pub fn goto_end_of_line(context: &mut Context) {
 
     buf.set_cursor(focused_window.id, cursor)
 }
+
+/// Go to a 1-based line and optional 1-based column, clamped to the buffer and to the last
+/// grapheme of the line
+pub fn goto_line(context: &mut Context, line: usize, column: Option<usize>) {
+    let focused_window = context.editor.windows.get_focused().unwrap();
+    let buf = context
+        .editor
+        .buffers
+        .get_mut(focused_window.buffer_id)
+        .unwrap();
+
+    let content = &buf.content().slice(..);
+
+    let line_index = line
+        .saturating_sub(1)
+        .min(content.len_lines().saturating_sub(1));
+    let line_start = content.line_to_char(line_index);
+    let line = content.line(line_index);
+    let breaks = line
+        .chars_at(line.len_chars())
+        .reversed()
+        .take_while(|char| *char == '\n' || *char == '\r')
+        .count();
+    let line_end = line_start + line.len_chars() - breaks;
+    let last = match line_end > line_start {
+        true => prev_grapheme_boundary(content, line_end),
+        false => line_start,
+    };
+    let index = (line_start + column.unwrap_or(1).saturating_sub(1)).min(last);
+
+    let cursor = Cursor {
+        range: index..next_grapheme_boundary(content, index),
+    };
+
+    buf.set_cursor(focused_window.id, cursor)
+}
+
+#[inline]
+pub fn goto_last_line(context: &mut Context) {
+    goto_line(context, usize::MAX, None);
+}
+
+/// Move to the next match of `pattern` after the cursor, wrapping around the buffer.
+/// Lines hidden by closed folds are skipped.
+///
+/// Returns whether a match was found.
+pub fn search_forward(context: &mut Context, pattern: &Regex) -> bool {
+    let focused_window = context.editor.windows.get_focused().unwrap();
+    let buf = context
+        .editor
+        .buffers
+        .get_mut(focused_window.buffer_id)
+        .unwrap();
+
+    let content = &buf.content().slice(..);
+    let cursor_pos = buf.get_cursor(focused_window.id).range.start;
+
+    let line_count = content.len_lines();
+    let cursor_line = content.char_to_line(cursor_pos);
+
+    // search after the cursor on its line first, then every line once wrapping around
+    for step in 0..=line_count {
+        let line_index = (cursor_line + step) % line_count;
+        if buf.folds.hidden(line_index) {
+            continue;
+        }
+        let line_start = content.line_to_char(line_index);
+        let line = content.line(line_index).to_string();
+
+        let from = match step {
+            0 => line
+                .char_indices()
+                .nth(cursor_pos - line_start + 1)
+                .map_or(line.len(), |(index, _)| index),
+            _ => 0,
+        };
+
+        if let Some(found) = pattern.find_at(&line, from) {
+            let index = line_start + line[..found.start()].chars().count();
+            let cursor = Cursor {
+                range: index..next_grapheme_boundary(content, index),
+            };
+            buf.set_cursor(focused_window.id, cursor);
+            return true;
+        }
+    }
+
+    false
+}
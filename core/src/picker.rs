@@ -0,0 +1,560 @@
+//! Fuzzy file picker shown over the editor
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use ignore::WalkBuilder;
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+use ropey::Rope;
+use syntax::Highlight;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    components::{Component, Context, EventPropagation, Position},
+    editor::{Editor, OpenAction, RenderableText},
+    fuzzy::{self, fuzzy_match},
+    jobs::{JobCallback, Running},
+    language::Language,
+    popup::{BACKGROUND, SELECTED},
+    terminal::{Event, Frame},
+};
+
+const MATCH_COLOR: Color = Color::Rgb(235, 188, 186);
+
+/// Files found by one walk, the rest are not listed
+const MAX_FILES: usize = 100_000;
+/// Files found between two redraws while walking
+const BATCH_SIZE: usize = 500;
+/// Bytes of the selected file read for the preview
+const PREVIEW_BYTES: u64 = 64 * 1024;
+/// Previews kept before the cache starts over
+const PREVIEW_CACHE: usize = 64;
+
+/// Picker listing the files below a directory, skipping those ignored by
+/// `.gitignore` and similar files.
+///
+/// Typing narrows the list, Ctrl-n/Ctrl-p or the arrows select, Enter opens the
+/// file in the focused window, Ctrl-s and Ctrl-v in a split and Esc closes.
+pub struct FilePicker {
+    /// Paths are listed relative to it
+    root: PathBuf,
+    files: Arc<Mutex<Vec<PathBuf>>>,
+    /// Set once the walk finished
+    done: Arc<AtomicBool>,
+    /// Tells the walk to stop once the picker is closed
+    cancelled: Arc<AtomicBool>,
+    query: String,
+    selected: usize,
+    matches: RefCell<Matches>,
+    /// Previews read in the background by path, `None` while reading
+    previews: Arc<Mutex<HashMap<PathBuf, Option<Preview>>>>,
+    tx: UnboundedSender<JobCallback>,
+    running: Running,
+}
+
+/// Files matching the query, recomputed when either changes
+#[derive(Default)]
+struct Matches {
+    query: String,
+    files: usize,
+    list: Vec<(usize, fuzzy::Match)>,
+}
+
+struct Preview {
+    content: Rope,
+    highlight: Option<Highlight>,
+}
+
+impl FilePicker {
+    /// Start walking `root` in the background, an empty path walks the working directory
+    pub fn new(root: &Path, editor: &Editor) -> Self {
+        let picker = Self {
+            root: root.to_path_buf(),
+            files: Arc::default(),
+            done: Arc::default(),
+            cancelled: Arc::default(),
+            query: String::new(),
+            selected: 0,
+            matches: RefCell::default(),
+            previews: Arc::default(),
+            tx: editor.jobs.sender(),
+            running: editor.jobs.running(),
+        };
+
+        let walk_root = match root.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => root.to_path_buf(),
+        };
+        let files = picker.files.clone();
+        let done = picker.done.clone();
+        let cancelled = picker.cancelled.clone();
+        let tx = editor.jobs.sender();
+        let redraw = move || {
+            let job: JobCallback = Box::new(|_, _| {});
+            let _ = tx.send(job);
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let walk = WalkBuilder::new(&walk_root)
+                // ignore files apply outside of git repositories too
+                .require_git(false)
+                .sort_by_file_name(|a, b| a.cmp(b))
+                .build();
+
+            let mut batch = vec![];
+            let mut found = 0;
+            for entry in walk.flatten() {
+                if cancelled.load(Ordering::Relaxed) || found == MAX_FILES {
+                    break;
+                }
+                if !entry
+                    .file_type()
+                    .is_some_and(|file_type| file_type.is_file())
+                {
+                    continue;
+                }
+                let Ok(path) = entry.path().strip_prefix(&walk_root) else {
+                    continue;
+                };
+
+                batch.push(path.to_path_buf());
+                found += 1;
+                if batch.len() == BATCH_SIZE {
+                    files.lock().unwrap().append(&mut batch);
+                    redraw();
+                }
+            }
+
+            files.lock().unwrap().append(&mut batch);
+            done.store(true, Ordering::Relaxed);
+            redraw();
+        });
+
+        picker
+    }
+
+    /// Update the matches if the query changed or more files were found.
+    ///
+    /// Files found since the last update are matched on their own and merged in.
+    fn refresh(&self) {
+        let files = self.files.lock().unwrap();
+        let mut matches = self.matches.borrow_mut();
+        if matches.query == self.query && matches.files == files.len() {
+            return;
+        }
+
+        let first_new = match matches.query == self.query {
+            true => matches.files,
+            false => 0,
+        };
+        let mut found: Vec<(usize, fuzzy::Match)> = files[first_new..]
+            .iter()
+            .enumerate()
+            .filter_map(|(index, path)| {
+                fuzzy_match(&self.query, &path.to_string_lossy())
+                    .map(|matched| (first_new + index, matched))
+            })
+            .collect();
+
+        // best score first, then shorter paths, without a query in the order found
+        let order = |(a, a_match): &(usize, fuzzy::Match), (b, b_match): &(usize, fuzzy::Match)| {
+            b_match
+                .score
+                .cmp(&a_match.score)
+                .then(
+                    files[*a]
+                        .as_os_str()
+                        .len()
+                        .cmp(&files[*b].as_os_str().len()),
+                )
+                .then(files[*a].cmp(&files[*b]))
+        };
+        let list = if first_new == 0 {
+            if !self.query.is_empty() {
+                found.sort_by(order);
+            }
+            found
+        } else if self.query.is_empty() {
+            let mut list = std::mem::take(&mut matches.list);
+            list.append(&mut found);
+            list
+        } else {
+            found.sort_by(order);
+            let mut list = Vec::with_capacity(matches.list.len() + found.len());
+            let mut found = found.into_iter().peekable();
+            for old in std::mem::take(&mut matches.list) {
+                while let Some(new) = found.next_if(|new| order(new, &old).is_lt()) {
+                    list.push(new);
+                }
+                list.push(old);
+            }
+            list.extend(found);
+            list
+        };
+
+        *matches = Matches {
+            query: self.query.clone(),
+            files: files.len(),
+            list,
+        };
+    }
+
+    /// Path of the selected file, relative to the root
+    fn selected_file(&self) -> Option<PathBuf> {
+        self.refresh();
+        let matches = self.matches.borrow();
+        let (index, _) = matches.list.get(self.selected)?;
+        Some(self.files.lock().unwrap()[*index].clone())
+    }
+
+    fn move_selection(&mut self, forward: bool) {
+        self.refresh();
+        let len = self.matches.borrow().list.len();
+        if len == 0 {
+            return;
+        }
+        self.selected = match forward {
+            true => (self.selected + 1) % len,
+            false => (self.selected + len - 1) % len,
+        };
+    }
+
+    /// Close the picker and open the selected file
+    fn open(&self, action: OpenAction) -> EventPropagation {
+        let path = self.selected_file().map(|file| self.root.join(file));
+
+        EventPropagation::Consume(Some(Box::new(move |components, context| {
+            components.pop();
+            let Some(path) = path else {
+                return;
+            };
+            if let Err(err) = context.editor.open(&path, action) {
+                context.editor.set_error(err.to_string());
+            }
+        })))
+    }
+
+    fn render_list(&self, f: &mut Frame<'_>, area: Rect) {
+        self.refresh();
+        let matches = self.matches.borrow();
+        let files = self.files.lock().unwrap();
+
+        let scanning = match self.done.load(Ordering::Relaxed) {
+            true => "",
+            false => "…",
+        };
+        let title = format!(" Files {}/{}{scanning} ", matches.list.len(), files.len());
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+        if inner.height == 0 {
+            return;
+        }
+
+        // the query is typed on the last line, matches are listed above it
+        let visible = inner.height.saturating_sub(1) as usize;
+        let first = (self.selected + 1).saturating_sub(visible);
+        let lines: Vec<Line> = matches
+            .list
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .map(|(row, (index, matched))| {
+                let line = highlighted(&files[*index].to_string_lossy(), matched);
+                match row == self.selected {
+                    true => line.style(Style::default().bg(SELECTED)),
+                    false => line,
+                }
+            })
+            .collect();
+        f.render_widget(Paragraph::new(lines), inner);
+
+        let prompt = Line::from(format!("> {}", self.query));
+        let prompt_area = Rect {
+            y: inner.bottom() - 1,
+            height: 1,
+            ..inner
+        };
+        f.render_widget(Paragraph::new(prompt), prompt_area);
+    }
+
+    fn render_preview(&self, f: &mut Frame<'_>, area: Rect) {
+        let block = Block::default().borders(Borders::ALL);
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        let Some(file) = self.selected_file() else {
+            return;
+        };
+        let path = self.root.join(file);
+
+        let mut previews = self.previews.lock().unwrap();
+        let Some(preview) = previews.get(&path) else {
+            if previews.len() == PREVIEW_CACHE {
+                previews.clear();
+            }
+            previews.insert(path.clone(), None);
+            self.read_preview(path);
+            return;
+        };
+        // still reading
+        let Some(preview) = preview else {
+            return;
+        };
+
+        let content = preview.content.slice(..);
+        let colors = preview.highlight.as_ref().map(|highlight| {
+            let last_line = (inner.height as usize).min(content.len_lines());
+            highlight.colors(content, 0..content.line_to_byte(last_line))
+        });
+        let text = RenderableText::new(content, colors.unwrap_or_default());
+        f.render_widget(text, inner);
+    }
+
+    /// Read a preview without blocking the event loop, redrawing once it is there
+    fn read_preview(&self, path: PathBuf) {
+        let previews = self.previews.clone();
+        let tx = self.tx.clone();
+        let running = self.running.start();
+
+        tokio::task::spawn_blocking(move || {
+            let preview = Preview::read(&path);
+            previews.lock().unwrap().insert(path, Some(preview));
+
+            let redraw: JobCallback = Box::new(|_, _| {});
+            let _ = tx.send(redraw);
+            drop(running);
+        });
+    }
+}
+
+impl Preview {
+    /// Read the start of a file, highlighting languages the editor can highlight
+    fn read(path: &Path) -> Self {
+        let mut bytes = vec![];
+        let read =
+            File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes));
+
+        let text = match read {
+            Err(err) => err.to_string(),
+            Ok(_) if bytes.contains(&0) => "Binary file".to_string(),
+            Ok(_) => String::from_utf8_lossy(&bytes).into_owned(),
+        };
+        let content = Rope::from_str(&text);
+        let highlight = Language::from_path(path)
+            .filter(|language| language.name == "rust")
+            .map(|_| Highlight::new(content.slice(..)));
+
+        Self { content, highlight }
+    }
+}
+
+/// Line of a candidate with the chars matched by the query emphasized
+pub fn highlighted(text: &str, matched: &fuzzy::Match) -> Line<'static> {
+    let spans: Vec<Span> = text
+        .chars()
+        .enumerate()
+        .map(|(index, char)| {
+            let style = match matched.indices.contains(&index) {
+                true => Style::default()
+                    .fg(MATCH_COLOR)
+                    .add_modifier(Modifier::BOLD),
+                false => Style::default(),
+            };
+            Span::styled(char.to_string(), style)
+        })
+        .collect();
+    Line::from(spans)
+}
+
+/// Centered area covering most of the screen
+pub fn overlay_area(area: Rect) -> Rect {
+    let width = area.width * 9 / 10;
+    let height = area.height * 8 / 10;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+impl Drop for FilePicker {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Component for FilePicker {
+    fn handle_events(&mut self, event: &Event, _context: &mut Context) -> EventPropagation {
+        let key = match event {
+            Event::Key(key) => key,
+            Event::Paste(text) => {
+                self.query.push_str(text.lines().next().unwrap_or_default());
+                self.selected = 0;
+                return EventPropagation::Consume(None);
+            }
+            _ => return EventPropagation::Consume(None),
+        };
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (key.code, ctrl) {
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), true) => {
+                return EventPropagation::Consume(Some(Box::new(|components, _| components.pop())));
+            }
+            (KeyCode::Enter, _) => return self.open(OpenAction::Replace),
+            (KeyCode::Char('s'), true) => return self.open(OpenAction::HorizontalSplit),
+            (KeyCode::Char('v'), true) => return self.open(OpenAction::VerticalSplit),
+            (KeyCode::Char('n'), true) | (KeyCode::Down | KeyCode::Tab, _) => {
+                self.move_selection(true)
+            }
+            (KeyCode::Char('p'), true) | (KeyCode::Up | KeyCode::BackTab, _) => {
+                self.move_selection(false)
+            }
+            (KeyCode::Backspace, _) => {
+                self.query.pop();
+                self.selected = 0;
+            }
+            (KeyCode::Char(char), false) => {
+                self.query.push(char);
+                self.selected = 0;
+            }
+            _ => {}
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    fn cursor(&self, area: Rect, _editor: &mut Editor) -> Option<Position> {
+        let area = overlay_area(area);
+        // after the query on the last line inside the list's border
+        Some(Position {
+            x: area.x as usize + 3 + self.query.chars().count(),
+            y: area.bottom().saturating_sub(2) as usize,
+        })
+    }
+
+    fn render(&self, f: &mut Frame<'_>, area: Rect, _context: &mut Context) {
+        let area = overlay_area(area);
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Block::default().style(Style::default().bg(BACKGROUND)),
+            area,
+        );
+
+        let [list_area, preview_area] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(area);
+
+        self.render_list(f, list_area);
+        self.render_preview(f, preview_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::test_harness::TestApp;
+
+    /// Project with an ignored build directory
+    const PROJECT: &[(&str, &str)] = &[
+        ("README.md", "# Project\n"),
+        ("src/main.rs", "fn main() {}\n"),
+        ("src/lib.rs", "pub fn lib() {}\n"),
+        ("target/main.rs", ""),
+        (".gitignore", "target/\n"),
+    ];
+
+    /// App showing the README of the project with the file picker open
+    async fn open_picker() -> (tempfile::TempDir, TestApp) {
+        let (dir, mut app) = TestApp::with_files(PROJECT).await;
+
+        app.keys(&format!(":files {}<CR>", dir.path().display()))
+            .await;
+        while !(0..24).any(|y| app.screen_line(y).contains("Files 3/3 ")) {
+            app.wait_for_job().await;
+        }
+        (dir, app)
+    }
+
+    #[tokio::test]
+    async fn lists_files_not_ignored_and_opens_the_match() {
+        let (_dir, mut app) = open_picker().await;
+
+        app.keys("mnrs").await;
+        app.app.wait_for_jobs().await;
+        app.app.render_app().await;
+        let screen: Vec<String> = (0..24).map(|y| app.screen_line(y)).collect();
+        assert!(screen.iter().any(|line| line.contains("Files 1/3")));
+        assert!(screen.iter().any(|line| line.contains("src/main.rs")));
+        // the preview shows the selected file
+        assert!(screen.iter().any(|line| line.contains("fn main() {}")));
+
+        app.keys("<CR>").await;
+        assert_eq!(app.text(), "fn main() {}\n");
+        assert!(app.buffer().file_path().unwrap().ends_with("src/main.rs"));
+    }
+
+    #[tokio::test]
+    async fn opens_in_a_split() {
+        let (_dir, mut app) = open_picker().await;
+
+        app.keys("lib<C-v>").await;
+        assert_eq!(app.text(), "pub fn lib() {}\n");
+        assert_eq!(app.app.editor.windows.iter().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn merges_files_found_later_into_the_matches() {
+        let (dir, app) = TestApp::with_files(PROJECT).await;
+        let mut picker = FilePicker::new(&dir.path().join("missing"), &app.app.editor);
+        picker.query = "ma".to_string();
+
+        let batches = [
+            vec!["src/main.rs", "lib/mad.rs", "docs/readme.md"],
+            vec!["main.rs", "x/y/map.rs", "other.txt", "ma"],
+        ];
+        for batch in batches {
+            let batch = batch.into_iter().map(PathBuf::from);
+            picker.files.lock().unwrap().extend(batch);
+            picker.refresh();
+
+            let merged: Vec<usize> = picker
+                .matches
+                .borrow()
+                .list
+                .iter()
+                .map(|(index, _)| *index)
+                .collect();
+            *picker.matches.borrow_mut() = Matches::default();
+            picker.refresh();
+            let all: Vec<usize> = picker
+                .matches
+                .borrow()
+                .list
+                .iter()
+                .map(|(index, _)| *index)
+                .collect();
+            assert_eq!(merged, all);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Floating boxes drawn next to the cursor, on top of the windows
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    components::{Component, Context, EventCallback, EventPropagation, Position},
+    terminal::{Event, Frame},
+};
+
+pub const BACKGROUND: Color = Color::Rgb(31, 29, 46);
+pub const SELECTED: Color = Color::Rgb(64, 61, 82);
+
+const MAX_WIDTH: u16 = 80;
+/// Rows of text before a popup is cut off
+const MAX_HEIGHT: u16 = 15;
+/// Items shown at once in a menu, the rest is scrolled to
+pub const MENU_ITEMS: usize = 10;
+
+/// Area of `width` x `height` below the anchor row, or above it when there is more room.
+///
+/// The area is kept inside `bounds`.
+pub fn area_near(anchor: &Position, width: u16, height: u16, bounds: Rect) -> Rect {
+    let anchor_y = (anchor.y as u16).clamp(bounds.top(), bounds.bottom().saturating_sub(1));
+    let below = bounds.bottom().saturating_sub(anchor_y + 1);
+    let above = anchor_y.saturating_sub(bounds.top());
+
+    let (y, height) = match height <= below || below >= above {
+        true => (anchor_y + 1, height.min(below)),
+        false => {
+            let height = height.min(above);
+            (anchor_y - height, height)
+        }
+    };
+
+    let width = width.min(bounds.width);
+    let x = (anchor.x as u16)
+        .max(bounds.left())
+        .min(bounds.right().saturating_sub(width));
+
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+/// Screen position of the cursor popups are placed next to
+pub fn anchor(context: &mut Context) -> Position {
+    context.editor.cursor().unwrap_or(Position { x: 0, y: 0 })
+}
+
+/// Callback removing the component on top, i.e. the popup handling the event
+pub fn close() -> EventCallback {
+    Box::new(|components, _| components.pop())
+}
+
+/// Text shown until the next key press, e.g. hover documentation
+pub struct Popup {
+    lines: Vec<String>,
+}
+
+impl Popup {
+    pub fn new(text: String) -> Self {
+        Self {
+            lines: text.lines().map(str::to_string).collect(),
+        }
+    }
+}
+
+impl Component for Popup {
+    /// Any key closes the popup, everything but Esc is handled as usual afterwards
+    fn handle_events(&mut self, event: &Event, _context: &mut Context) -> EventPropagation {
+        match event {
+            Event::Key(key) if key.code == KeyCode::Esc => EventPropagation::Consume(Some(close())),
+            Event::Key(_) | Event::Mouse(_) | Event::Paste(_) => {
+                EventPropagation::Ignore(Some(close()))
+            }
+            _ => EventPropagation::Ignore(None),
+        }
+    }
+
+    fn render(&self, f: &mut Frame<'_>, area: Rect, context: &mut Context) {
+        let longest = self
+            .lines
+            .iter()
+            .map(|line| line.width())
+            .max()
+            .unwrap_or(0);
+        let width = (longest as u16 + 2).min(MAX_WIDTH).min(area.width);
+
+        // rows needed once long lines wrap
+        let inner_width = width.saturating_sub(2).max(1) as usize;
+        let rows: usize = self
+            .lines
+            .iter()
+            .map(|line| line.width().div_ceil(inner_width).max(1))
+            .sum();
+        let height = (rows as u16).min(MAX_HEIGHT) + 2;
+
+        let popup_area = area_near(&anchor(context), width, height, area);
+
+        let text: Vec<Line> = self
+            .lines
+            .iter()
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        let paragraph = Paragraph::new(text)
+            .wrap(Wrap { trim: false })
+            .style(Style::default().bg(BACKGROUND).fg(Color::White))
+            .block(Block::default().borders(Borders::ALL));
+
+        f.render_widget(Clear, popup_area);
+        f.render_widget(paragraph, popup_area);
+    }
+}
+
+type OnSelect = Box<dyn FnOnce(&mut Context, usize)>;
+
+/// List to pick an item from with `j`/`k` and Enter, Esc closes it
+pub struct Menu {
+    items: Vec<String>,
+    selected: usize,
+    on_select: Option<OnSelect>,
+}
+
+impl Menu {
+    pub fn new(items: Vec<String>, on_select: impl FnOnce(&mut Context, usize) + 'static) -> Self {
+        Self {
+            items,
+            selected: 0,
+            on_select: Some(Box::new(on_select)),
+        }
+    }
+
+    fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.items.len().max(1);
+    }
+
+    fn select_previous(&mut self) {
+        let len = self.items.len().max(1);
+        self.selected = (self.selected + len - 1) % len;
+    }
+}
+
+impl Component for Menu {
+    fn handle_events(&mut self, event: &Event, _context: &mut Context) -> EventPropagation {
+        let Event::Key(key) = event else {
+            return EventPropagation::Ignore(None);
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Char('n') if ctrl => self.select_next(),
+            KeyCode::Char('p') if ctrl => self.select_previous(),
+            KeyCode::Char('j') | KeyCode::Down | KeyCode::Tab => self.select_next(),
+            KeyCode::Char('k') | KeyCode::Up | KeyCode::BackTab => self.select_previous(),
+            KeyCode::Enter => {
+                let index = self.selected;
+                let on_select = self.on_select.take();
+                return EventPropagation::Consume(Some(Box::new(move |components, context| {
+                    components.pop();
+                    if let Some(on_select) = on_select {
+                        on_select(context, index);
+                    }
+                })));
+            }
+            KeyCode::Esc | KeyCode::Char('q') => return EventPropagation::Consume(Some(close())),
+            _ => {}
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    fn render(&self, f: &mut Frame<'_>, area: Rect, context: &mut Context) {
+        let longest = self
+            .items
+            .iter()
+            .map(|item| item.width())
+            .max()
+            .unwrap_or(0);
+        let width = (longest as u16 + 2).min(area.width);
+        let height = self.items.len().min(MENU_ITEMS) as u16 + 2;
+
+        let menu_area = area_near(&anchor(context), width, height, area);
+        let visible = menu_area.height.saturating_sub(2).max(1) as usize;
+        let first = (self.selected + 1).saturating_sub(visible);
+
+        let lines: Vec<Line> = self
+            .items
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .map(|(index, item)| {
+                let line = Line::raw(item.as_str());
+                match index == self.selected {
+                    true => line.style(Style::default().bg(SELECTED)),
+                    false => line,
+                }
+            })
+            .collect();
+
+        let paragraph = Paragraph::new(lines)
+            .style(Style::default().bg(BACKGROUND).fg(Color::White))
+            .block(Block::default().borders(Borders::ALL));
+
+        f.render_widget(Clear, menu_area);
+        f.render_widget(paragraph, menu_area);
+    }
+}
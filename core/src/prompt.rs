@@ -1,4 +1,5 @@
 use crate::{
+    commands,
     components::{Component, Context, EventPropagation, Position},
     mode::Mode,
     terminal::Event,
@@ -16,6 +17,13 @@ impl CommandPrompt {
         Self::default()
     }
 
+    /// Prompt starting with `input` already typed, e.g. a range
+    pub fn with_input(input: impl Into<String>) -> Self {
+        Self {
+            input: input.into(),
+        }
+    }
+
     fn handle_key_events(&mut self, event: &KeyEvent, context: &mut Context) -> EventPropagation {
         let _event_context = Context {
             editor: context.editor,
@@ -29,8 +37,15 @@ impl CommandPrompt {
                 self.input.pop();
             }
             KeyCode::Enter => {
-                // TODO: take command and do something with it
-                todo!();
+                let input = std::mem::take(&mut self.input);
+                return EventPropagation::Consume(Some(Box::new(move |components, context| {
+                    context.editor.mode = Mode::Normal;
+                    components.pop();
+
+                    if let Err(err) = commands::execute(components, context, &input) {
+                        context.editor.set_error(err.to_string());
+                    }
+                })));
             }
             KeyCode::Esc => {
                 return EventPropagation::Consume(Some(Box::new(|components, context| {
@@ -55,6 +70,12 @@ impl Component for CommandPrompt {
     ) -> crate::components::EventPropagation {
         match event {
             Event::Key(key_event) => self.handle_key_events(key_event, context),
+            Event::Paste(text) => {
+                // a command is a single line
+                let line = text.lines().next().unwrap_or_default();
+                self.input.push_str(line);
+                EventPropagation::Consume(None)
+            }
             _ => EventPropagation::Ignore(None),
         }
     }
@@ -93,11 +114,8 @@ impl<'a> Widget for PromptLine<'a> {
 
         buf.set_string(0, y, ":", style);
 
-        let mut x = 1;
-        for char in self.text.chars() {
+        for (x, char) in (1..).zip(self.text.chars()) {
             buf.set_string(x, y, char.to_string(), style);
-
-            x += 1;
         }
     }
 }
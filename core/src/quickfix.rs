@@ -0,0 +1,371 @@
+//! List of positions to step through, e.g. compiler errors or search results
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{
+    layout::Rect,
+    style::Style,
+    text::Line,
+    widgets::{Block, Borders, Clear, Paragraph},
+};
+use regex::Regex;
+
+use crate::{
+    components::{Component, Context, EventPropagation},
+    editor::{Editor, OpenAction},
+    movements,
+    popup::{BACKGROUND, SELECTED},
+    shell,
+    terminal::{Event, Frame},
+};
+
+/// Entries shown at once by the panel
+const PANEL_ENTRIES: usize = 10;
+/// Error file read by `:cfile` without an argument
+pub const DEFAULT_ERROR_FILE: &str = "errors.err";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuickfixEntry {
+    pub path: PathBuf,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in chars
+    pub column: usize,
+    pub text: String,
+}
+
+impl QuickfixEntry {
+    /// `path:line:col: text`
+    pub fn label(&self) -> String {
+        format!(
+            "{}:{}:{}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.text
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Quickfix {
+    /// What filled the list, e.g. the `:make` command
+    pub title: String,
+    pub entries: Vec<QuickfixEntry>,
+    /// Entry last jumped to
+    pub current: Option<usize>,
+}
+
+impl Quickfix {
+    /// Replace the entries, the next jump goes to the first one
+    pub fn set(&mut self, title: impl Into<String>, entries: Vec<QuickfixEntry>) {
+        self.title = title.into();
+        self.entries = entries;
+        self.current = None;
+    }
+}
+
+/// Compile the configured error patterns
+pub fn error_patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|err| eyre!("Invalid error pattern {pattern}: {err}"))
+        })
+        .collect()
+}
+
+/// Entries of the lines matching one of the patterns, the first matching one wins
+pub fn parse(output: &str, patterns: &[Regex]) -> Vec<QuickfixEntry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let captures = patterns.iter().find_map(|pattern| pattern.captures(line))?;
+            let number = |name| captures.name(name)?.as_str().parse::<usize>().ok();
+
+            Some(QuickfixEntry {
+                path: PathBuf::from(captures.name("file")?.as_str()),
+                line: number("line")?.max(1),
+                column: number("col").unwrap_or(1).max(1),
+                text: captures
+                    .name("message")
+                    .map_or(line, |message| message.as_str())
+                    .to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Run the make command in the background and fill the list from what it printed
+pub fn make(editor: &mut Editor, args: &[&str]) -> Result<()> {
+    let patterns = error_patterns(&editor.config.error_patterns)?;
+    let mut cmd = editor.config.make_command.clone();
+    for arg in args {
+        cmd.push(' ');
+        cmd.push_str(arg);
+    }
+
+    editor.set_status(format!("Running {cmd}"));
+    let run = shell::run(shell::command(&cmd), None);
+    editor
+        .jobs
+        .spawn_then(run, move |result, components, context| {
+            let editor = &mut context.editor;
+            let output = match result {
+                Ok(output) => output,
+                Err(err) => return editor.set_error(format!("Failed to run {cmd}: {err}")),
+            };
+
+            let mut text = output.stdout.clone();
+            text.push_str(&output.stderr);
+            let entries = parse(&text, &patterns);
+            let success = output.success();
+            let failure = output.error_message();
+
+            let found = !entries.is_empty();
+            editor.quickfix.set(cmd, entries);
+            if found {
+                if let Err(err) = jump(editor, 0) {
+                    editor.set_error(err.to_string());
+                }
+                components.push(Box::new(QuickfixPanel::new(editor)));
+            } else if success {
+                editor.set_status("No errors");
+            } else {
+                editor.set_error(failure);
+            }
+        });
+
+    Ok(())
+}
+
+/// Fill the list from an error file and jump to the first entry
+pub fn read_error_file(editor: &mut Editor, path: &Path) -> Result<()> {
+    let patterns = error_patterns(&editor.config.error_patterns)?;
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| eyre!("Can't read {}: {err}", path.display()))?;
+
+    editor
+        .quickfix
+        .set(path.display().to_string(), parse(&text, &patterns));
+    step(editor, true)
+}
+
+/// Open the file of an entry and place the cursor on it
+pub fn jump(editor: &mut Editor, index: usize) -> Result<()> {
+    let Some(entry) = editor.quickfix.entries.get(index).cloned() else {
+        bail!("No quickfix entries");
+    };
+    editor.quickfix.current = Some(index);
+
+    editor.open(&entry.path, OpenAction::Replace)?;
+    movements::goto_line(&mut Context { editor }, entry.line, Some(entry.column));
+
+    let scrolloff = editor.config.scrolloff;
+    if let Some(window) = editor.windows.get_focused_mut() {
+        let buf = editor.buffers.get(window.buffer_id).unwrap();
+        window.position_cursor_in_view(buf, scrolloff);
+    }
+
+    let total = editor.quickfix.entries.len();
+    editor.set_status(format!("({} of {total}) {}", index + 1, entry.text.trim()));
+    Ok(())
+}
+
+/// Jump to the entry after or before the current one
+pub fn step(editor: &mut Editor, forward: bool) -> Result<()> {
+    let len = editor.quickfix.entries.len();
+    if len == 0 {
+        bail!("No quickfix entries");
+    }
+
+    let index = match (editor.quickfix.current, forward) {
+        (None, _) => 0,
+        (Some(current), true) if current + 1 < len => current + 1,
+        (Some(current), false) if current > 0 => current - 1,
+        _ => bail!("No more items"),
+    };
+    jump(editor, index)
+}
+
+/// Panel at the bottom of the screen listing the entries.
+///
+/// `j`/`k` select, Enter jumps to the selected entry and closes the panel, `q`
+/// or Esc close it.
+pub struct QuickfixPanel {
+    selected: usize,
+}
+
+impl QuickfixPanel {
+    /// Panel starting at the current entry
+    pub fn new(editor: &Editor) -> Self {
+        Self {
+            selected: editor.quickfix.current.unwrap_or(0),
+        }
+    }
+}
+
+impl Component for QuickfixPanel {
+    fn handle_events(&mut self, event: &Event, context: &mut Context) -> EventPropagation {
+        let Event::Key(key) = event else {
+            return EventPropagation::Ignore(None);
+        };
+        let len = context.editor.quickfix.entries.len();
+        let close = || -> EventPropagation {
+            EventPropagation::Consume(Some(Box::new(|components, _| components.pop())))
+        };
+        if len == 0 {
+            return close();
+        }
+
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match (key.code, ctrl) {
+            (KeyCode::Char('j') | KeyCode::Down, _) | (KeyCode::Char('n'), true) => {
+                self.selected = (self.selected + 1).min(len - 1)
+            }
+            (KeyCode::Char('k') | KeyCode::Up, _) | (KeyCode::Char('p'), true) => {
+                self.selected = self.selected.saturating_sub(1)
+            }
+            (KeyCode::Char('g'), false) => self.selected = 0,
+            (KeyCode::Char('G'), _) => self.selected = len - 1,
+            (KeyCode::Enter, _) => {
+                let selected = self.selected;
+                return EventPropagation::Consume(Some(Box::new(move |components, context| {
+                    components.pop();
+                    if let Err(err) = jump(context.editor, selected) {
+                        context.editor.set_error(err.to_string());
+                    }
+                })));
+            }
+            (KeyCode::Esc | KeyCode::Char('q'), _) => return close(),
+            _ => {}
+        }
+
+        EventPropagation::Consume(None)
+    }
+
+    fn render(&self, f: &mut Frame<'_>, area: Rect, context: &mut Context) {
+        let quickfix = &context.editor.quickfix;
+
+        // keep the last line for messages
+        let height = (quickfix.entries.len().min(PANEL_ENTRIES) as u16 + 2)
+            .min(area.height.saturating_sub(1));
+        let panel_area = Rect {
+            y: area.bottom().saturating_sub(1 + height),
+            height,
+            ..area
+        };
+
+        let title = format!(
+            " Quickfix: {} ({}) ",
+            quickfix.title,
+            quickfix.entries.len()
+        );
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .style(Style::default().bg(BACKGROUND));
+        let inner = block.inner(panel_area);
+
+        let visible = inner.height.max(1) as usize;
+        let selected = self.selected.min(quickfix.entries.len().saturating_sub(1));
+        let first = (selected + 1).saturating_sub(visible);
+        let lines: Vec<Line> = quickfix
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .map(|(index, entry)| {
+                let line = Line::from(entry.label());
+                match index == selected {
+                    true => line.style(Style::default().bg(SELECTED)),
+                    false => line,
+                }
+            })
+            .collect();
+
+        f.render_widget(Clear, panel_area);
+        f.render_widget(Paragraph::new(lines).block(block), panel_area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::{config::Config, test_harness::TestApp};
+
+    #[test]
+    fn parses_compiler_output() {
+        let output = "\
+   Compiling demo v0.1.0
+src/main.rs:2:5: error[E0425]: cannot find value `x` in this scope
+warning: unused import
+src/lib.rs:7: note: from a tool without columns
+error: could not compile `demo` (bin \"demo\") due to 1 previous error
+";
+        let patterns = error_patterns(&Config::default().error_patterns).unwrap();
+
+        assert_eq!(
+            parse(output, &patterns),
+            vec![
+                QuickfixEntry {
+                    path: PathBuf::from("src/main.rs"),
+                    line: 2,
+                    column: 5,
+                    text: "error[E0425]: cannot find value `x` in this scope".to_string(),
+                },
+                QuickfixEntry {
+                    path: PathBuf::from("src/lib.rs"),
+                    line: 7,
+                    column: 1,
+                    text: "note: from a tool without columns".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn make_fills_the_list_and_jumps_to_entries() {
+        let (dir, mut app) =
+            TestApp::with_files(&[("main.rs", "fn main() {\n    let a = b;\n    c();\n}\n")]).await;
+
+        let path = dir.path().join("main.rs");
+        let path = path.display();
+        app.app.editor.config.make_command =
+            format!("printf '{path}:2:13: error: no b\\n{path}:3:5: error: no c\\n' >&2; false");
+
+        app.keys(":make<CR>").await;
+        app.wait_for_job().await;
+        assert_eq!(app.cursor(), (1, 12));
+        assert_eq!(
+            app.app.editor.status.as_ref().unwrap().text,
+            "(1 of 2) error: no b"
+        );
+        assert!((0..24).any(|y| app.screen_line(y).contains("3:5: error: no c")));
+
+        // the panel jumps to the selected entry
+        app.keys("j<CR>").await;
+        assert_eq!(app.cursor(), (2, 4));
+
+        app.keys(":cp<CR>").await;
+        assert_eq!(app.cursor(), (1, 12));
+
+        // an error file with the same lines
+        let errors = dir.path().join("errors.err");
+        fs::write(&errors, format!("{path}:3:5: error: no c\n")).unwrap();
+        app.keys(&format!(":cfile {}<CR>", errors.display())).await;
+        assert_eq!(app.cursor(), (2, 4));
+
+        app.keys(":copen<CR>").await;
+        assert!((0..24).any(|y| app.screen_line(y).contains("Quickfix: ")));
+        app.keys("q").await;
+        assert!(!(0..24).any(|y| app.screen_line(y).contains("Quickfix: ")));
+    }
+}
@@ -0,0 +1,168 @@
+use color_eyre::eyre::{bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A single line of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptStep {
+    /// Line starting with `:`, run as a command
+    Command(String),
+    /// Any other line, sent as keys in key notation such as `ihello<Esc>`
+    Keys(Vec<KeyEvent>),
+}
+
+/// A step with the 1-based line it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptLine {
+    pub line: usize,
+    pub step: ScriptStep,
+}
+
+/// Parse a script, skipping empty lines and lines starting with `"`
+pub fn parse(script: &str) -> Result<Vec<ScriptLine>> {
+    let mut steps = vec![];
+
+    for (index, line) in script.lines().enumerate() {
+        let line_number = index + 1;
+
+        if line.trim().is_empty() || line.trim_start().starts_with('"') {
+            continue;
+        }
+
+        let step = match line.strip_prefix(':') {
+            Some(command) => ScriptStep::Command(command.to_string()),
+            None => match parse_keys(line) {
+                Ok(keys) => ScriptStep::Keys(keys),
+                Err(err) => bail!("line {line_number}: {err}"),
+            },
+        };
+
+        steps.push(ScriptLine {
+            line: line_number,
+            step,
+        });
+    }
+
+    Ok(steps)
+}
+
+/// Parse key notation, e.g. `dd`, `<C-w>`, `<Esc>` or `<lt>` for a literal `<`
+pub fn parse_keys(keys: &str) -> Result<Vec<KeyEvent>> {
+    let mut events = vec![];
+    let mut rest = keys;
+
+    while let Some(char) = rest.chars().next() {
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|after| after.split_once('>'))
+            .filter(|(name, _)| !name.is_empty() && !name.contains('<'));
+
+        match special {
+            Some((name, after)) => {
+                events.push(parse_special_key(name)?);
+                rest = after;
+            }
+            None => {
+                events.push(KeyEvent::new(KeyCode::Char(char), KeyModifiers::NONE));
+                rest = &rest[char.len_utf8()..];
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+fn parse_special_key(name: &str) -> Result<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut key = name;
+
+    loop {
+        let lower = key.to_ascii_lowercase();
+        let modifier = match lower.get(..2) {
+            Some("c-") => KeyModifiers::CONTROL,
+            Some("a-") | Some("m-") => KeyModifiers::ALT,
+            Some("s-") => KeyModifiers::SHIFT,
+            _ => break,
+        };
+        if key.len() == 2 {
+            break;
+        }
+        modifiers.insert(modifier);
+        key = &key[2..];
+    }
+
+    let code = match key.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "tab" => KeyCode::Tab,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        "del" => KeyCode::Delete,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ if key.chars().count() == 1 => KeyCode::Char(key.chars().next().unwrap()),
+        _ => bail!("Unknown key <{name}>"),
+    };
+
+    Ok(KeyEvent::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn parses_key_notation() {
+        assert_eq!(
+            parse_keys("ix<Esc><lt><C-w>").unwrap(),
+            vec![
+                key(KeyCode::Char('i')),
+                key(KeyCode::Char('x')),
+                key(KeyCode::Esc),
+                key(KeyCode::Char('<')),
+                KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+            ]
+        );
+        assert_eq!(
+            parse_keys("a<b").unwrap(),
+            vec![
+                key(KeyCode::Char('a')),
+                key(KeyCode::Char('<')),
+                key(KeyCode::Char('b'))
+            ]
+        );
+        assert!(parse_keys("<Nope>").is_err());
+    }
+
+    #[test]
+    fn parses_script_lines() {
+        let steps = parse("\" comment\n:w out.txt\n\nj<CR>\n").unwrap();
+
+        assert_eq!(
+            steps,
+            vec![
+                ScriptLine {
+                    line: 2,
+                    step: ScriptStep::Command("w out.txt".to_string()),
+                },
+                ScriptLine {
+                    line: 4,
+                    step: ScriptStep::Keys(vec![key(KeyCode::Char('j')), key(KeyCode::Enter)]),
+                },
+            ]
+        );
+    }
+}
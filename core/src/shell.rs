@@ -0,0 +1,190 @@
+use std::{io, ops::Range, process::Stdio};
+
+use ropey::Rope;
+use text::next_grapheme_boundary;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{buffer::BufferId, cursor::Cursor, editor::OpenAction, jobs::JobCallback};
+
+/// Output of a finished shell command
+pub struct ShellOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Exit code, `None` when killed by a signal
+    pub code: Option<i32>,
+}
+
+impl ShellOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    /// Message describing a failed run, preferring the first line of stderr
+    pub fn error_message(&self) -> String {
+        let code = match self.code {
+            Some(code) => format!("shell returned {code}"),
+            None => "shell killed by a signal".to_string(),
+        };
+
+        match self.stderr.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => format!("{code}: {line}"),
+            None => code,
+        }
+    }
+}
+
+/// `$SHELL -c cmd`, falling back to `sh`
+pub fn command(cmd: &str) -> Command {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+
+    let mut command = Command::new(shell);
+    command.arg("-c").arg(cmd).kill_on_drop(true);
+    command
+}
+
+/// Run a command, feeding it `input` on stdin
+pub async fn run(mut command: Command, input: Option<String>) -> io::Result<ShellOutput> {
+    command
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command.spawn()?;
+
+    // write from a separate task so a command filling its stdout pipe can't block us
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        tokio::spawn(async move {
+            let _ = stdin.write_all(input.as_bytes()).await;
+        });
+    }
+
+    let output = child.wait_with_output().await?;
+
+    Ok(ShellOutput {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code(),
+    })
+}
+
+/// Job for `:!cmd`, showing everything the command printed in a read-only scratch buffer
+pub async fn run_to_scratch(cmd: String) -> JobCallback {
+    let result = run(command(&cmd), None).await;
+
+    Box::new(move |_components, context| {
+        let editor = &mut context.editor;
+
+        let output = match result {
+            Ok(output) => output,
+            Err(err) => {
+                editor.set_error(format!("Failed to run {cmd}: {err}"));
+                return;
+            }
+        };
+
+        let mut text = output.stdout.clone();
+        text.push_str(&output.stderr);
+
+        let buffer_id = editor.buffers.add(Rope::from(text), None);
+        editor.buffers.get_mut(buffer_id).unwrap().readonly = true;
+
+        // reuse the window of the previous output
+        let previous = editor.shell_output.replace(buffer_id);
+        let previous_window = previous
+            .and_then(|previous| editor.windows.get_by_buffer_id(previous))
+            .map(|window| window.id);
+        match previous_window {
+            Some(window_id) => {
+                editor.windows.focus(window_id);
+                editor.show_buffer(buffer_id, OpenAction::Replace);
+            }
+            None => editor.show_buffer(buffer_id, OpenAction::HorizontalSplit),
+        }
+
+        // and drop the previous output unless it is still shown or was edited
+        if let Some(previous) = previous {
+            let unused = editor.windows.get_by_buffer_id(previous).is_none()
+                && editor
+                    .buffers
+                    .get(previous)
+                    .is_some_and(|buf| !buf.is_modified());
+            if unused {
+                editor.remove_buffer(previous);
+            }
+        }
+
+        match output.success() {
+            true => editor.set_status(format!(":!{cmd}")),
+            false => editor.set_error(output.error_message()),
+        }
+    })
+}
+
+/// Job for `:{range}!cmd`, replacing `chars` with the output of the command fed `input`.
+///
+/// Nothing changes when the command fails or the text was edited in the meantime.
+pub async fn filter(
+    buffer_id: BufferId,
+    chars: Range<usize>,
+    input: String,
+    cmd: String,
+) -> JobCallback {
+    let result = run(command(&cmd), Some(input.clone())).await;
+
+    Box::new(move |_components, context| {
+        let editor = &mut context.editor;
+
+        let mut output = match result {
+            Ok(output) if output.success() => output.stdout,
+            Ok(output) => {
+                editor.set_error(output.error_message());
+                return;
+            }
+            Err(err) => {
+                editor.set_error(format!("Failed to run {cmd}: {err}"));
+                return;
+            }
+        };
+
+        let Some(buf) = editor.buffers.get_mut(buffer_id) else {
+            return;
+        };
+
+        let content = buf.content();
+        if chars.end > content.len_chars() || content.slice(chars.clone()) != input.as_str() {
+            editor.set_error("Text changed while filtering, not replaced");
+            return;
+        }
+
+        // keep a last line without newline that way
+        if !input.ends_with('\n') && output.ends_with('\n') {
+            output.pop();
+        }
+
+        buf.commit();
+        let result = buf
+            .remove(chars.clone())
+            .and_then(|_| buf.insert(chars.start, &output));
+        buf.commit();
+
+        if let Err(err) = result {
+            editor.set_error(err.to_string());
+            return;
+        }
+
+        if let Some(window) = editor
+            .windows
+            .get_focused()
+            .filter(|window| window.buffer_id == buffer_id)
+        {
+            let end = next_grapheme_boundary(&buf.content().slice(..), chars.start);
+            buf.set_cursor(window.id, Cursor::with_range(chars.start, end));
+        }
+
+        let lines = input.lines().count();
+        editor.set_status(format!("{lines} lines filtered"));
+    })
+}
@@ -1,4 +1,6 @@
 use std::{
+    fs::OpenOptions,
+    io::{IsTerminal, Write},
     ops::{Deref, DerefMut},
     time::Duration,
 };
@@ -13,19 +15,67 @@ use crossterm::{
     terminal::{EnterAlternateScreen, LeaveAlternateScreen},
 };
 use futures::{FutureExt, StreamExt};
-use ratatui::backend::CrosstermBackend as Backend;
+use ratatui::backend::{Backend, CrosstermBackend, TestBackend};
+use signal_hook::{
+    consts::{SIGCONT, SIGTSTP},
+    iterator::Signals,
+};
 use tokio::{
     sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
     task::JoinHandle,
 };
 use tokio_util::sync::CancellationToken;
 
-pub type IO = std::io::Stdout;
+/// Terminal output, the controlling tty when stdout is redirected
+pub enum IO {
+    Stdout(std::io::Stdout),
+    Tty(std::fs::File),
+}
+
 pub fn io() -> IO {
-    std::io::stdout()
+    let stdout = std::io::stdout();
+    if !stdout.is_terminal() {
+        if let Ok(tty) = OpenOptions::new().write(true).open("/dev/tty") {
+            return IO::Tty(tty);
+        }
+    }
+    IO::Stdout(stdout)
+}
+
+impl Write for IO {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            IO::Stdout(stdout) => stdout.write(buf),
+            IO::Tty(tty) => tty.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            IO::Stdout(stdout) => stdout.flush(),
+            IO::Tty(tty) => tty.flush(),
+        }
+    }
 }
+
 pub type Frame<'a> = ratatui::Frame<'a>;
 
+/// Backend the editor can draw on
+pub trait EditorBackend: Backend {
+    /// Whether the backend owns the real terminal, handling raw mode and reading its input.
+    ///
+    /// Other backends only receive the events sent through [`Terminal::event_tx`]
+    const INTERACTIVE: bool;
+}
+
+impl EditorBackend for CrosstermBackend<IO> {
+    const INTERACTIVE: bool = true;
+}
+
+impl EditorBackend for TestBackend {
+    const INTERACTIVE: bool = false;
+}
+
 pub enum Event {
     Error,
     FocusGained,
@@ -35,13 +85,15 @@ pub enum Event {
     Paste(String),
     Render,
     Resize(u16, u16),
+    /// The process was continued after being stopped, the screen needs a full redraw
+    Resume,
     /// Tick duration used for updating ui and other things
     /// Defaulted to 250ms
     Tick,
 }
 
-pub struct Terminal {
-    pub terminal: ratatui::Terminal<Backend<IO>>,
+pub struct Terminal<B: EditorBackend = CrosstermBackend<IO>> {
+    pub terminal: ratatui::Terminal<B>,
     pub task: JoinHandle<()>,
     pub cancellation_token: CancellationToken,
     pub event_rx: UnboundedReceiver<Event>,
@@ -50,19 +102,33 @@ pub struct Terminal {
     pub tick_rate: f64,
     pub mouse: bool,
     pub paste: bool,
+    /// Stops the thread forwarding SIGCONT as [`Event::Resume`]
+    signals: Option<signal_hook::iterator::Handle>,
 }
 
 impl Terminal {
     pub fn new() -> Result<Self> {
+        Self::with_backend(CrosstermBackend::new(io()))
+    }
+}
+
+impl<B: EditorBackend> Terminal<B> {
+    pub fn with_backend(backend: B) -> Result<Self> {
         let tick_rate = 250.0;
         let frame_rate = 60.0;
 
-        let terminal = ratatui::Terminal::new(Backend::new(io()))?;
+        let terminal = ratatui::Terminal::new(backend)?;
         let (event_tx, event_rx) = mpsc::unbounded_channel();
         let cancellation_token = CancellationToken::new();
         let task = tokio::spawn(async {});
         let mouse = false;
         let paste = false;
+
+        let signals = match B::INTERACTIVE {
+            true => Some(forward_resume(event_tx.clone())?),
+            false => None,
+        };
+
         Ok(Self {
             terminal,
             task,
@@ -73,6 +139,7 @@ impl Terminal {
             tick_rate,
             mouse,
             paste,
+            signals,
         })
     }
 
@@ -97,6 +164,10 @@ impl Terminal {
     }
 
     pub fn start(&mut self) {
+        if !B::INTERACTIVE {
+            return;
+        }
+
         let tick_delay = std::time::Duration::from_millis(250);
         let render_delay = std::time::Duration::from_secs_f64(1.0 / self.frame_rate);
         self.cancel();
@@ -176,6 +247,10 @@ impl Terminal {
     }
 
     pub fn enter(&mut self) -> Result<()> {
+        if !B::INTERACTIVE {
+            return Ok(());
+        }
+
         crossterm::terminal::enable_raw_mode()?;
         crossterm::execute!(io(), EnterAlternateScreen, cursor::Hide)?;
         if self.mouse {
@@ -190,7 +265,7 @@ impl Terminal {
 
     pub fn exit(&mut self) -> Result<()> {
         self.stop()?;
-        if crossterm::terminal::is_raw_mode_enabled()? {
+        if B::INTERACTIVE && crossterm::terminal::is_raw_mode_enabled()? {
             self.flush()?;
             if self.paste {
                 crossterm::execute!(io(), DisableBracketedPaste)?;
@@ -204,6 +279,21 @@ impl Terminal {
         Ok(())
     }
 
+    /// Give the terminal back to the shell and stop the process until it is continued
+    pub fn suspend(&mut self) -> Result<()> {
+        if !B::INTERACTIVE {
+            return Ok(());
+        }
+
+        self.exit()?;
+        signal_hook::low_level::raise(SIGTSTP)?;
+
+        // continued, take the screen back and redraw everything
+        self.enter()?;
+        self.clear()?;
+        Ok(())
+    }
+
     pub fn cancel(&self) {
         self.cancellation_token.cancel();
     }
@@ -211,24 +301,48 @@ impl Terminal {
     pub async fn next(&mut self) -> Option<Event> {
         self.event_rx.recv().await
     }
+
+    /// Next event if one is already queued
+    pub fn try_next(&mut self) -> Option<Event> {
+        self.event_rx.try_recv().ok()
+    }
 }
 
-impl Deref for Terminal {
-    type Target = ratatui::Terminal<Backend<IO>>;
+impl<B: EditorBackend> Deref for Terminal<B> {
+    type Target = ratatui::Terminal<B>;
 
     fn deref(&self) -> &Self::Target {
         &self.terminal
     }
 }
 
-impl DerefMut for Terminal {
+impl<B: EditorBackend> DerefMut for Terminal<B> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.terminal
     }
 }
 
-impl Drop for Terminal {
+/// Send [`Event::Resume`] whenever the process receives SIGCONT
+fn forward_resume(event_tx: UnboundedSender<Event>) -> Result<signal_hook::iterator::Handle> {
+    let mut signals = Signals::new([SIGCONT])?;
+    let handle = signals.handle();
+
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if event_tx.send(Event::Resume).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(handle)
+}
+
+impl<B: EditorBackend> Drop for Terminal<B> {
     fn drop(&mut self) {
+        if let Some(signals) = self.signals.take() {
+            signals.close();
+        }
         self.exit().unwrap();
     }
 }
@@ -0,0 +1,133 @@
+//! Drive the whole app on a [`TestBackend`] with scripted events
+//!
+//! ```ignore
+//! let mut app = TestApp::new(&["--clean", "file.txt"]).await;
+//! app.keys("ihello<Esc>").await;
+//! assert_eq!(app.text(), "hellofile contents\n");
+//! ```
+
+use clap::Parser;
+use crossterm::event::{KeyModifiers, MouseEvent, MouseEventKind};
+use ratatui::backend::TestBackend;
+
+use crate::{
+    app::App,
+    buffer::Buffer,
+    cli::Args,
+    script,
+    terminal::{Event, Terminal},
+};
+
+pub const WIDTH: u16 = 80;
+pub const HEIGHT: u16 = 24;
+
+/// Temporary file with `content`, removed when dropped
+pub fn file(content: &str) -> tempfile::NamedTempFile {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), content).unwrap();
+    file
+}
+
+pub struct TestApp {
+    pub app: App<TestBackend>,
+}
+
+impl TestApp {
+    /// Start the app with command line arguments, e.g. `&["--clean", "file.txt"]`
+    pub async fn new(args: &[&str]) -> Self {
+        Self::with_size(args, WIDTH, HEIGHT).await
+    }
+
+    /// Start the app on the first of `files`, given as paths in a new temporary
+    /// directory and their content, e.g. `&[("src/main.rs", "fn main() {}\n")]`
+    pub async fn with_files(files: &[(&str, &str)]) -> (tempfile::TempDir, Self) {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let path = dir.path().join(files[0].0);
+        let app = Self::new(&["--clean", path.to_str().unwrap()]).await;
+        (dir, app)
+    }
+
+    pub async fn with_size(args: &[&str], width: u16, height: u16) -> Self {
+        let args = Args::parse_from(std::iter::once("editor").chain(args.iter().copied()));
+        let terminal = Terminal::with_backend(TestBackend::new(width, height)).unwrap();
+
+        let mut app = App::with_terminal(args, Some(terminal)).unwrap();
+        app.wait_for_loads().await;
+        app.render_app().await;
+
+        Self { app }
+    }
+
+    /// Send keys in key notation, e.g. `ihello<Esc>`, and handle them
+    pub async fn keys(&mut self, keys: &str) {
+        for key in script::parse_keys(keys).unwrap() {
+            self.send(Event::Key(key));
+        }
+        self.app.handle_pending_events().await;
+    }
+
+    /// Queue an event as if it was read from the terminal and handle it
+    pub async fn event(&mut self, event: Event) {
+        self.send(event);
+        self.app.handle_pending_events().await;
+    }
+
+    /// Wait for a background job, e.g. a shell command, and apply its result
+    pub async fn wait_for_job(&mut self) {
+        self.app.handle_next_job().await;
+    }
+
+    /// Send a mouse event at a screen position
+    pub async fn mouse(&mut self, kind: MouseEventKind, x: u16, y: u16) {
+        self.event(Event::Mouse(MouseEvent {
+            kind,
+            column: x,
+            row: y,
+            modifiers: KeyModifiers::NONE,
+        }))
+        .await;
+    }
+
+    fn send(&mut self, event: Event) {
+        self.app.terminal().event_tx.send(event).unwrap();
+    }
+
+    /// Buffer shown in the focused window
+    pub fn buffer(&self) -> &Buffer {
+        let window = self.app.editor.windows.get_focused().unwrap();
+        self.app.editor.buffers.get(window.buffer_id).unwrap()
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer().content().to_string()
+    }
+
+    /// 0-based line and column of the cursor in the focused window
+    pub fn cursor(&self) -> (usize, usize) {
+        let window = self.app.editor.windows.get_focused().unwrap();
+        let content = self.buffer().content();
+
+        let char_idx = self.buffer().get_cursor(window.id).range.start;
+        let line = content.char_to_line(char_idx);
+        (line, char_idx - content.line_to_char(line))
+    }
+
+    /// Position of the terminal cursor after the last render
+    pub fn screen_cursor(&mut self) -> (u16, u16) {
+        self.app.terminal().get_cursor().unwrap()
+    }
+
+    /// Rendered symbols of a screen row, trailing spaces included
+    pub fn screen_line(&mut self, y: u16) -> String {
+        let buffer = self.app.terminal().backend().buffer().clone();
+        (0..buffer.area.width)
+            .map(|x| buffer.get(x, y).symbol())
+            .collect()
+    }
+}
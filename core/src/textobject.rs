@@ -0,0 +1,209 @@
+//! Text objects, e.g. `iw` or `af`, and selections following the syntax tree
+
+use std::ops::Range;
+
+use color_eyre::eyre::{bail, Result};
+use syntax::TextObject;
+use text::{prev_grapheme_boundary, textobject};
+
+use crate::{cursor::Cursor, editor::Editor};
+
+/// Text object selected by the key after `a` or `i`
+enum Object {
+    /// Found in the syntax tree, around the Visual mode selection
+    Syntax(TextObject),
+    Word {
+        long: bool,
+    },
+    Quotes(char),
+    Brackets(char, char),
+    Paragraph,
+    Tag,
+}
+
+fn object(key: char) -> Option<Object> {
+    let object = match key {
+        'f' => Object::Syntax(TextObject::Function),
+        'c' => Object::Syntax(TextObject::Class),
+        'a' | ',' => Object::Syntax(TextObject::Argument),
+        'w' => Object::Word { long: false },
+        'W' => Object::Word { long: true },
+        quote @ ('"' | '\'' | '`') => Object::Quotes(quote),
+        '(' | ')' | 'b' => Object::Brackets('(', ')'),
+        '{' | '}' | 'B' => Object::Brackets('{', '}'),
+        '[' | ']' => Object::Brackets('[', ']'),
+        '<' | '>' => Object::Brackets('<', '>'),
+        'p' => Object::Paragraph,
+        't' => Object::Tag,
+        _ => return None,
+    };
+    Some(object)
+}
+
+/// Chars of the Visual mode selection of the focused window, the cursor otherwise
+fn selection(editor: &Editor) -> Result<Range<usize>> {
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    Ok(window
+        .selection(buf)
+        .unwrap_or_else(|| buf.get_cursor(window.id).range.clone()))
+}
+
+/// Map the selection to bytes, ask the syntax tree for new ones and map them back to chars
+fn from_syntax(
+    editor: &mut Editor,
+    find: impl FnOnce(&syntax::Highlight, ropey::RopeSlice, Range<usize>) -> Option<Range<usize>>,
+) -> Result<Option<Range<usize>>> {
+    let selection = selection(editor)?;
+    let window = editor.windows.get_focused().unwrap();
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    let content = buf.content().clone();
+    let Some(highlight) = buf.syntax() else {
+        bail!("No syntax tree for this buffer");
+    };
+
+    let bytes = content.char_to_byte(selection.start)..content.char_to_byte(selection.end);
+    Ok(find(highlight, content.slice(..), bytes)
+        .map(|bytes| content.byte_to_char(bytes.start)..content.byte_to_char(bytes.end)))
+}
+
+/// Chars of the text object `key` at the cursor, syntax ones are around the selection in
+/// Visual mode
+pub fn range(editor: &mut Editor, key: char, around: bool) -> Result<Range<usize>> {
+    let Some(object) = object(key) else {
+        bail!("Unknown text object: {key}");
+    };
+    let Some(window) = editor.windows.get_focused() else {
+        bail!("No buffer");
+    };
+    let buf = editor.buffers.get(window.buffer_id).unwrap();
+    let content = buf.content().clone();
+    let content = content.slice(..);
+    let pos = buf.get_cursor(window.id).range.start;
+
+    let found = match object {
+        Object::Syntax(object) => from_syntax(editor, |highlight, content, bytes| {
+            highlight.text_object(content, bytes, object, around)
+        })?,
+        Object::Word { long } => textobject::word(&content, pos, long, around),
+        Object::Quotes(quote) => textobject::quotes(&content, pos, quote, around),
+        Object::Brackets(open, close) => textobject::brackets(&content, pos, (open, close), around),
+        Object::Paragraph => textobject::paragraph(&content, pos, around),
+        Object::Tag => textobject::tag(&content, pos, around),
+    };
+    match found {
+        Some(range) if !range.is_empty() => Ok(range),
+        _ => bail!("No text object found"),
+    }
+}
+
+/// Select chars in Visual mode, the cursor on the last one
+fn select_range(editor: &mut Editor, range: Range<usize>) {
+    editor.start_visual(Some(range.start));
+    let Some(window) = editor.windows.get_focused() else {
+        return;
+    };
+    let buf = editor.buffers.get_mut(window.buffer_id).unwrap();
+    let start = prev_grapheme_boundary(&buf.content().slice(..), range.end).max(range.start);
+    buf.set_cursor(
+        window.id,
+        Cursor {
+            range: start..range.end,
+        },
+    );
+}
+
+/// Select the text object `key`, `af` and the like in Visual mode
+pub fn select(editor: &mut Editor, key: char, around: bool) -> Result<()> {
+    let range = range(editor, key, around)?;
+    select_range(editor, range);
+    Ok(())
+}
+
+/// Select the syntax node around the selection or the cursor
+pub fn expand_selection(editor: &mut Editor) -> Result<()> {
+    let Some(range) = from_syntax(editor, |highlight, _, bytes| {
+        highlight.expand_selection(bytes)
+    })?
+    else {
+        bail!("No larger node");
+    };
+    select_range(editor, range);
+    Ok(())
+}
+
+/// Select the first syntax node inside the selection
+pub fn shrink_selection(editor: &mut Editor) -> Result<()> {
+    match from_syntax(editor, |highlight, _, bytes| {
+        highlight.shrink_selection(bytes)
+    })? {
+        Some(range) if !range.is_empty() => {
+            select_range(editor, range);
+            Ok(())
+        }
+        _ => bail!("No smaller node"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::test_harness::{file, TestApp};
+
+    /// Text of the Visual mode selection
+    fn selected(app: &TestApp) -> String {
+        let window = app.app.editor.windows.get_focused().unwrap();
+        let selection = window.selection(app.buffer()).unwrap();
+        app.buffer().content().slice(selection).to_string()
+    }
+
+    #[tokio::test]
+    async fn selects_and_filters_syntax_nodes() {
+        let text = "fn add(a: u8, b: u8) -> u8 {\n    a + b\n}\n\nfn main() {\n    let c = add(1, 2);\n}\n";
+        let (_dir, mut app) = TestApp::with_files(&[("main.rs", text)]).await;
+
+        app.keys("lllllllvia").await;
+        assert_eq!(selected(&app), "a: u8");
+        app.keys("<Esc>vaa").await;
+        assert_eq!(selected(&app), "a: u8, ");
+        app.keys("<A-o>").await;
+        assert_eq!(selected(&app), "(a: u8, b: u8)");
+        app.keys("<A-o>").await;
+        assert!(selected(&app).starts_with("fn add"));
+        app.keys("<A-i>").await;
+        assert_eq!(selected(&app), "add");
+
+        // operators act on the lines of the object
+        app.keys("<Esc>jjjj!if").await;
+        app.keys("sed s/c/d/<CR>").await;
+        app.wait_for_job().await;
+        assert!(app
+            .text()
+            .ends_with("fn main() {\n    let d = add(1, 2);\n}\n"));
+
+        app.keys("vac").await;
+        let status = app.app.editor.status.as_ref().unwrap();
+        assert_eq!(status.text, "No text object found");
+    }
+
+    #[tokio::test]
+    async fn selects_text_objects_of_any_file() {
+        let file = file("say(\"hi\", (a + b))\nnext\n\nlast\n");
+        let mut app = TestApp::new(&["--clean", file.path().to_str().unwrap()]).await;
+
+        app.keys("vi(").await;
+        assert_eq!(selected(&app), "\"hi\", (a + b)");
+        app.keys("<Esc>0vi\"").await;
+        assert_eq!(selected(&app), "hi");
+        app.keys("<Esc>vaw").await;
+        assert_eq!(selected(&app), "hi");
+
+        app.keys("<Esc>!ap").await;
+        app.keys("tr a-z A-Z<CR>").await;
+        app.wait_for_job().await;
+        assert_eq!(app.text(), "SAY(\"HI\", (A + B))\nNEXT\n\nlast\n");
+    }
+}
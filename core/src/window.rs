@@ -1,7 +1,8 @@
-use std::cmp;
+use std::{cmp, ops::Range};
 
-use ratatui::layout::{Constraint, Layout, Rect};
-use text::width;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use text::{next_grapheme_boundary, prev_grapheme_boundary, width};
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
     buffer::{Buffer, BufferId},
@@ -12,6 +13,9 @@ use crate::{
 pub struct Windows {
     pub nodes: Vec<Window>,
     pub focused_node: Option<WindowId>,
+    next_window_id: usize,
+    /// Whether windows are laid out side by side or stacked
+    direction: Direction,
 
     /// total area for windows
     area: Rect,
@@ -21,13 +25,15 @@ impl Windows {
     pub fn new(area: Rect) -> Self {
         Self {
             area,
+            direction: Direction::Horizontal,
             ..Default::default()
         }
     }
 
     pub fn add(&mut self, buffer_id: BufferId) -> WindowId {
         // when adding multiple windows, eventually handle different sizes with layout
-        let id = WindowId(self.nodes.len());
+        let id = WindowId(self.next_window_id);
+        self.next_window_id += 1;
         self.nodes.push(Window::new(id, buffer_id, self.area));
 
         self.reorder_window_size();
@@ -35,22 +41,83 @@ impl Windows {
         id
     }
 
+    /// Add a window laid out in `direction` next to the existing ones
+    pub fn split(&mut self, buffer_id: BufferId, direction: Direction) -> WindowId {
+        self.direction = direction;
+        self.add(buffer_id)
+    }
+
+    /// Close a window, focusing its neighbour if it was focused
+    pub fn remove(&mut self, id: WindowId) {
+        let Some(index) = self.nodes.iter().position(|node| node.id == id) else {
+            return;
+        };
+        self.nodes.remove(index);
+
+        if self.focused_node == Some(id) {
+            self.focused_node = None;
+            let next = index.min(self.nodes.len().saturating_sub(1));
+            if let Some(next) = self.nodes.get(next).map(|node| node.id) {
+                self.focus(next);
+            }
+        }
+
+        self.reorder_window_size();
+    }
+
     pub fn focus(&mut self, id: WindowId) {
+        if self.get(id).is_none() {
+            return;
+        }
+
+        self.focused_node = Some(id);
         for node in self.nodes.iter_mut() {
-            if node.id == id {
-                self.focused_node = Some(id);
-                node.focused = true;
-            }
+            node.focused = node.id == id;
+        }
+    }
+
+    /// Focus the window after the focused one, wrapping around
+    pub fn focus_next(&mut self) {
+        let Some(focused) = self.focused_node else {
+            return;
+        };
+        let index = self
+            .nodes
+            .iter()
+            .position(|node| node.id == focused)
+            .unwrap_or(0);
+
+        if let Some(next) = self.nodes.get((index + 1) % self.nodes.len()) {
+            self.focus(next.id);
         }
     }
 
+    pub fn get(&self, id: WindowId) -> Option<&Window> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.nodes.iter_mut().find(|node| node.id == id)
+    }
+
+    /// Window whose area contains the screen position
+    pub fn window_at(&self, x: u16, y: u16) -> Option<WindowId> {
+        self.nodes
+            .iter()
+            .find(|node| {
+                let area = node.area;
+                (area.left()..area.right()).contains(&x) && (area.top()..area.bottom()).contains(&y)
+            })
+            .map(|node| node.id)
+    }
+
     pub fn get_by_buffer_id(&self, buffer_id: BufferId) -> Option<&Window> {
         self.nodes.iter().find(|node| node.buffer_id == buffer_id)
     }
 
     pub fn get_focused(&self) -> Option<&Window> {
         if let Some(focused_node) = self.focused_node {
-            self.nodes.get(focused_node.0)
+            self.get(focused_node)
         } else {
             None
         }
@@ -58,7 +125,7 @@ impl Windows {
 
     pub fn get_focused_mut(&mut self) -> Option<&mut Window> {
         if let Some(focused_node) = self.focused_node {
-            self.nodes.get_mut(focused_node.0)
+            self.get_mut(focused_node)
         } else {
             None
         }
@@ -80,13 +147,25 @@ impl Windows {
         self.nodes.iter_mut()
     }
 
+    pub fn resize(&mut self, area: Rect) {
+        self.area = area;
+        self.reorder_window_size();
+    }
+
     pub fn reorder_window_size(&mut self) {
         let window_count = self.count() as u16;
+        if window_count == 0 {
+            return;
+        }
+
         let constraints: Vec<Constraint> = (0..window_count)
-            .map(|_| Constraint::Percentage(100 / window_count))
+            .map(|_| Constraint::Ratio(1, window_count as u32))
             .collect();
 
-        let window_layout = Layout::horizontal(constraints).split(self.area);
+        let window_layout = Layout::default()
+            .direction(self.direction)
+            .constraints(constraints)
+            .split(self.area);
 
         for (i, window) in &mut self.nodes.iter_mut().enumerate() {
             let area = window_layout[i];
@@ -95,6 +174,15 @@ impl Windows {
     }
 }
 
+/// Rows below the text used by the status line
+pub const STATUS_LINE_HEIGHT: u16 = 2;
+
+/// Columns left of the text showing signs, e.g. diagnostics
+pub const SIGN_COLUMN_WIDTH: u16 = 2;
+
+/// Columns left of the signs showing the commit of each line, see `:blame`
+pub const BLAME_WIDTH: u16 = 36;
+
 #[derive(Default, Copy, Clone)]
 pub struct Offset {
     pub vertical: usize,
@@ -110,6 +198,10 @@ pub struct Window {
     /// area for single window
     pub area: Rect,
     pub cursor: Cursor,
+    /// Char index where the Visual mode selection started
+    pub anchor: Option<usize>,
+    /// Chars of the last Visual mode selection, `'<,'>` in commands
+    pub last_selection: Option<Range<usize>>,
 }
 
 impl Window {
@@ -121,6 +213,8 @@ impl Window {
             offset: Offset::default(),
             area,
             cursor: Cursor::default(),
+            anchor: None,
+            last_selection: None,
         }
     }
 
@@ -128,6 +222,124 @@ impl Window {
         self.area = area;
     }
 
+    /// Part of the window showing text, without the status line
+    pub fn text_area(&self) -> Rect {
+        Rect {
+            height: self.area.height.saturating_sub(STATUS_LINE_HEIGHT),
+            ..self.area
+        }
+    }
+
+    /// Width of the sign column, only shown while the buffer has signs
+    pub fn sign_column_width(&self, buf: &Buffer) -> u16 {
+        let hunks = buf.git.as_ref().map_or(&[][..], |diff| &diff.hunks);
+        match buf.diagnostics.is_empty() && hunks.is_empty() {
+            true => 0,
+            false => SIGN_COLUMN_WIDTH.min(self.area.width),
+        }
+    }
+
+    /// Width of the blame panel, at most half of the window
+    pub fn blame_width(&self, buf: &Buffer) -> u16 {
+        match buf.blame {
+            Some(_) => BLAME_WIDTH.min(self.area.width / 2),
+            None => 0,
+        }
+    }
+
+    /// Part of the text area right of the blame panel and the sign column
+    pub fn content_area(&self, buf: &Buffer) -> Rect {
+        let text_area = self.text_area();
+        let left = self.blame_width(buf) + self.sign_column_width(buf);
+        Rect {
+            x: text_area.x + left,
+            width: text_area.width - left,
+            ..text_area
+        }
+    }
+
+    /// Char index shown at a screen position, clamped to the text of the clicked line.
+    ///
+    /// Clicking either half of a wide character selects it.
+    pub fn char_at(&self, buf: &Buffer, x: u16, y: u16) -> Option<usize> {
+        let area = self.content_area(buf);
+        if y < area.top() || y >= area.bottom() || x < area.left() {
+            return None;
+        }
+
+        let content = buf.content().slice(..);
+        let line_index = (self.offset.vertical + (y - area.y) as usize)
+            .min(content.len_lines().saturating_sub(1));
+        let column = self.offset.horizontal + (x - area.x) as usize;
+
+        let line_start = content.line_to_char(line_index);
+        let mut char_idx = line_start;
+        let mut line_width = 0;
+
+        for char in content.line(line_index).chars() {
+            if char == '\n' || char == '\r' {
+                break;
+            }
+
+            line_width += char.width().unwrap_or(0);
+            if line_width > column {
+                return Some(char_idx);
+            }
+            char_idx += 1;
+        }
+
+        // past the end of the line, stay on its last character
+        if char_idx > line_start {
+            char_idx = prev_grapheme_boundary(&content, char_idx);
+        }
+        Some(char_idx)
+    }
+
+    /// Scroll by `lines`, moving the cursor along when it would leave the view
+    pub fn scroll(&mut self, buf: &mut Buffer, lines: isize, scrolloff: usize) {
+        let content = buf.content().slice(..);
+        let last_line = content.len_lines().saturating_sub(1);
+
+        self.offset.vertical = self
+            .offset
+            .vertical
+            .saturating_add_signed(lines)
+            .min(last_line);
+
+        let height = (self.text_area().height as usize).max(1);
+        let scrolloff = scrolloff.min((height - 1) / 2);
+
+        let cursor = buf.get_cursor(self.id).range.start;
+        let line = content.char_to_line(cursor);
+
+        let top = (self.offset.vertical + scrolloff).min(last_line);
+        let bottom = (self.offset.vertical + height - 1).saturating_sub(scrolloff);
+        let new_line = buf
+            .folds
+            .row_start(line.clamp(top, bottom.max(top)).min(last_line));
+
+        if new_line != line {
+            let char_idx = content.line_to_char(new_line);
+            buf.set_cursor(
+                self.id,
+                Cursor {
+                    range: char_idx..next_grapheme_boundary(&content, char_idx),
+                },
+            );
+        }
+    }
+
+    /// Chars covered by the Visual mode selection, including the character under the cursor
+    pub fn selection(&self, buf: &Buffer) -> Option<Range<usize>> {
+        let anchor = self.anchor?;
+        let content = buf.content().slice(..);
+        let cursor = &buf.get_cursor(self.id).range;
+
+        let start = anchor.min(cursor.start);
+        let end = next_grapheme_boundary(&content, anchor).max(cursor.end);
+        Some(start..end)
+    }
+
     pub fn position_cursor_in_view(&mut self, buf: &Buffer, scrolloff: usize) {
         let height = (self.area.height as usize).saturating_sub(1);
 
@@ -136,11 +348,16 @@ impl Window {
 
         let y = content.char_to_line(cursor);
 
-        self.offset.vertical = cmp::min(self.offset.vertical, y.saturating_sub(scrolloff));
-        if y >= self.offset.vertical + height.saturating_sub(scrolloff)
+        // rows are counted, a closed fold takes one
+        let folds = &buf.folds;
+        self.offset.vertical = cmp::min(
+            folds.row_start(self.offset.vertical),
+            folds.rows_up(y, scrolloff),
+        );
+        if folds.rows_between(self.offset.vertical, y) >= height.saturating_sub(scrolloff)
             && content.len_lines() != (self.offset.vertical + height)
         {
-            self.offset.vertical = y.saturating_sub(height.saturating_sub(scrolloff)) + 1;
+            self.offset.vertical = folds.rows_up(y, height.saturating_sub(scrolloff) - 1);
         }
 
         let x = {
@@ -149,7 +366,7 @@ impl Window {
             width(&line_to_cursor)
         };
 
-        let screen_width = (self.area.width as usize).saturating_sub(1);
+        let screen_width = (self.content_area(buf).width as usize).saturating_sub(1);
         let cur_line_width = width(&content.line(y));
 
         self.offset.horizontal = cmp::min(self.offset.horizontal, x.saturating_sub(scrolloff));
@@ -0,0 +1,90 @@
+//! Talk to the `mock-lsp` binary over stdio like the editor does
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use pretty_assertions::assert_eq;
+use serde_json::{json, Value};
+
+fn send(stdin: &mut ChildStdin, message: Value) {
+    let body = message.to_string();
+    write!(stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+    stdin.flush().unwrap();
+}
+
+fn receive(stdout: &mut BufReader<ChildStdout>) -> Value {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        match line.trim_end().strip_prefix("Content-Length:") {
+            Some(value) => length = value.trim().parse().unwrap(),
+            None if line.trim_end().is_empty() => break,
+            None => {}
+        }
+    }
+
+    let mut body = vec![0; length];
+    stdout.read_exact(&mut body).unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn answers_over_stdio() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_mock-lsp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = server.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+
+    send(
+        &mut stdin,
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+    );
+    let response = receive(&mut stdout);
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"]["capabilities"]["hoverProvider"], true);
+
+    let uri = "file:///project/main.rs";
+    send(
+        &mut stdin,
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": {
+                "uri": uri, "languageId": "rust", "version": 0,
+                "text": "// TODO\nfn main() {}\n",
+            } },
+        }),
+    );
+    let diagnostics = receive(&mut stdout);
+    assert_eq!(diagnostics["method"], "textDocument/publishDiagnostics");
+    assert_eq!(
+        diagnostics["params"]["diagnostics"][0]["range"]["start"],
+        json!({ "line": 0, "character": 3 })
+    );
+
+    send(
+        &mut stdin,
+        json!({
+            "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover",
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": 1, "character": 4 },
+            },
+        }),
+    );
+    let hover = receive(&mut stdout);
+    assert_eq!(hover["id"], 2);
+    assert!(hover["result"]["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("main"));
+
+    send(&mut stdin, json!({ "jsonrpc": "2.0", "method": "exit" }));
+    assert!(server.wait().unwrap().success());
+}
@@ -0,0 +1,70 @@
+//! Use the editor as a filter in a shell pipeline
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use pretty_assertions::assert_eq;
+
+/// Run the editor headless on `args`, piping `input` in, and return what it printed
+fn pipe(args: &[&str], script: &str, input: &str) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let script_path = dir.path().join("script.txt");
+    fs::write(&script_path, script).unwrap();
+
+    let mut editor = Command::new(env!("CARGO_BIN_EXE_rust-text-editor"))
+        .args(["--clean", "--headless", "-s", script_path.to_str().unwrap()])
+        .args(args)
+        .current_dir(dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    editor
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = editor.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn filters_stdin_to_stdout() {
+    assert_eq!(
+        pipe(&["-", "--stdout"], ":%!sort\n", "c\nb\na\n"),
+        "a\nb\nc\n"
+    );
+}
+
+#[test]
+fn reads_piped_stdin_without_arguments() {
+    assert_eq!(
+        pipe(&["--stdout"], "ix<Esc>\n", "one\ntwo\n"),
+        "xone\ntwo\n"
+    );
+
+    // without --stdout nothing is printed
+    assert_eq!(pipe(&[], "ix<Esc>\n", "one\n"), "");
+}
+
+#[test]
+fn writes_the_file_buffer_to_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.txt");
+    fs::write(&path, "two\none\n").unwrap();
+
+    let output = pipe(&["--stdout", path.to_str().unwrap()], ":%!sort\n", "");
+    assert_eq!(output, "one\ntwo\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+}
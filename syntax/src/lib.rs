@@ -1,14 +1,12 @@
 // https://tree-sitter.github.io/tree-sitter/syntax-highlighting
 // https://www.youtube.com/watch?v=rHgXmG8U5YQ&list=PL9KpW-9Hl_het1V3_dLhG_0K99a9043ac&index=6
 
-use std::ops::Range;
+use std::{collections::HashSet, ops::Range};
 
 use ratatui::style::Color;
 use ropey::RopeSlice;
 use tree_sitter::{Node, Parser, Query, QueryCursor, TextProvider, Tree};
 
-const CANCELLATION_CHECK_INTERVAL: usize = 100;
-
 #[derive(Debug)]
 enum Error {
     Cancelled,
@@ -32,13 +30,104 @@ impl<'a> TextProvider<&'a [u8]> for RopeProvider<'a> {
     type I = ChunksBytes<'a>;
 
     fn text(&mut self, node: Node) -> Self::I {
-        let fragment = self.0.byte_slice(node.start_byte()..node.end_byte());
+        // the tree can be older than the content, e.g. after an undo, so its
+        // bytes may be past the end or inside a char
+        let snap = |byte: usize| {
+            let byte = byte.min(self.0.len_bytes());
+            self.0.char_to_byte(self.0.byte_to_char(byte))
+        };
+        let end = snap(node.end_byte());
+        let start = snap(node.start_byte()).min(end);
+        let fragment = self.0.byte_slice(start..end);
         ChunksBytes {
             chunks: fragment.chunks(),
         }
     }
 }
 
+/// Node kinds that can be folded, e.g. functions, impls and blocks
+const FOLD_NODES: &[&str] = &[
+    "function_item",
+    "impl_item",
+    "trait_item",
+    "mod_item",
+    "struct_item",
+    "enum_item",
+    "union_item",
+    "macro_definition",
+    "match_expression",
+    "block",
+    "block_comment",
+];
+
+/// Text objects of the grammar, captured as `{object}.around` and `{object}.inside`.
+///
+/// An object with only an inside capture extends it over the comma separating it from its
+/// neighbour when taken around, e.g. an argument.
+const TEXTOBJECTS_QUERY: &str = r#"
+(function_item body: (_) @function.inside) @function.around
+(function_signature_item) @function.around
+(closure_expression body: (_) @function.inside) @function.around
+
+(struct_item body: (_) @class.inside) @class.around
+(enum_item body: (_) @class.inside) @class.around
+(union_item body: (_) @class.inside) @class.around
+(trait_item body: (_) @class.inside) @class.around
+(impl_item body: (_) @class.inside) @class.around
+
+(parameters (_) @parameter.inside)
+(closure_parameters (_) @parameter.inside)
+(type_parameters (_) @parameter.inside)
+(type_arguments (_) @parameter.inside)
+(arguments (_) @parameter.inside)
+"#;
+
+/// Nodes indenting the lines inside them, `@indent`, and closers taking a line back to the
+/// indentation of the line opening them, `@outdent`
+const INDENTS_QUERY: &str = r#"
+[
+  (block)
+  (declaration_list)
+  (field_declaration_list)
+  (enum_variant_list)
+  (field_initializer_list)
+  (match_block)
+  (use_list)
+  (arguments)
+  (parameters)
+  (type_parameters)
+  (type_arguments)
+  (token_tree)
+  (array_expression)
+  (tuple_expression)
+  (let_declaration)
+  (where_clause)
+] @indent
+
+["}" ")" "]"] @outdent
+"#;
+
+/// Syntax node kinds selected by text objects, e.g. `af`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    Function,
+    /// Structs, enums, traits and impls
+    Class,
+    /// Arguments and parameters, of types too
+    Argument,
+}
+
+impl TextObject {
+    /// Name of the object in the captures of the textobjects query
+    fn capture(self) -> &'static str {
+        match self {
+            TextObject::Function => "function",
+            TextObject::Class => "class",
+            TextObject::Argument => "parameter",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct HighlightInfo {
     pub range: Range<usize>,
@@ -49,6 +138,8 @@ pub struct Highlight {
     parser: Parser,
     root: Option<Tree>,
     query: Query,
+    textobjects: Query,
+    indents: Query,
 }
 
 impl Highlight {
@@ -59,11 +150,15 @@ impl Highlight {
             tree_sitter_rust::HIGHLIGHTS_QUERY,
         )
         .unwrap();
+        let textobjects = Query::new(&tree_sitter_rust::language(), TEXTOBJECTS_QUERY).unwrap();
+        let indents = Query::new(&tree_sitter_rust::language(), INDENTS_QUERY).unwrap();
 
         let mut highlight = Self {
             parser: Parser::new(),
             root: None,
             query,
+            textobjects,
+            indents,
         };
 
         highlight.update(content);
@@ -86,6 +181,234 @@ impl Highlight {
         }
     }
 
+    /// Parse the changed content from scratch, the old tree is kept if parsing fails
+    pub fn reparse(&mut self, content: RopeSlice) {
+        // the old tree was not told about the edits, so it cannot be reused
+        let old = self.root.take();
+        if self.parse(content).is_err() {
+            self.root = old;
+        }
+    }
+
+    /// Line ranges of foldable nodes spanning several lines, the end line excluded.
+    ///
+    /// Sorted by start line, a node starting on the same line as a larger one is left out.
+    pub fn fold_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = vec![];
+        let mut cursor = self.tree().walk();
+
+        // depth first, so nodes come by start and the outer node of a line first
+        'walk: loop {
+            let node = cursor.node();
+            let (start, end) = (node.start_position().row, node.end_position().row);
+            if end > start
+                && FOLD_NODES.contains(&node.kind())
+                && ranges.last().is_none_or(|last| last.start != start)
+            {
+                ranges.push(start..end + 1);
+            }
+
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            loop {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+
+        ranges
+    }
+
+    /// Bytes of the smallest text object containing the byte range, with its delimiters and
+    /// separators when `around`, without them otherwise
+    pub fn text_object(
+        &self,
+        content: RopeSlice,
+        range: Range<usize>,
+        object: TextObject,
+        around: bool,
+    ) -> Option<Range<usize>> {
+        let around_name = format!("{}.around", object.capture());
+        let inside_name = format!("{}.inside", object.capture());
+        let names = self.textobjects.capture_names();
+
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(range.clone());
+        let matches = cursor.matches(
+            &self.textobjects,
+            self.tree().root_node(),
+            RopeProvider(content),
+        );
+
+        // (span of the object, around node, inside node)
+        let mut best: Option<(Range<usize>, Option<Node>, Option<Node>)> = None;
+        for match_ in matches {
+            let capture = |name: &str| {
+                match_
+                    .captures
+                    .iter()
+                    .find(|capture| names[capture.index as usize] == name)
+                    .map(|capture| capture.node)
+            };
+            let (around_node, inside_node) = (capture(&around_name), capture(&inside_name));
+            let Some(span) = around_node.or(inside_node).map(|node| node.byte_range()) else {
+                continue;
+            };
+            if span.start > range.start
+                || span.end < range.end
+                || best
+                    .as_ref()
+                    .is_some_and(|(best, ..)| best.len() <= span.len())
+            {
+                continue;
+            }
+            best = Some((span, around_node, inside_node));
+        }
+
+        match best? {
+            (_, Some(node), _) if around => Some(node.byte_range()),
+            (_, None, Some(node)) if around => Some(with_separator(node)),
+            (_, _, Some(node)) => Some(inner(content, node.byte_range())),
+            (span, ..) => Some(span),
+        }
+    }
+
+    /// Bytes of the smallest node around the byte range and larger than it
+    pub fn expand_selection(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let mut node = self
+            .tree()
+            .root_node()
+            .named_descendant_for_byte_range(range.start, range.end)?;
+        while node.byte_range() == range {
+            node = node.parent()?;
+        }
+        Some(node.byte_range())
+    }
+
+    /// Bytes of the first node inside the byte range and smaller than it
+    pub fn shrink_selection(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let mut node = self
+            .tree()
+            .root_node()
+            .named_descendant_for_byte_range(range.start, range.end)?;
+        loop {
+            let mut cursor = node.walk();
+            let child = node
+                .named_children(&mut cursor)
+                .find(|child| range.start <= child.start_byte() && child.end_byte() <= range.end)?;
+            if child.byte_range() != range {
+                return Some(child.byte_range());
+            }
+            node = child;
+        }
+    }
+
+    /// Byte of the bracket matching the one at `byte`; brackets in strings and comments are not
+    /// nodes of their own, so they have no match
+    pub fn matching_bracket(&self, byte: usize) -> Option<usize> {
+        let node = self
+            .tree()
+            .root_node()
+            .descendant_for_byte_range(byte, byte + 1)?;
+        let (kind, forward) = match node.kind() {
+            "(" => (")", true),
+            "[" => ("]", true),
+            "{" => ("}", true),
+            ")" => ("(", false),
+            "]" => ("[", false),
+            "}" => ("{", false),
+            _ => return None,
+        };
+        if node.start_byte() != byte {
+            return None;
+        }
+
+        // pairs are siblings, the brackets nested between them belong to nodes of their own
+        let mut sibling = node;
+        loop {
+            sibling = match forward {
+                true => sibling.next_sibling()?,
+                false => sibling.prev_sibling()?,
+            };
+            if sibling.kind() == kind && !sibling.is_missing() {
+                return Some(sibling.start_byte());
+            }
+        }
+    }
+
+    /// Innermost highlight capture around a byte, e.g. `string` or `comment`; a line comment
+    /// also covers the end of its line
+    pub fn scope_at(&self, content: RopeSlice, byte: usize) -> Option<&str> {
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(byte.saturating_sub(1)..byte + 1);
+        let captures = cursor.captures(&self.query, self.tree().root_node(), RopeProvider(content));
+        let names = self.query.capture_names();
+
+        let mut scope: Option<(usize, &str)> = None;
+        for (match_, capture_index) in captures {
+            let capture = match_.captures[capture_index];
+            let (node, name) = (capture.node, names[capture.index as usize]);
+            let end = node.end_byte();
+            let closed = end >= 2
+                && end <= content.len_bytes()
+                && content.byte(end - 2) == b'*'
+                && content.byte(end - 1) == b'/';
+            let inside = node.start_byte() < byte
+                && (byte < end || (byte == end && name == "comment" && !closed));
+            if inside && scope.is_none_or(|(len, _)| end - node.start_byte() < len) {
+                scope = Some((end - node.start_byte(), name));
+            }
+        }
+        scope.map(|(_, name)| name)
+    }
+
+    /// Indentation level of a line: the indenting nodes around its first non blank char that
+    /// start on a line above, counting a line once, and one less when it starts with a closer
+    pub fn indent_level(&self, content: RopeSlice, line: usize) -> usize {
+        let blanks: usize = content
+            .line(line)
+            .chars()
+            .take_while(|char| *char == ' ' || *char == '\t')
+            .map(char::len_utf8)
+            .sum();
+        let pos = content.line_to_byte(line) + blanks;
+        let root = self.tree().root_node();
+
+        let mut indent = HashSet::new();
+        let mut outdent = HashSet::new();
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(pos..pos + 1);
+        let names = self.indents.capture_names();
+        for (match_, capture_index) in cursor.captures(&self.indents, root, RopeProvider(content)) {
+            let capture = match_.captures[capture_index];
+            match names[capture.index as usize] {
+                "indent" => indent.insert(capture.node.id()),
+                _ => outdent.insert(capture.node.id()),
+            };
+        }
+
+        let Some(node) = root.descendant_for_byte_range(pos, pos) else {
+            return 0;
+        };
+        let closes = node.start_byte() == pos && outdent.contains(&node.id());
+
+        let mut rows = vec![];
+        let mut current = Some(node);
+        while let Some(node) = current {
+            let row = node.start_position().row;
+            if row < line && indent.contains(&node.id()) && !rows.contains(&row) {
+                rows.push(row);
+            }
+            current = node.parent();
+        }
+        rows.len().saturating_sub(closes as usize)
+    }
+
     fn parse(&mut self, content: RopeSlice) -> Result<(), Error> {
         let parser = &mut self.parser;
 
@@ -113,6 +436,7 @@ impl Highlight {
         Ok(())
     }
 
+    /// Returns highlights intersecting the byte range, sorted by start byte
     pub fn colors(&self, content: RopeSlice, range: Range<usize>) -> Vec<HighlightInfo> {
         let mut cursor = QueryCursor::new();
         cursor.set_byte_range(range);
@@ -142,10 +466,53 @@ impl Highlight {
             colors.push(HighlightInfo { range, color })
         }
 
+        colors.sort_by_key(|info| info.range.start);
+
         colors
     }
 }
 
+/// Bytes of a node with the comma after it up to the next node, or the comma before it when it
+/// is the last one
+fn with_separator(node: Node) -> Range<usize> {
+    let range = node.byte_range();
+    match (node.next_sibling(), node.prev_sibling()) {
+        (Some(next), _) if next.kind() == "," => {
+            let end = next
+                .next_sibling()
+                .filter(|after| after.is_named())
+                .map_or(next.end_byte(), |after| after.start_byte());
+            range.start..end
+        }
+        (_, Some(prev)) if prev.kind() == "," => {
+            let start = prev
+                .prev_sibling()
+                .map_or(prev.start_byte(), |before| before.end_byte());
+            start..range.end
+        }
+        _ => range,
+    }
+}
+
+/// Bytes inside the brackets around a range, if any, without the whitespace at both ends
+fn inner(content: RopeSlice, range: Range<usize>) -> Range<usize> {
+    let (mut start, mut end) = (range.start, range.end);
+    if end - start >= 2 {
+        let pair = (content.byte(start), content.byte(end - 1));
+        if matches!(pair, (b'{', b'}') | (b'(', b')') | (b'[', b']')) {
+            start += 1;
+            end -= 1;
+        }
+    }
+    while start < end && content.byte(start).is_ascii_whitespace() {
+        start += 1;
+    }
+    while end > start && content.byte(end - 1).is_ascii_whitespace() {
+        end -= 1;
+    }
+    start..end
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,6 +526,65 @@ mod tests {
 
     use std::{fs::File, io::BufReader};
 
+    #[test]
+    fn selects_text_objects() {
+        let content = Rope::from("fn add(a: u8, b: u8) -> u8 {\n    a + b\n}\n");
+        let content = content.slice(..);
+        let highlight = Highlight::new(content);
+        let text = |range: Option<Range<usize>>| content.byte_slice(range.unwrap()).to_string();
+        let b = 14..15;
+
+        let function = highlight.text_object(content, b.clone(), TextObject::Function, true);
+        assert_eq!(text(function), "fn add(a: u8, b: u8) -> u8 {\n    a + b\n}");
+        let body = highlight.text_object(content, 33..34, TextObject::Function, false);
+        assert_eq!(text(body), "a + b");
+
+        let argument = highlight.text_object(content, 7..8, TextObject::Argument, true);
+        assert_eq!(text(argument), "a: u8, ");
+        let argument = highlight.text_object(content, b.clone(), TextObject::Argument, true);
+        assert_eq!(text(argument), ", b: u8");
+        let argument = highlight.text_object(content, b.clone(), TextObject::Argument, false);
+        assert_eq!(text(argument), "b: u8");
+        assert_eq!(
+            highlight.text_object(content, b.clone(), TextObject::Class, true),
+            None
+        );
+
+        let parameter = highlight.expand_selection(b).unwrap();
+        assert_eq!(text(Some(parameter.clone())), "b: u8");
+        let parameters = highlight.expand_selection(parameter.clone()).unwrap();
+        assert_eq!(text(Some(parameters.clone())), "(a: u8, b: u8)");
+        let first = highlight.shrink_selection(parameters).unwrap();
+        assert_eq!(text(Some(first)), "a: u8");
+    }
+
+    #[test]
+    fn counts_indent_levels() {
+        let content = Rope::from(
+            "fn main() {\n    let a = foo(\n        1,\n    );\n    if a {\n\n    }\n}\n",
+        );
+        let content = content.slice(..);
+        let highlight = Highlight::new(content);
+
+        let levels: Vec<usize> = (0..8)
+            .map(|line| highlight.indent_level(content, line))
+            .collect();
+        assert_eq!(levels, vec![0, 1, 2, 1, 1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn finds_strings_and_comments() {
+        let content = Rope::from("fn a() {\n    // hi\n    let s = \"x\"; /* c */\n}\n");
+        let content = content.slice(..);
+        let highlight = Highlight::new(content);
+
+        assert_eq!(highlight.scope_at(content, 16), Some("comment"));
+        assert_eq!(highlight.scope_at(content, 18), Some("comment"));
+        assert_eq!(highlight.scope_at(content, 32), Some("string"));
+        assert_eq!(highlight.scope_at(content, 34), None);
+        assert_eq!(highlight.scope_at(content, 43), None);
+    }
+
     #[test]
     fn it_works() {
         let mut parser = Parser::new();
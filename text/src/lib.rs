@@ -3,16 +3,12 @@ use ropey::{iter::Chunks, RopeSlice};
 use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
 use unicode_width::UnicodeWidthStr;
 
+pub mod textobject;
+
 /// Returns the display length of a unicode string
 pub fn width(content: &RopeSlice) -> usize {
-    // as_str will sometimes panic while crossing chunk borders
-    match content.as_str() {
-        Some(str) => UnicodeWidthStr::width(str),
-        None => {
-            let content: String = content.chars().collect();
-            UnicodeWidthStr::width(content.as_str())
-        }
-    }
+    // width is summed per char, so chunk borders do not change the result
+    content.chunks().map(UnicodeWidthStr::width).sum()
 }
 
 /// An implementation of a graphemes iterator, for iterating over
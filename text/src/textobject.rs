@@ -0,0 +1,457 @@
+//! Text objects found in the characters around a position, e.g. `iw` or `a(`
+//!
+//! Positions and returned ranges are char indices of the slice.
+
+use std::ops::Range;
+
+use ropey::RopeSlice;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Newline,
+    Space,
+    Punctuation,
+    Word,
+}
+
+/// Class of a char for words, every non blank char is the same for `long` words
+fn class(char: char, long: bool) -> Class {
+    match char {
+        '\n' | '\r' => Class::Newline,
+        char if char.is_whitespace() => Class::Space,
+        _ if long => Class::Word,
+        char if char.is_alphanumeric() || char == '_' => Class::Word,
+        _ => Class::Punctuation,
+    }
+}
+
+/// Chars around `pos` of the same class
+fn run(slice: &RopeSlice, pos: usize, long: bool) -> Range<usize> {
+    let current = class(slice.char(pos), long);
+    let mut start = pos;
+    while start > 0 && class(slice.char(start - 1), long) == current {
+        start -= 1;
+    }
+    let mut end = pos + 1;
+    while end < slice.len_chars() && class(slice.char(end), long) == current {
+        end += 1;
+    }
+    start..end
+}
+
+/// Extend a range over the blanks after it, or the ones before it when there are none after
+/// and they do not indent the line
+fn with_space(slice: &RopeSlice, range: Range<usize>) -> Range<usize> {
+    let is_space = |pos: usize| class(slice.char(pos), false) == Class::Space;
+    if range.end < slice.len_chars() && is_space(range.end) {
+        return range.start..run(slice, range.end, false).end;
+    }
+    if range.start > 0 && is_space(range.start - 1) {
+        let start = run(slice, range.start - 1, false).start;
+        if start > 0 && slice.char(start - 1) != '\n' {
+            return start..range.end;
+        }
+    }
+    range
+}
+
+/// Whether the char at `pos` follows a backslash
+fn escaped(slice: &RopeSlice, pos: usize) -> bool {
+    let backslashes = (0..pos)
+        .rev()
+        .take_while(|&before| slice.char(before) == '\\')
+        .count();
+    backslashes % 2 == 1
+}
+
+/// Word at `pos`, `iw`, of any non blank chars when `long`, `iW`.
+///
+/// Around, `aw`, takes the blanks after it too, or the word after blanks at `pos`.
+pub fn word(slice: &RopeSlice, pos: usize, long: bool, around: bool) -> Option<Range<usize>> {
+    if pos >= slice.len_chars() {
+        return None;
+    }
+    let word = run(slice, pos, long);
+    match class(slice.char(pos), long) {
+        Class::Newline => Some(pos..pos + 1),
+        _ if !around => Some(word),
+        Class::Space => {
+            let followed =
+                word.end < slice.len_chars() && class(slice.char(word.end), long) != Class::Newline;
+            match followed {
+                true => Some(word.start..run(slice, word.end, long).end),
+                false => Some(word),
+            }
+        }
+        _ => Some(with_space(slice, word)),
+    }
+}
+
+/// String between `quote` chars on the line of `pos`, `i"`, with the quotes and the blanks
+/// after them when `around`, `a"`.
+///
+/// Quotes pair up from the start of the line, escaped ones are skipped. Outside of a string
+/// the next one on the line is taken.
+pub fn quotes(slice: &RopeSlice, pos: usize, quote: char, around: bool) -> Option<Range<usize>> {
+    if pos >= slice.len_chars() {
+        return None;
+    }
+    let line_start = slice.line_to_char(slice.char_to_line(pos));
+    let line = slice.line(slice.char_to_line(pos));
+
+    let mut escaped = false;
+    let mut positions = vec![];
+    for (col, char) in line.chars().enumerate() {
+        if char == quote && !escaped {
+            positions.push(line_start + col);
+        }
+        escaped = char == '\\' && !escaped;
+    }
+
+    let (open, close) = positions
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| pos <= close)?;
+    match around {
+        true => Some(with_space(slice, open..close + 1)),
+        false => Some(open + 1..close),
+    }
+}
+
+/// Closer matching the opener at `open`
+fn matching_close(slice: &RopeSlice, open: usize, pair: (char, char)) -> Option<usize> {
+    let mut depth = 0;
+    for (pos, char) in slice.chars_at(open + 1).enumerate() {
+        let pos = open + 1 + pos;
+        if escaped(slice, pos) {
+            continue;
+        }
+        if char == pair.0 {
+            depth += 1;
+        } else if char == pair.1 {
+            if depth == 0 {
+                return Some(pos);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Opener of the innermost pair around `pos`, a char of the pair at `pos` belongs to it
+fn enclosing_open(slice: &RopeSlice, pos: usize, pair: (char, char)) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = slice.chars_at(pos + 1);
+    let mut at = pos + 1;
+    while let Some(char) = chars.prev() {
+        at -= 1;
+        if escaped(slice, at) {
+            continue;
+        }
+        if char == pair.1 && at != pos {
+            depth += 1;
+        } else if char == pair.0 {
+            if depth == 0 {
+                return Some(at);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Text between the brackets of `pair` around `pos`, `i(`, with them when `around`, `a(`.
+///
+/// Outside of brackets the next pair on the line is taken. Inside, brackets alone on their
+/// lines leave out the line breaks around them, so the lines between are taken whole.
+pub fn brackets(
+    slice: &RopeSlice,
+    pos: usize,
+    pair: (char, char),
+    around: bool,
+) -> Option<Range<usize>> {
+    if pos >= slice.len_chars() {
+        return None;
+    }
+    let enclosing = enclosing_open(slice, pos, pair)
+        .and_then(|open| Some((open, matching_close(slice, open, pair)?)));
+    let (open, close) = match enclosing {
+        Some(found) => found,
+        None => {
+            let line_end = slice.line_to_char(slice.char_to_line(pos) + 1);
+            let open =
+                (pos..line_end).find(|&at| slice.char(at) == pair.0 && !escaped(slice, at))?;
+            (open, matching_close(slice, open, pair)?)
+        }
+    };
+
+    if around {
+        return Some(open..close + 1);
+    }
+    let mut start = open + 1;
+    if start < close && slice.char(start) == '\n' {
+        start += 1;
+    }
+    let close_line = slice.line_to_char(slice.char_to_line(close));
+    let end = match close_line > start
+        && slice
+            .slice(close_line..close)
+            .chars()
+            .all(char::is_whitespace)
+    {
+        true => close_line,
+        false => close,
+    };
+    Some(start..end.max(start))
+}
+
+/// Lines of the paragraph at `pos`, `ip`, or of the blank lines at it.
+///
+/// Around, `ap`, takes the blank lines after it too, or the ones before it when there are none
+/// after; on blank lines it takes the paragraph after them.
+pub fn paragraph(slice: &RopeSlice, pos: usize, around: bool) -> Option<Range<usize>> {
+    // a line break at the end does not start another line
+    let len = match slice.len_chars() {
+        0 => return None,
+        len if slice.char(len - 1) == '\n' => slice.len_lines() - 1,
+        _ => slice.len_lines(),
+    };
+    let blank = |line: usize| slice.line(line).chars().all(char::is_whitespace);
+    let run = |line: usize| {
+        let current = blank(line);
+        let start = (0..line)
+            .rev()
+            .find(|&before| blank(before) != current)
+            .map_or(0, |before| before + 1);
+        let end = (line + 1..len)
+            .find(|&after| blank(after) != current)
+            .unwrap_or(len);
+        start..end
+    };
+
+    let line = slice.char_to_line(pos).min(len - 1);
+    let mut lines = run(line);
+    if around {
+        if lines.end < len {
+            lines.end = run(lines.end).end;
+        } else if lines.start > 0 && !blank(line) {
+            lines.start = run(lines.start - 1).start;
+        }
+    }
+    Some(slice.line_to_char(lines.start)..slice.line_to_char(lines.end))
+}
+
+/// Markup tag, `<name ...>` or `</name>`
+struct Tag {
+    range: Range<usize>,
+    name: String,
+    closing: bool,
+}
+
+/// Opening and closing tags, without comments, declarations and self closing tags
+fn tags(slice: &RopeSlice) -> Vec<Tag> {
+    let mut tags = vec![];
+    // start and text of the tag being read
+    let mut current: Option<(usize, String)> = None;
+
+    for (pos, char) in slice.chars().enumerate() {
+        match (char, current.as_mut()) {
+            ('<', _) => current = Some((pos, String::new())),
+            ('>', Some((start, text))) => {
+                let closing = text.starts_with('/');
+                let name: String = text
+                    .trim_start_matches('/')
+                    .chars()
+                    .take_while(|char| char.is_alphanumeric() || "-_:.".contains(*char))
+                    .collect();
+                if !name.is_empty() && !text.ends_with('/') {
+                    tags.push(Tag {
+                        range: *start..pos + 1,
+                        name,
+                        closing,
+                    });
+                }
+                current = None;
+            }
+            (char, Some((_, text))) => text.push(char),
+            (_, None) => {}
+        }
+    }
+
+    tags
+}
+
+/// Content of the innermost tag element around `pos`, `it`, with its tags when `around`, `at`.
+///
+/// Outside of elements the next one starting on the line is taken.
+pub fn tag(slice: &RopeSlice, pos: usize, around: bool) -> Option<Range<usize>> {
+    let mut open: Vec<Tag> = vec![];
+    let mut elements: Vec<(Range<usize>, Range<usize>)> = vec![];
+    for tag in tags(slice) {
+        if !tag.closing {
+            open.push(tag);
+        } else if let Some(index) = open.iter().rposition(|opening| opening.name == tag.name) {
+            elements.push((open[index].range.clone(), tag.range));
+            open.truncate(index);
+        }
+    }
+
+    let line_end = slice.line_to_char(slice.char_to_line(pos) + 1);
+    let (opening, closing) = elements
+        .iter()
+        .filter(|(opening, closing)| opening.start <= pos && pos < closing.end)
+        .min_by_key(|(opening, closing)| closing.end - opening.start)
+        .or_else(|| {
+            elements
+                .iter()
+                .filter(|(opening, _)| pos < opening.start && opening.start < line_end)
+                .min_by_key(|(opening, _)| opening.start)
+        })?;
+
+    match around {
+        true => Some(opening.start..closing.end),
+        false => Some(opening.end..closing.start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use ropey::Rope;
+
+    use super::*;
+
+    /// Text of a text object at the char after `|` in `marked`, which is removed
+    fn select(
+        marked: &str,
+        find: impl Fn(&RopeSlice, usize) -> Option<Range<usize>>,
+    ) -> Option<String> {
+        let pos = marked.find('|').unwrap();
+        let rope = Rope::from(marked.replacen('|', "", 1));
+        let slice = rope.slice(..);
+        let pos = slice.byte_to_char(pos);
+        find(&slice, pos).map(|range| slice.slice(range).to_string())
+    }
+
+    #[test]
+    fn selects_words() {
+        let text = "let |foo_bar = a.b;\n";
+        assert_eq!(
+            select(text, |s, p| word(s, p, false, false)).unwrap(),
+            "foo_bar"
+        );
+        assert_eq!(
+            select(text, |s, p| word(s, p, false, true)).unwrap(),
+            "foo_bar "
+        );
+        assert_eq!(
+            select("x = a|.b;", |s, p| word(s, p, false, false)).unwrap(),
+            "."
+        );
+        assert_eq!(
+            select("x = a|.b;", |s, p| word(s, p, true, false)).unwrap(),
+            "a.b;"
+        );
+        // at the end of the line the blanks before it are taken, not the indentation
+        assert_eq!(
+            select("  a |b\n", |s, p| word(s, p, false, true)).unwrap(),
+            " b"
+        );
+        assert_eq!(
+            select("  |b\n", |s, p| word(s, p, false, true)).unwrap(),
+            "b"
+        );
+    }
+
+    #[test]
+    fn selects_quotes_on_the_line() {
+        let text = r#"f("a \"b\"|", 'c')"#;
+        assert_eq!(
+            select(text, |s, p| quotes(s, p, '"', false)).unwrap(),
+            r#"a \"b\""#
+        );
+        assert_eq!(
+            select(text, |s, p| quotes(s, p, '\'', true)).unwrap(),
+            " 'c'"
+        );
+        // before the string or between two of them
+        let text = r#"|x = "a" + "b""#;
+        assert_eq!(select(text, |s, p| quotes(s, p, '"', false)).unwrap(), "a");
+        let text = r#"x = "a" |+ "b""#;
+        assert_eq!(
+            select(text, |s, p| quotes(s, p, '"', true)).unwrap(),
+            r#" "b""#
+        );
+        assert_eq!(select("\"a\"\n|\"b", |s, p| quotes(s, p, '"', false)), None);
+    }
+
+    #[test]
+    fn selects_nested_brackets() {
+        let pair = ('(', ')');
+        let text = "f(a, g(b|), c)";
+        assert_eq!(
+            select(text, |s, p| brackets(s, p, pair, false)).unwrap(),
+            "b"
+        );
+        assert_eq!(
+            select("f(a, g(b)|, c)", |s, p| brackets(s, p, pair, true)).unwrap(),
+            "(a, g(b), c)"
+        );
+        assert_eq!(
+            select("f(a, g(b)|)", |s, p| brackets(s, p, pair, true)).unwrap(),
+            "(a, g(b))"
+        );
+        assert_eq!(
+            select("|f(a, (b))", |s, p| brackets(s, p, pair, false)).unwrap(),
+            "a, (b)"
+        );
+        assert_eq!(
+            select(r"(a \) |b)", |s, p| brackets(s, p, pair, false)).unwrap(),
+            r"a \) b"
+        );
+        // the lines between brackets on their own lines
+        let text = "fn f() {\n    a|;\n}\n";
+        assert_eq!(
+            select(text, |s, p| brackets(s, p, ('{', '}'), false)).unwrap(),
+            "    a;\n"
+        );
+        assert_eq!(select("x|\n(a)", |s, p| brackets(s, p, pair, false)), None);
+    }
+
+    #[test]
+    fn selects_paragraphs() {
+        let text = "a\nb|\n\n\nc\n";
+        assert_eq!(
+            select(text, |s, p| paragraph(s, p, false)).unwrap(),
+            "a\nb\n"
+        );
+        assert_eq!(
+            select(text, |s, p| paragraph(s, p, true)).unwrap(),
+            "a\nb\n\n\n"
+        );
+        assert_eq!(
+            select("a\n\nc|", |s, p| paragraph(s, p, true)).unwrap(),
+            "\nc"
+        );
+        assert_eq!(
+            select("a\n|\nc\n", |s, p| paragraph(s, p, true)).unwrap(),
+            "\nc\n"
+        );
+    }
+
+    #[test]
+    fn selects_tags() {
+        let text = "<div><p>a |b</p><br/></div>";
+        assert_eq!(select(text, |s, p| tag(s, p, false)).unwrap(), "a b");
+        assert_eq!(select(text, |s, p| tag(s, p, true)).unwrap(), "<p>a b</p>");
+        let text = "<div|><p>a</p><br/></div>";
+        assert_eq!(
+            select(text, |s, p| tag(s, p, false)).unwrap(),
+            "<p>a</p><br/>"
+        );
+        assert_eq!(
+            select("|x <b>y</b>", |s, p| tag(s, p, true)).unwrap(),
+            "<b>y</b>"
+        );
+    }
+}